@@ -0,0 +1,204 @@
+use crayfish::aabb::Aabb;
+use crayfish::material::Lambertian;
+#[cfg(feature = "packet")]
+use crayfish::packet::{hit_aabb4, RayPacket4};
+use crayfish::shapes::Sphere;
+use crayfish::simd::{self, Real4};
+use crayfish::{Color3, Intersectable, Point3, Ray, Real, Vector3};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::sync::Arc;
+
+// the vector type and sphere test as they were before vectors were backed by Real4, to see
+// what the simd versions gain over plain arrays
+mod plain {
+    use crayfish::Real;
+
+    #[derive(Copy, Clone)]
+    pub struct Vector3(pub [Real; 3]);
+
+    impl Vector3 {
+        pub fn sub(&self, other: &Vector3) -> Vector3 {
+            Vector3([
+                self.0[0] - other.0[0],
+                self.0[1] - other.0[1],
+                self.0[2] - other.0[2],
+            ])
+        }
+
+        pub fn scale(&self, factor: Real) -> Vector3 {
+            Vector3([self.0[0] * factor, self.0[1] * factor, self.0[2] * factor])
+        }
+
+        pub fn dot(&self, other: &Vector3) -> Real {
+            self.0[0] * other.0[0] + self.0[1] * other.0[1] + self.0[2] * other.0[2]
+        }
+
+        pub fn as_normal(&self) -> Vector3 {
+            self.scale(1.0 / self.dot(self).sqrt())
+        }
+
+        pub fn reflect(&self, around: &Vector3) -> Vector3 {
+            self.sub(&around.scale(2.0 * self.dot(around)))
+        }
+    }
+
+    pub struct Ray {
+        pub origin: Vector3,
+        pub direction: Vector3,
+    }
+
+    // the nearest root in range, found the way Sphere::hit did
+    pub fn hit_sphere(
+        center: &Vector3,
+        radius: Real,
+        ray: &Ray,
+        t_min: Real,
+        t_max: Real,
+    ) -> Option<Real> {
+        let oc = ray.origin.sub(center);
+        let a = ray.direction.dot(&ray.direction);
+        let half_b = oc.dot(&ray.direction);
+        let c = oc.dot(&oc) - radius * radius;
+
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let disc_sqrt = discriminant.sqrt();
+        let mut root = (-half_b - disc_sqrt) / a;
+        if root < t_min || t_max < root {
+            root = (-half_b + disc_sqrt) / a;
+            if root < t_min || t_max < root {
+                return None;
+            }
+        }
+        Some(root)
+    }
+
+    pub fn from(vector: &crayfish::Vector3) -> Vector3 {
+        Vector3([vector[0], vector[1], vector[2]])
+    }
+}
+
+fn rays() -> [Ray; 4] {
+    [
+        Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0)),
+        Ray::new(Point3::new(0.1, 0.2, 5.0), Vector3::new(0.0, 0.1, -1.0)),
+        Ray::new(Point3::new(-0.3, 0.0, 5.0), Vector3::new(0.2, 0.0, -1.0)),
+        Ray::new(Point3::new(0.0, 3.0, 5.0), Vector3::new(0.0, 0.0, -1.0)),
+    ]
+}
+
+fn vector_math(c: &mut Criterion) {
+    let a = Real4::new(1.0, 2.0, 3.0, 0.0);
+    let b = Real4::new(4.0, 5.0, 6.0, 0.0);
+
+    let mut group = c.benchmark_group(format!("real4 ({})", simd::BACKEND));
+    group.bench_function("mul add scalar", |bench| {
+        bench
+            .iter(|| simd::scalar::add(simd::scalar::mul(black_box(a), black_box(b)), black_box(a)))
+    });
+    group.bench_function("mul add simd", |bench| {
+        bench.iter(|| black_box(a) * black_box(b) + black_box(a))
+    });
+    group.bench_function("sqrt div scalar", |bench| {
+        bench.iter(|| simd::scalar::div(simd::scalar::sqrt(black_box(b)), black_box(a)))
+    });
+    group.bench_function("sqrt div simd", |bench| {
+        bench.iter(|| black_box(b).sqrt() / black_box(a))
+    });
+    group.finish();
+
+    let v = Vector3::new(1.0, 2.0, 3.0);
+    let w = Vector3::new(-2.0, 0.5, 4.0);
+    let (plain_v, plain_w) = (plain::from(&v), plain::from(&w));
+
+    let mut group = c.benchmark_group(format!("vector3 ({})", simd::BACKEND));
+    group.bench_function("normalize dot plain", |bench| {
+        bench.iter(|| black_box(plain_v).as_normal().dot(&black_box(plain_w)))
+    });
+    group.bench_function("normalize dot", |bench| {
+        bench.iter(|| black_box(v).as_normal().dot(&black_box(w)))
+    });
+    group.bench_function("reflect plain", |bench| {
+        bench.iter(|| black_box(plain_v).reflect(&black_box(plain_w).as_normal()))
+    });
+    group.bench_function("reflect", |bench| {
+        bench.iter(|| black_box(v).reflect(&black_box(w).as_normal()))
+    });
+    group.finish();
+}
+
+fn intersection(c: &mut Criterion) {
+    let sphere = Sphere {
+        center: Point3::new(0.0, 0.0, 0.0),
+        radius: 1.0,
+        material: Arc::new(Lambertian {
+            diffuse: Color3::new(0.5, 0.5, 0.5),
+        }),
+    };
+    let aabb = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+    let rays = rays();
+    let plain_rays: Vec<plain::Ray> = rays
+        .iter()
+        .map(|ray| plain::Ray {
+            origin: plain::from(&ray.origin),
+            direction: plain::from(&ray.direction),
+        })
+        .collect();
+    let plain_center = plain::from(&sphere.center);
+
+    c.bench_function("sphere 4 plain rays", |bench| {
+        bench.iter(|| {
+            plain_rays
+                .iter()
+                .filter(|ray| {
+                    plain::hit_sphere(&plain_center, 1.0, black_box(ray), 0.001, Real::INFINITY)
+                        .is_some()
+                })
+                .count()
+        })
+    });
+
+    c.bench_function("sphere 4 single rays", |bench| {
+        bench.iter(|| {
+            rays.iter()
+                .filter(|ray| sphere.hit(black_box(ray), 0.001, Real::INFINITY).is_some())
+                .count()
+        })
+    });
+
+    c.bench_function("aabb 4 single rays", |bench| {
+        bench.iter(|| {
+            rays.iter()
+                .filter(|ray| aabb.hit(black_box(ray), 0.001, Real::INFINITY))
+                .count()
+        })
+    });
+
+    #[cfg(feature = "packet")]
+    {
+        let packet = RayPacket4::new(&rays);
+        c.bench_function("sphere ray packet", |bench| {
+            bench.iter(|| {
+                sphere
+                    .hit_packet(black_box(&packet), 0.001, Real::INFINITY)
+                    .iter()
+                    .filter(|record| record.is_some())
+                    .count()
+            })
+        });
+        c.bench_function("aabb ray packet", |bench| {
+            bench.iter(|| {
+                hit_aabb4(black_box(&packet), &aabb, 0.001, Real::INFINITY)
+                    .iter()
+                    .filter(|hit| **hit)
+                    .count()
+            })
+        });
+    }
+}
+
+criterion_group!(benches, vector_math, intersection);
+criterion_main!(benches);
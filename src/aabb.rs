@@ -0,0 +1,59 @@
+use crate::defs::Real;
+use crate::math::{Point3, Ray};
+
+/// An axis aligned bounding box.
+#[derive(Copy, Clone)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn surrounding(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                Real::min(self.min[0], other.min[0]),
+                Real::min(self.min[1], other.min[1]),
+                Real::min(self.min[2], other.min[2]),
+            ),
+            max: Point3::new(
+                Real::max(self.max[0], other.max[0]),
+                Real::max(self.max[1], other.max[1]),
+                Real::max(self.max[2], other.max[2]),
+            ),
+        }
+    }
+
+    /// Returns where the ray enters and leaves the box, clipped to [`t_min`, `t_max`].
+    pub fn hit_interval(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<(Real, Real)> {
+        let mut t_entry = t_min;
+        let mut t_exit = t_max;
+
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inverse_direction;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inverse_direction;
+
+            if inverse_direction < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_entry = Real::max(t0, t_entry);
+            t_exit = Real::min(t1, t_exit);
+
+            if t_exit <= t_entry {
+                return None;
+            }
+        }
+
+        Some((t_entry, t_exit))
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> bool {
+        self.hit_interval(ray, t_min, t_max).is_some()
+    }
+}
@@ -0,0 +1,178 @@
+use crate::configuration::{CameraKeyframe, Configuration, Material, Shape, ShapeKeyframe};
+use crate::defs::Real;
+
+use std::collections::HashMap;
+
+pub trait Keyframe {
+    fn frame(&self) -> i64;
+    fn interpolation(&self) -> Option<&str>;
+}
+
+impl Keyframe for CameraKeyframe {
+    fn frame(&self) -> i64 {
+        self.frame
+    }
+
+    fn interpolation(&self) -> Option<&str> {
+        self.interpolation.as_deref()
+    }
+}
+
+impl Keyframe for ShapeKeyframe {
+    fn frame(&self) -> i64 {
+        self.frame
+    }
+
+    fn interpolation(&self) -> Option<&str> {
+        self.interpolation.as_deref()
+    }
+}
+
+// returns the configuration with every keyframed property evaluated at the given frame
+pub fn config_at_frame(config: &Configuration, frame: i64) -> Configuration {
+    let mut frame_config = config.clone();
+
+    if let Some(keyframes) = &config.camera.keyframes {
+        let camera = &mut frame_config.camera;
+
+        if let Some(position) = animate_vec(keyframes, frame, |k| k.position.as_ref()) {
+            camera.position = position;
+        }
+        if let Some(look_at) = animate_vec(keyframes, frame, |k| k.look_at.as_ref()) {
+            camera.look_at = look_at;
+        }
+        if let Some(fov_deg) = animate_real(keyframes, frame, |k| k.fov_deg) {
+            camera.fov_deg = Some(fov_deg);
+        }
+    }
+
+    // parts of objects are animated where they're defined, every instance follows them
+    let objects = frame_config.objects.iter_mut().flatten();
+    let parts = objects.flat_map(|(_, parts)| parts.iter_mut());
+    for shape in frame_config.shapes.iter_mut().chain(parts) {
+        animate_shape(shape, frame, &config.materials);
+    }
+
+    frame_config
+}
+
+fn animate_shape(shape: &mut Shape, frame: i64, materials: &Option<HashMap<String, Material>>) {
+    let keyframes = match &shape.keyframes {
+        Some(keyframes) => keyframes,
+        None => return,
+    };
+
+    if let Some(position) = animate_vec(keyframes, frame, |k| k.position.as_ref()) {
+        shape.transform.position = position;
+    }
+    if let Some(size) = animate_vec(keyframes, frame, |k| k.size.as_ref()) {
+        shape.transform.size = size;
+    }
+
+    // gives the shape its own copy of a shared material so only it is animated
+    let material = shape.material.make_inline(materials);
+    if let Some(diffuse) = animate_vec(keyframes, frame, |k| k.diffuse.as_ref()) {
+        material.diffuse = Some(diffuse);
+    }
+    if let Some(albedo) = animate_vec(keyframes, frame, |k| k.albedo.as_ref()) {
+        material.albedo = Some(albedo);
+    }
+    if let Some(fuzz) = animate_real(keyframes, frame, |k| k.fuzz) {
+        material.fuzz = Some(fuzz);
+    }
+    if let Some(refraction_index) = animate_real(keyframes, frame, |k| k.refraction_index) {
+        material.refraction_index = Some(refraction_index);
+    }
+    if let Some(anisotropy) = animate_real(keyframes, frame, |k| k.anisotropy) {
+        material.anisotropy = Some(anisotropy);
+    }
+}
+
+// replaces a printf style frame number pattern (%d, or %04d for a padded one) in the path with
+// the frame, paths without a pattern get the frame number appended before the extension
+pub fn frame_output_path(output_path: &str, frame: i64) -> String {
+    for (start, _) in output_path.match_indices('%') {
+        let after = &output_path[start + 1..];
+        let digits = after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if after[digits..].starts_with('d') {
+            let width = after[..digits].parse::<usize>().unwrap_or(0);
+
+            return format!(
+                "{}{:0width$}{}",
+                &output_path[..start],
+                frame,
+                &after[digits + 1..],
+                width = width
+            );
+        }
+    }
+
+    match output_path.rfind('.') {
+        Some(extension) => format!(
+            "{}_{:04}{}",
+            &output_path[..extension],
+            frame,
+            &output_path[extension..]
+        ),
+        None => format!("{}_{:04}", output_path, frame),
+    }
+}
+
+// finds the keyframes on either side of the frame that have a value for the property,
+// along with how far between them the frame is
+fn surrounding_keyframes<'k, K: Keyframe, T, F: Fn(&'k K) -> Option<T>>(
+    keyframes: &'k [K],
+    frame: i64,
+    value: F,
+) -> Option<(T, T, Real)> {
+    let mut keys: Vec<&K> = keyframes.iter().filter(|k| value(k).is_some()).collect();
+    keys.sort_by_key(|k| k.frame());
+
+    let first = keys.first()?;
+    let last = keys.last()?;
+
+    if frame <= first.frame() {
+        return Some((value(first)?, value(first)?, 0.0));
+    }
+    if frame >= last.frame() {
+        return Some((value(last)?, value(last)?, 0.0));
+    }
+
+    let next_index = keys.iter().position(|k| k.frame() > frame)?;
+    let previous = keys[next_index - 1];
+    let next = keys[next_index];
+
+    let t = (frame - previous.frame()) as Real / (next.frame() - previous.frame()) as Real;
+    let t = match previous.interpolation().unwrap_or("linear") {
+        "linear" => t,
+        "smooth" => t * t * (3.0 - 2.0 * t),
+        _ => panic!("Unsupported keyframe interpolation"),
+    };
+
+    Some((value(previous)?, value(next)?, t))
+}
+
+fn animate_real<K: Keyframe, F: Fn(&K) -> Option<Real>>(
+    keyframes: &[K],
+    frame: i64,
+    value: F,
+) -> Option<Real> {
+    let (from, to, t) = surrounding_keyframes(keyframes, frame, value)?;
+
+    Some(from + (to - from) * t)
+}
+
+fn animate_vec<'k, K: Keyframe, F: Fn(&'k K) -> Option<&'k Vec<Real>>>(
+    keyframes: &'k [K],
+    frame: i64,
+    value: F,
+) -> Option<Vec<Real>> {
+    let (from, to, t) = surrounding_keyframes(keyframes, frame, value)?;
+
+    Some(
+        from.iter()
+            .zip(to.iter())
+            .map(|(from, to)| from + (to - from) * t)
+            .collect(),
+    )
+}
@@ -1,17 +1,139 @@
+use crate::defs::consts::PI;
 use crate::defs::Real;
 use crate::math::{Point3, Ray, Vector3};
+use crate::random;
 
+use rand::Rng;
+
+/// The projection used to turn image coordinates into rays.
+#[derive(Copy, Clone)]
+pub enum CameraModel {
+    Perspective { fov_deg: Real },
+    Orthographic { view_height: Real },
+    Fisheye { fov_deg: Real },
+    Equirectangular,
+    Cylindrical { fov_deg: Real },
+}
+
+impl CameraModel {
+    // a perspective projection matching a lens of the given focal length in front of a
+    // sensor of the given width, both in millimeters
+    pub fn thin_lens(focal_length: Real, sensor_width: Real, aspect_ratio: Real) -> CameraModel {
+        let sensor_height = sensor_width / aspect_ratio;
+        let fov_deg = (2.0 * (sensor_height / (2.0 * focal_length)).atan()).to_degrees();
+
+        CameraModel::Perspective { fov_deg }
+    }
+}
+
+/// The shape of the lens opening, which gives out of focus highlights their shape.
+pub enum ApertureShape {
+    Circle,
+    Polygon { blades: u32, rotation_deg: Real },
+    Mask(ApertureMask),
+}
+
+impl ApertureShape {
+    // returns a point on the aperture in [-1, 1] on the x and y axes
+    fn sample(&self) -> Vector3 {
+        match self {
+            ApertureShape::Circle => Vector3::random_in_unit_disk(),
+            ApertureShape::Polygon {
+                blades,
+                rotation_deg,
+            } => sample_polygon(*blades, rotation_deg.to_radians()),
+            ApertureShape::Mask(mask) => mask.sample(),
+        }
+    }
+}
+
+fn sample_polygon(blades: u32, rotation: Real) -> Vector3 {
+    let mut rng = random::rng();
+
+    // pick one of the triangles fanning out from the center, then a uniform point in it
+    let blade = rng.gen_range(0..blades) as Real;
+    let blade_angle = 2.0 * PI / blades as Real;
+    let angle_a = rotation + blade * blade_angle;
+    let angle_b = angle_a + blade_angle;
+
+    let mut a: Real = rng.gen_range(0.0..1.0);
+    let mut b: Real = rng.gen_range(0.0..1.0);
+    if a + b > 1.0 {
+        a = 1.0 - a;
+        b = 1.0 - b;
+    }
+
+    Vector3::new(
+        a * angle_a.cos() + b * angle_b.cos(),
+        a * angle_a.sin() + b * angle_b.sin(),
+        0.0,
+    )
+}
+
+// an image of the aperture, brighter pixels let through more light
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    weights: Vec<Real>,
+}
+
+impl ApertureMask {
+    pub fn load(path: &str) -> ApertureMask {
+        let image = image::open(path)
+            .unwrap_or_else(|error| panic!("Unable to load aperture mask {}: {}", path, error))
+            .to_luma8();
+        let (width, height) = image.dimensions();
+
+        let weights: Vec<Real> = image.pixels().map(|p| p[0] as Real / 255.0).collect();
+        let max_weight = weights.iter().cloned().fold(0.0, Real::max);
+        assert!(
+            max_weight > 0.0,
+            "Aperture mask must not be completely black"
+        );
+
+        ApertureMask {
+            width: width as usize,
+            height: height as usize,
+            weights: weights.iter().map(|w| w / max_weight).collect(),
+        }
+    }
+
+    fn sample(&self) -> Vector3 {
+        let mut rng = random::rng();
+
+        loop {
+            let x: Real = rng.gen_range(0.0..1.0);
+            let y: Real = rng.gen_range(0.0..1.0);
+
+            let column = ((x * self.width as Real) as usize).min(self.width - 1);
+            let row = ((y * self.height as Real) as usize).min(self.height - 1);
+
+            if rng.gen_range(0.0..1.0) < self.weights[row * self.width + column] {
+                // image rows go downwards
+                return Vector3::new(2.0 * x - 1.0, 1.0 - 2.0 * y, 0.0);
+            }
+        }
+    }
+}
+
+/// A camera placed at `origin` looking towards `look_at`.
 pub struct Camera {
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vector3,
     vertical: Vector3,
     lens_radius: Real,
+    aperture_shape: ApertureShape,
+    aspect_ratio: Real,
+    model: CameraModel,
     u: Vector3,
     v: Vector3,
+    w: Vector3,
 }
 
 impl Camera {
+    /// A perspective camera with a vertical field of view of `fov_deg`. A non zero `aperture`
+    /// gives depth of field with the focal plane `focus_distance` in front of the camera.
     pub fn new(
         origin: Point3,
         look_at: Point3,
@@ -21,20 +143,51 @@ impl Camera {
         focus_distance: Real,
         aperture: Real,
     ) -> Camera {
-        let theta = fov_deg.to_radians();
-        let h = (theta * 0.5).tan();
-
-        let viewport_height: Real = 2.0 * h;
-        let viewport_width: Real = aspect_ratio * viewport_height;
+        Self::with_model(
+            origin,
+            look_at,
+            up,
+            aspect_ratio,
+            CameraModel::Perspective { fov_deg },
+            focus_distance,
+            aperture,
+        )
+    }
 
+    /// A camera using any of the projections in [`CameraModel`], the aperture only applies to
+    /// perspective projections.
+    pub fn with_model(
+        origin: Point3,
+        look_at: Point3,
+        up: Vector3,
+        aspect_ratio: Real,
+        model: CameraModel,
+        focus_distance: Real,
+        aperture: Real,
+    ) -> Camera {
         let w = (origin - look_at).as_normal();
         let u = up.cross(&w).as_normal();
         let v = w.cross(&u);
 
-        let horizontal = u * viewport_width * focus_distance;
-        let vertical = v * viewport_height * focus_distance;
+        // the image plane, placed at the focus distance for perspective projections
+        let (horizontal, vertical, plane_distance) = match model {
+            CameraModel::Perspective { fov_deg } => {
+                let viewport_height = 2.0 * (fov_deg.to_radians() * 0.5).tan();
+                let viewport_width = aspect_ratio * viewport_height;
+                (
+                    u * viewport_width * focus_distance,
+                    v * viewport_height * focus_distance,
+                    focus_distance,
+                )
+            }
+            CameraModel::Orthographic { view_height } => {
+                (u * aspect_ratio * view_height, v * view_height, 0.0)
+            }
+            _ => (Vector3::default(), Vector3::default(), 0.0),
+        };
+
         let lower_left_corner =
-            origin - (horizontal / 2.0) - (vertical / 2.0) - (w * focus_distance);
+            origin - (horizontal / 2.0) - (vertical / 2.0) - (w * plane_distance);
 
         Camera {
             origin,
@@ -42,14 +195,34 @@ impl Camera {
             horizontal,
             vertical,
             lens_radius: aperture * 0.5,
+            aperture_shape: ApertureShape::Circle,
+            aspect_ratio,
+            model,
             u,
             v,
+            w,
         }
     }
 
+    pub fn with_aperture_shape(mut self, aperture_shape: ApertureShape) -> Camera {
+        self.aperture_shape = aperture_shape;
+        self
+    }
+
+    /// Returns a ray through the image at `px`, `py` which are in [0, 1] from the bottom left.
     pub fn get_ray(&self, px: Real, py: Real) -> Ray {
-        let random_disk = Vector3::random_in_unit_disk() * self.lens_radius;
-        let offset = (self.u * random_disk[0]) + (self.v * random_disk[1]);
+        match self.model {
+            CameraModel::Perspective { .. } => self.perspective_ray(px, py),
+            CameraModel::Orthographic { .. } => self.orthographic_ray(px, py),
+            CameraModel::Fisheye { fov_deg } => self.fisheye_ray(px, py, fov_deg),
+            CameraModel::Equirectangular => self.equirectangular_ray(px, py),
+            CameraModel::Cylindrical { fov_deg } => self.cylindrical_ray(px, py, fov_deg),
+        }
+    }
+
+    fn perspective_ray(&self, px: Real, py: Real) -> Ray {
+        let lens_point = self.aperture_shape.sample() * self.lens_radius;
+        let offset = (self.u * lens_point[0]) + (self.v * lens_point[1]);
 
         Ray::new(
             self.origin + offset,
@@ -58,4 +231,47 @@ impl Camera {
                 - offset,
         )
     }
+
+    fn orthographic_ray(&self, px: Real, py: Real) -> Ray {
+        Ray::new(
+            self.lower_left_corner + (self.horizontal * px) + (self.vertical * py),
+            -self.w,
+        )
+    }
+
+    // equidistant projection, the angle from the view direction grows linearly with the
+    // distance from the image center and reaches fov / 2 at the top and bottom edges
+    fn fisheye_ray(&self, px: Real, py: Real, fov_deg: Real) -> Ray {
+        let x = (2.0 * px - 1.0) * self.aspect_ratio;
+        let y = 2.0 * py - 1.0;
+
+        let theta = (x * x + y * y).sqrt() * fov_deg.to_radians() * 0.5;
+        let phi = y.atan2(x);
+
+        let direction = (self.u * (theta.sin() * phi.cos())) + (self.v * (theta.sin() * phi.sin()))
+            - (self.w * theta.cos());
+
+        Ray::new(self.origin, direction)
+    }
+
+    // full 360 x 180 degree latitude/longitude panorama
+    fn equirectangular_ray(&self, px: Real, py: Real) -> Ray {
+        let longitude = (px - 0.5) * 2.0 * PI;
+        let latitude = (py - 0.5) * PI;
+
+        let direction = (self.u * (latitude.cos() * longitude.sin())) + (self.v * latitude.sin())
+            - (self.w * (latitude.cos() * longitude.cos()));
+
+        Ray::new(self.origin, direction)
+    }
+
+    // 360 degrees horizontally, perspective vertically with the given vertical fov
+    fn cylindrical_ray(&self, px: Real, py: Real, fov_deg: Real) -> Ray {
+        let longitude = (px - 0.5) * 2.0 * PI;
+        let height = (2.0 * py - 1.0) * (fov_deg.to_radians() * 0.5).tan();
+
+        let direction = (self.u * longitude.sin()) + (self.v * height) - (self.w * longitude.cos());
+
+        Ray::new(self.origin, direction)
+    }
 }
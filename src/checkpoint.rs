@@ -0,0 +1,296 @@
+// Checkpoints let a long render be resumed after it's stopped, or several independent
+// renders of the same scene be combined.
+//
+// The file is little endian binary:
+//   magic "CRAYCKPT", format version u32,
+//   width u64, height u64, seed u64, samples per pixel u64, ray step u64, scene hash u64,
+//   pass u64, row u64,
+//   the number of other seeds merged in u64 and the seeds as u64,
+//   then for every pixel, bottom row first, the radiance sum as three f64 and the sample
+//   count as u32.
+// Sums are always stored as f64 so checkpoints work with either precision.
+
+use crate::defs::Real;
+use crate::display::Accumulator;
+use crate::math::Color3;
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"CRAYCKPT";
+const VERSION: u32 = 3;
+// three f64 and a u32
+const PIXEL_SIZE: usize = 28;
+
+/// The state of a render between two rows: the samples so far and where to carry on from.
+pub struct Checkpoint {
+    pub seed: u64,
+    // what the render was asked for, a checkpoint can only be resumed or merged with the
+    // same ray step and scene, see scenefile::scene_hash
+    pub samples_per_pixel: u64,
+    pub ray_step: u64,
+    pub scene_hash: u64,
+    // the pass in progress and how many of its rows are done
+    pub pass: u64,
+    pub row: u64,
+    /// The seeds of the renders merged into this one, besides `seed`.
+    pub merged_seeds: Vec<u64>,
+    pub accumulator: Accumulator,
+}
+
+impl Checkpoint {
+    pub fn new(width: usize, height: usize, seed: u64) -> Checkpoint {
+        Checkpoint {
+            seed,
+            samples_per_pixel: 0,
+            ray_step: 1,
+            scene_hash: 0,
+            pass: 0,
+            row: 0,
+            merged_seeds: Vec::new(),
+            accumulator: Accumulator::new(width, height),
+        }
+    }
+
+    /// Writes the checkpoint next to `path` first and then moves it into place, so a crash
+    /// while saving leaves the previous checkpoint intact.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let partial_path = path.with_extension("partial");
+
+        let mut writer = BufWriter::new(File::create(&partial_path)?);
+        self.write_to(&mut writer)?;
+
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&partial_path, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint> {
+        Checkpoint::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Writes the checkpoint in the file format to any writer, e.g. a socket.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        for value in [
+            self.accumulator.width as u64,
+            self.accumulator.height as u64,
+            self.seed,
+            self.samples_per_pixel,
+            self.ray_step,
+            self.scene_hash,
+            self.pass,
+            self.row,
+            self.merged_seeds.len() as u64,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for seed in self.merged_seeds.iter() {
+            writer.write_all(&seed.to_le_bytes())?;
+        }
+
+        for y in 0..self.accumulator.height {
+            for x in 0..self.accumulator.width {
+                let sum = self.accumulator.sample_sum(x, y);
+                for channel in 0..3 {
+                    // a no-op when Real is already f64
+                    #[allow(clippy::unnecessary_cast)]
+                    writer.write_all(&(sum[channel] as f64).to_le_bytes())?;
+                }
+                writer.write_all(&self.accumulator.samples_at(x, y).to_le_bytes())?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Checkpoint> {
+        Checkpoint::read_checked(reader, None)
+    }
+
+    /// Like [`Checkpoint::read_from`] but fails before reading any samples if the checkpoint
+    /// isn't `width` by `height`, for checkpoints from a source that can't be trusted.
+    pub fn read_sized_from<R: Read>(
+        reader: &mut R,
+        width: usize,
+        height: usize,
+    ) -> io::Result<Checkpoint> {
+        Checkpoint::read_checked(reader, Some((width, height)))
+    }
+
+    fn read_checked<R: Read>(
+        reader: &mut R,
+        size: Option<(usize, usize)>,
+    ) -> io::Result<Checkpoint> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("Not a crayfish checkpoint"));
+        }
+
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(invalid_data(&format!(
+                "Unsupported checkpoint version {}",
+                version
+            )));
+        }
+
+        let width = read_u64(reader)? as usize;
+        let height = read_u64(reader)? as usize;
+        if let Some((expected_width, expected_height)) = size {
+            if (width, height) != (expected_width, expected_height) {
+                return Err(invalid_data(&format!(
+                    "The checkpoint is {}x{} but {}x{} was expected",
+                    width, height, expected_width, expected_height
+                )));
+            }
+        }
+        let pixels_size = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(PIXEL_SIZE))
+            .ok_or_else(|| {
+                invalid_data(&format!(
+                    "A checkpoint of {}x{} is too large",
+                    width, height
+                ))
+            })?;
+        let seed = read_u64(reader)?;
+        let samples_per_pixel = read_u64(reader)?;
+        let ray_step = read_u64(reader)?;
+        let scene_hash = read_u64(reader)?;
+        let pass = read_u64(reader)?;
+        let row = read_u64(reader)?;
+        let merged_seed_count = read_u64(reader)?;
+        // one at a time, the count can't be trusted any more than the size
+        let mut merged_seeds = Vec::new();
+        for _ in 0..merged_seed_count {
+            merged_seeds.push(read_u64(reader)?);
+        }
+
+        // the samples are read before anything is allocated for the image, so a header
+        // claiming a huge image only costs as much memory as there are samples behind it
+        let mut pixels = Vec::new();
+        reader.take(pixels_size as u64).read_to_end(&mut pixels)?;
+        if pixels.len() != pixels_size {
+            return Err(invalid_data(&format!(
+                "The checkpoint is {}x{} but only has samples for {} pixels",
+                width,
+                height,
+                pixels.len() / PIXEL_SIZE
+            )));
+        }
+
+        let mut checkpoint = Checkpoint::new(width, height, seed);
+        checkpoint.samples_per_pixel = samples_per_pixel;
+        checkpoint.ray_step = ray_step;
+        checkpoint.scene_hash = scene_hash;
+        checkpoint.pass = pass;
+        checkpoint.row = row;
+        checkpoint.merged_seeds = merged_seeds;
+
+        let mut pixels = pixels.as_slice();
+        for y in 0..height {
+            for x in 0..width {
+                let red = read_f64(&mut pixels)? as Real;
+                let green = read_f64(&mut pixels)? as Real;
+                let blue = read_f64(&mut pixels)? as Real;
+                let count = read_u32(&mut pixels)?;
+                checkpoint
+                    .accumulator
+                    .add_samples(x, y, &Color3::new(red, green, blue), count);
+            }
+        }
+
+        Ok(checkpoint)
+    }
+
+    /// Fails unless the checkpoint is of an image of the given size rendered with the same
+    /// ray step from the same scene.
+    pub fn check_matches(
+        &self,
+        width: usize,
+        height: usize,
+        ray_step: u64,
+        scene_hash: u64,
+    ) -> io::Result<()> {
+        let (own_width, own_height) = (self.accumulator.width, self.accumulator.height);
+        if (own_width, own_height) != (width, height) {
+            return Err(invalid_data(&format!(
+                "The checkpoint is {}x{} but the render is {}x{}",
+                own_width, own_height, width, height
+            )));
+        }
+        if self.ray_step != ray_step {
+            return Err(invalid_data(&format!(
+                "The checkpoint was rendered with a ray step of {} but the render uses {}",
+                self.ray_step, ray_step
+            )));
+        }
+        if self.scene_hash != scene_hash {
+            return Err(invalid_data(
+                "The checkpoint was rendered from another scene",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Adds the samples of another render of the same scene. Fails if the other render is of
+    /// another scene or shares a seed with any render merged so far, whose samples would
+    /// just be repeated.
+    pub fn merge(&mut self, other: &Checkpoint) -> io::Result<()> {
+        other.check_matches(
+            self.accumulator.width,
+            self.accumulator.height,
+            self.ray_step,
+            self.scene_hash,
+        )?;
+        // every render merged in so far, on either side, has to have its own seed
+        let seeds = || std::iter::once(&self.seed).chain(self.merged_seeds.iter());
+        let other_seeds = std::iter::once(&other.seed).chain(other.merged_seeds.iter());
+        for seed in other_seeds.clone() {
+            if seeds().any(|own| own == seed) {
+                return Err(invalid_data(&format!(
+                    "Both checkpoints have samples rendered with seed {}",
+                    seed
+                )));
+            }
+        }
+        self.merged_seeds.extend(other_seeds);
+
+        self.accumulator.merge(&other.accumulator);
+        self.samples_per_pixel += other.samples_per_pixel;
+
+        // carry on with passes this seed hasn't rendered yet, a pass that was only partly
+        // done is skipped rather than having its first rows rendered twice
+        let partial = (self.row > 0 || other.row > 0) as u64;
+        self.pass += other.pass + partial;
+        self.row = 0;
+        Ok(())
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
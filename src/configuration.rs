@@ -1,9 +1,21 @@
 use crate::defs::Real;
-use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// The version of the scene format written by this build, see scenefile::migrate.
+pub const CURRENT_VERSION: u32 = 2;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Configuration {
+    /// The version of the scene format the file was written for, files without one are from
+    /// before the format was versioned and are migrated when they're loaded.
+    #[serde(default)]
+    pub version: u32,
     pub width: i64,
     pub aspect_ratio: Real,
     pub output_path: String,
@@ -11,41 +23,238 @@ pub struct Configuration {
     pub samples_per_pixel: i64,
     pub ray_max_depth: i64,
     pub camera: Camera,
+    #[serde(default)]
+    pub shapes: Vec<Shape>,
+    pub frames: Option<FrameRange>,
+    pub include: Option<Vec<String>>,
+    pub materials: Option<HashMap<String, Material>>,
+    pub objects: Option<HashMap<String, Vec<Shape>>>,
+    pub generators: Option<Vec<Generator>>,
+    pub write_stats: Option<bool>,
+    pub time_budget_seconds: Option<f64>,
+    pub seed: Option<u64>,
+    pub checkpoint_interval_seconds: Option<f64>,
+}
+
+// the parts of a scene that can be shared between files with include
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SceneFragment {
+    #[serde(default)]
     pub shapes: Vec<Shape>,
+    pub include: Option<Vec<String>>,
+    pub materials: Option<HashMap<String, Material>>,
+    pub objects: Option<HashMap<String, Vec<Shape>>>,
+    pub generators: Option<Vec<Generator>>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Generator {
+    #[serde(rename = "type")]
+    pub type_field: String,
+    pub shape: Shape,
+    pub count: Vec<i64>,
+    pub origin: Option<Vec<Real>>,
+    pub spacing: Option<Vec<Real>>,
+    pub jitter: Option<Vec<Real>>,
+    pub min: Option<Vec<Real>>,
+    pub max: Option<Vec<Real>>,
+    pub materials: Option<Vec<MaterialChoice>>,
+    pub exclude: Option<Vec<Exclusion>>,
+    pub seed: Option<u64>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MaterialChoice {
+    pub weight: Real,
+    pub material: MaterialReference,
+    pub diffuse_range: Option<Vec<Real>>,
+    pub fuzz_range: Option<Vec<Real>>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Exclusion {
+    pub center: Vec<Real>,
+    pub radius: Real,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameRange {
+    pub start: i64,
+    pub end: i64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Camera {
-    pub fov_deg: Real,
+    pub model: Option<String>,
+    pub fov_deg: Option<Real>,
     pub position: Vec<Real>,
     pub look_at: Vec<Real>,
     pub up: Vec<Real>,
+    #[serde(default)]
     pub aperture: Real,
+    pub focus_distance: Option<Real>,
+    pub focus_target: Option<Vec<Real>>,
+    pub aperture_blades: Option<u32>,
+    pub aperture_rotation_deg: Option<Real>,
+    pub aperture_mask: Option<String>,
+    pub view_height: Option<Real>,
+    /// Focal length of a thin lens camera in millimetres.
+    pub focal_length: Option<Real>,
+    pub f_stop: Option<Real>,
+    /// Width of a thin lens camera's sensor in millimetres, 36 by default.
+    pub sensor_width: Option<Real>,
+    /// How long one scene unit is in metres, 1 by default. Thin lens cameras use it to turn
+    /// their aperture into scene units.
+    pub meters_per_unit: Option<Real>,
+    pub keyframes: Option<Vec<CameraKeyframe>>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+pub struct CameraKeyframe {
+    pub frame: i64,
+    pub interpolation: Option<String>,
+    pub position: Option<Vec<Real>>,
+    pub look_at: Option<Vec<Real>>,
+    pub fov_deg: Option<Real>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Shape {
     #[serde(rename = "type")]
     pub type_field: String,
-    pub material: Material,
+    #[serde(default)]
+    pub material: MaterialReference,
+    #[serde(default)]
     pub transform: Transform,
+    pub object: Option<String>,
+    pub boundary: Option<Box<Shape>>,
+    pub density: Option<Real>,
+    pub path: Option<String>,
+    pub resolution: Option<[usize; 3]>,
+    pub density_scale: Option<Real>,
+    pub emission_scale: Option<Real>,
+    pub temperature_scale: Option<Real>,
+    pub normals: Option<String>,
+    pub point_radius: Option<Real>,
+    pub keyframes: Option<Vec<ShapeKeyframe>>,
+    /// Values for shape types registered with WorldBuilder that the fields above don't cover.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub parameters: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+pub struct ShapeKeyframe {
+    pub frame: i64,
+    pub interpolation: Option<String>,
+    pub position: Option<Vec<Real>>,
+    pub size: Option<Vec<Real>>,
+    pub diffuse: Option<Vec<Real>>,
+    pub fuzz: Option<Real>,
+    pub refraction_index: Option<Real>,
+    pub albedo: Option<Vec<Real>>,
+    pub anisotropy: Option<Real>,
+}
+
+// either the name of an entry in the materials map or the material itself
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum MaterialReference {
+    Named(String),
+    Inline(Material),
+}
+
+// by hand rather than untagged, which would only say that neither variant matched instead of
+// what's wrong with an inline material
+impl<'de> Deserialize<'de> for MaterialReference {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ReferenceVisitor;
+
+        impl<'de> Visitor<'de> for ReferenceVisitor {
+            type Value = MaterialReference;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("the name of a material or a material")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+                Ok(MaterialReference::Named(name.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                Material::deserialize(MapAccessDeserializer::new(map))
+                    .map(MaterialReference::Inline)
+            }
+        }
+
+        deserializer.deserialize_any(ReferenceVisitor)
+    }
+}
+
+impl Default for MaterialReference {
+    fn default() -> Self {
+        MaterialReference::Inline(Material::default())
+    }
+}
+
+impl MaterialReference {
+    pub fn resolve<'a>(&'a self, materials: &'a Option<HashMap<String, Material>>) -> &'a Material {
+        match self {
+            MaterialReference::Inline(material) => material,
+            MaterialReference::Named(name) => materials
+                .as_ref()
+                .and_then(|materials| materials.get(name))
+                .unwrap_or_else(|| panic!("Unknown material {}", name)),
+        }
+    }
+
+    pub fn make_inline(&mut self, materials: &Option<HashMap<String, Material>>) -> &mut Material {
+        if let MaterialReference::Named(_) = self {
+            *self = MaterialReference::Inline(self.resolve(materials).clone());
+        }
+
+        match self {
+            MaterialReference::Inline(material) => material,
+            MaterialReference::Named(_) => unreachable!(),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Material {
     #[serde(rename = "type")]
     pub type_field: String,
     pub diffuse: Option<Vec<Real>>,
     pub fuzz: Option<Real>,
     pub refraction_index: Option<Real>,
+    pub albedo: Option<Vec<Real>>,
+    pub anisotropy: Option<Real>,
+    pub emission: Option<Vec<Real>>,
+    pub two_sided: Option<bool>,
+    /// Values for material types registered with WorldBuilder that the fields above don't
+    /// cover.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub parameters: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Transform {
     pub position: Vec<Real>,
     pub size: Vec<Real>,
 }
+
+/// The JSON Schema of a scene file. Camera, Shape, Material and Transform are in its
+/// definitions.
+pub fn json_schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(Configuration)
+}
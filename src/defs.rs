@@ -1,2 +1,9 @@
-//pub type Real = f64;
+#[cfg(feature = "f64")]
+pub type Real = f64;
+#[cfg(not(feature = "f64"))]
 pub type Real = f32;
+
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
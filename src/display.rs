@@ -1,6 +1,90 @@
 use crate::defs::Real;
 use crate::math::Color3;
 
+use exr::prelude::{Image, SpecificChannels, Vec2, WritableImage};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::Path;
+
+/// Running sums of radiance samples for every pixel, rendering adds to it a pass at a time so
+/// it can be turned into a valid [`Canvas`] whenever rendering stops.
+pub struct Accumulator {
+    pub width: usize,
+    pub height: usize,
+    sums: Vec<Color3>,
+    counts: Vec<u32>,
+}
+
+impl Accumulator {
+    pub fn new(width: usize, height: usize) -> Accumulator {
+        Accumulator {
+            width,
+            height,
+            sums: vec![Color3::default(); width * height],
+            counts: vec![0; width * height],
+        }
+    }
+
+    /// Adds one radiance sample to a pixel. `y` counts upwards from the bottom of the image.
+    pub fn add_sample(&mut self, x: usize, y: usize, color: &Color3) {
+        let index = y * self.width + x;
+        self.sums[index] += *color;
+        self.counts[index] += 1;
+    }
+
+    /// Adds `count` samples that together sum to `sum`, e.g. from another accumulator.
+    pub fn add_samples(&mut self, x: usize, y: usize, sum: &Color3, count: u32) {
+        let index = y * self.width + x;
+        self.sums[index] += *sum;
+        self.counts[index] += count;
+    }
+
+    /// Adds every sample of `other`, which has to be the same size.
+    pub fn merge(&mut self, other: &Accumulator) {
+        assert!(
+            self.width == other.width && self.height == other.height,
+            "Can't merge {}x{} samples into a {}x{} image",
+            other.width,
+            other.height,
+            self.width,
+            self.height
+        );
+
+        for (sum, other_sum) in self.sums.iter_mut().zip(other.sums.iter()) {
+            *sum += *other_sum;
+        }
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += *other_count;
+        }
+    }
+
+    pub fn samples_at(&self, x: usize, y: usize) -> u32 {
+        self.counts[y * self.width + x]
+    }
+
+    pub fn sample_sum(&self, x: usize, y: usize) -> Color3 {
+        self.sums[y * self.width + x]
+    }
+
+    /// Averages the samples of every pixel, pixels without any are left black.
+    pub fn to_canvas(&self) -> Canvas {
+        let mut canvas = Canvas::new(self.width, self.height);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = y * self.width + x;
+                if self.counts[index] > 0 {
+                    canvas.set_pixel(x, y, &self.sums[index], self.counts[index] as i64);
+                }
+            }
+        }
+
+        canvas
+    }
+}
+
+/// An image produced by [`World::render`](crate::World::render), gamma corrected and ready to
+/// be displayed or saved.
 pub struct Canvas {
     pub width: usize,
     pub height: usize,
@@ -16,6 +100,8 @@ impl Canvas {
         }
     }
 
+    /// Sets a pixel from the sum of `samples_per_pixel` radiance samples. `y` counts upwards
+    /// from the bottom of the image.
     pub fn set_pixel(&mut self, x: usize, y: usize, color: &Color3, samples_per_pixel: i64) {
         let mut red: Real = color[0];
         let mut green: Real = color[1];
@@ -28,9 +114,61 @@ impl Canvas {
 
         let new_color = Color3::new(red, green, blue);
 
-        self.data[(self.height as i32 - 1 - y as i32).abs() as usize * self.width + x] = new_color;
+        self.data[(self.height as i32 - 1 - y as i32).unsigned_abs() as usize * self.width + x] =
+            new_color;
+    }
+
+    /// Saves the canvas, the format is chosen from the file extension. `.exr` files hold linear
+    /// 32 bit float radiance, everything else is written as an 8 bit RGB image.
+    pub fn save(&self, path: &str) -> image::ImageResult<()> {
+        let is_exr = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"));
+        if is_exr {
+            let writer = BufWriter::new(File::create(path)?);
+            return self
+                .write_exr(writer)
+                .map_err(|error| image::ImageError::IoError(io::Error::other(error.to_string())));
+        }
+
+        image::save_buffer(
+            path,
+            &self.to_u8_vec(),
+            self.width as u32,
+            self.height as u32,
+            image::ColorType::Rgb8,
+        )
+    }
+
+    /// Writes the canvas as an 8 bit RGB PNG, for when there's no file to save to.
+    pub fn write_png<W: Write>(&self, writer: W) -> image::ImageResult<()> {
+        image::png::PngEncoder::new(writer).encode(
+            &self.to_u8_vec(),
+            self.width as u32,
+            self.height as u32,
+            image::ColorType::Rgb8,
+        )
+    }
+
+    /// Writes the canvas as OpenEXR, squaring the pixels to undo the gamma correction.
+    pub fn write_exr<W: Write + Seek>(&self, writer: W) -> exr::error::UnitResult {
+        // the casts are only needed with f64 reals
+        #[allow(clippy::unnecessary_cast)]
+        let channels = SpecificChannels::rgb(|Vec2(x, y): Vec2<usize>| {
+            let color = self.data[y * self.width + x];
+            (
+                (color[0] * color[0]) as f32,
+                (color[1] * color[1]) as f32,
+                (color[2] * color[2]) as f32,
+            )
+        });
+
+        Image::from_channels((self.width, self.height), channels)
+            .write()
+            .to_buffered(writer)
     }
 
+    /// Returns the pixels as tightly packed 8 bit RGB, top row first.
     pub fn to_u8_vec(&self) -> Vec<u8> {
         let mut u8_vec = Vec::with_capacity(self.data.len() * 3);
 
@@ -43,6 +181,7 @@ impl Canvas {
         u8_vec
     }
 
+    /// Returns the pixels packed as `0RGB` in a `u32` each, top row first.
     pub fn to_u32_vec(&self) -> Vec<u32> {
         let mut u32_vec = Vec::with_capacity(self.data.len() * 3);
 
@@ -0,0 +1,345 @@
+// Rendering one image on several machines.
+//
+// A coordinator holds the scene and splits the samples into jobs, each a range of passes.
+// Workers connect over TCP, get the scene, then render one job at a time and send back the
+// samples they took. Every pass is seeded the same way as a local render so the merged image
+// matches what a single machine would have rendered with the same seed.
+//
+// Messages are JSON preceded by their length as a little endian u32. Results are sent as
+// checkpoints in the same format as checkpoint files.
+//
+//   worker                     coordinator
+//                 <- scene
+//                 <- job
+//   checkpoint ->
+//                 <- job or done
+//
+// Files the scene refers to, like voxel grids, have to exist at the same path on every worker.
+//
+// Neither side trusts the other with its memory: messages have a maximum size and a worker's
+// checkpoint has to be of the image being rendered before its samples are read. A worker that
+// goes quiet for longer than the worker timeout has its job given to another one.
+
+use crate::checkpoint::Checkpoint;
+use crate::configuration::Configuration;
+use crate::defs::Real;
+use crate::display::{Accumulator, Canvas};
+use crate::random;
+use crate::scene::WorldRenderRequest;
+use crate::scenefile;
+use crate::worldbuilder::WorldBuilder;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_PASSES_PER_JOB: u64 = 4;
+const DEFAULT_WORKER_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+// scenes are sent with their generators already expanded so they can be big
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Message {
+    Scene {
+        configuration: Box<Configuration>,
+        seed: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Job {
+        first_pass: u64,
+        passes: u64,
+    },
+    Done,
+}
+
+#[derive(Copy, Clone)]
+struct Job {
+    first_pass: u64,
+    passes: u64,
+}
+
+struct Progress {
+    jobs: VecDeque<Job>,
+    remaining: usize,
+    accumulator: Accumulator,
+}
+
+/// Hands out the passes of a render to workers and merges what they send back.
+pub struct Coordinator {
+    listener: TcpListener,
+    config: Configuration,
+    seed: u64,
+    passes_per_job: u64,
+    worker_timeout: Duration,
+}
+
+impl Coordinator {
+    /// Listens on `address`, `config` should already have gone through
+    /// [`crate::scenefile::expand`] since workers build the world from it as is.
+    pub fn bind<A: ToSocketAddrs>(address: A, config: Configuration) -> io::Result<Coordinator> {
+        let seed = config.seed.unwrap_or_else(random::entropy_seed);
+
+        Ok(Coordinator {
+            listener: TcpListener::bind(address)?,
+            config,
+            seed,
+            passes_per_job: DEFAULT_PASSES_PER_JOB,
+            worker_timeout: DEFAULT_WORKER_TIMEOUT,
+        })
+    }
+
+    pub fn with_passes_per_job(mut self, passes_per_job: u64) -> Coordinator {
+        self.passes_per_job = passes_per_job.max(1);
+        self
+    }
+
+    /// How long a worker may take to send back a job before it's given up on, 10 minutes by
+    /// default. Jobs have to take less than this to render.
+    pub fn with_worker_timeout(mut self, worker_timeout: Duration) -> Coordinator {
+        self.worker_timeout = worker_timeout;
+        self
+    }
+
+    /// The address workers should connect to, useful when bound to port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts workers until every job has been rendered and returns the merged image. A
+    /// worker that disconnects has its job given to another one.
+    pub fn run(self) -> io::Result<Canvas> {
+        let (width, height) = image_size(&self.config);
+
+        let mut jobs = VecDeque::new();
+        let total_passes = self.config.samples_per_pixel.max(0) as u64;
+        let mut first_pass = 0;
+        while first_pass < total_passes {
+            let passes = self.passes_per_job.min(total_passes - first_pass);
+            jobs.push_back(Job { first_pass, passes });
+            first_pass += passes;
+        }
+
+        info!(
+            "Coordinating {} jobs on {}",
+            jobs.len(),
+            self.listener.local_addr()?
+        );
+
+        let state = Arc::new((
+            Mutex::new(Progress {
+                remaining: jobs.len(),
+                jobs,
+                accumulator: Accumulator::new(width, height),
+            }),
+            Condvar::new(),
+        ));
+        let scene = Arc::new((self.config, self.seed));
+
+        // accepting is polled so the loop can notice when the last job comes back
+        self.listener.set_nonblocking(true)?;
+        let mut workers = Vec::new();
+        loop {
+            if state.0.lock().unwrap().remaining == 0 {
+                break;
+            }
+
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    info!("Worker connected from {}", address);
+                    stream.set_nonblocking(false)?;
+                    stream.set_read_timeout(Some(self.worker_timeout))?;
+                    stream.set_write_timeout(Some(self.worker_timeout))?;
+
+                    let state = Arc::clone(&state);
+                    let scene = Arc::clone(&scene);
+                    workers.push(thread::spawn(move || {
+                        if let Err(error) = serve_worker(stream, &scene.0, scene.1, &state) {
+                            warn!("Worker {} failed: {}", address, error);
+                        }
+                    }));
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    let progress = state.0.lock().unwrap();
+                    let _ = state
+                        .1
+                        .wait_timeout(progress, Duration::from_millis(20))
+                        .unwrap();
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        // let every worker hear that it's done before returning
+        for worker in workers {
+            let _ = worker.join();
+        }
+
+        let progress = state.0.lock().unwrap();
+        Ok(progress.accumulator.to_canvas())
+    }
+}
+
+fn serve_worker(
+    stream: TcpStream,
+    config: &Configuration,
+    seed: u64,
+    state: &(Mutex<Progress>, Condvar),
+) -> io::Result<()> {
+    let (width, height) = image_size(config);
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    write_message(
+        &mut writer,
+        &Message::Scene {
+            configuration: Box::new(config.clone()),
+            seed,
+        },
+    )?;
+
+    loop {
+        let job = {
+            let mut progress = state.0.lock().unwrap();
+            if progress.remaining == 0 {
+                break;
+            }
+            progress.jobs.pop_front()
+        };
+
+        let job = match job {
+            Some(job) => job,
+            None => {
+                // everything is handed out but a worker may still fail and put its job back
+                thread::sleep(Duration::from_millis(20));
+                continue;
+            }
+        };
+
+        let result = write_message(
+            &mut writer,
+            &Message::Job {
+                first_pass: job.first_pass,
+                passes: job.passes,
+            },
+        )
+        .and_then(|_| Checkpoint::read_sized_from(&mut reader, width, height))
+        .and_then(|checkpoint| {
+            if checkpoint.seed == seed && checkpoint.pass == job.first_pass + job.passes {
+                Ok(checkpoint)
+            } else {
+                Err(invalid_data("The worker sent the samples of another job"))
+            }
+        });
+
+        let mut progress = state.0.lock().unwrap();
+
+        match result {
+            Ok(checkpoint) => {
+                progress.accumulator.merge(&checkpoint.accumulator);
+                progress.remaining -= 1;
+                info!(
+                    "Passes {} to {} done, {} jobs left",
+                    job.first_pass,
+                    job.first_pass + job.passes - 1,
+                    progress.remaining
+                );
+                state.1.notify_all();
+            }
+            Err(error) => {
+                progress.jobs.push_back(job);
+                return Err(error);
+            }
+        }
+    }
+
+    write_message(&mut writer, &Message::Done)
+}
+
+/// Connects to a coordinator and renders jobs for it until it says it's done.
+pub fn run_worker<A: ToSocketAddrs>(address: A) -> io::Result<()> {
+    let stream = TcpStream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let (config, seed) = match read_message(&mut reader)? {
+        Message::Scene {
+            configuration,
+            seed,
+        } => (*configuration, seed),
+        _ => return Err(invalid_data("Expected the scene first")),
+    };
+
+    let (width, height) = image_size(&config);
+    let scene_hash = scenefile::scene_hash(&config);
+    let world = WorldBuilder::from_config(&config);
+    info!("Received the scene, rendering {}x{}", width, height);
+
+    loop {
+        match read_message(&mut reader)? {
+            Message::Job { first_pass, passes } => {
+                // starting from an empty checkpoint at the job's first pass makes the render
+                // take exactly the samples a local render would for those passes
+                let mut start = Checkpoint::new(width, height, seed);
+                start.ray_step = config.ray_step as u64;
+                start.scene_hash = scene_hash;
+                start.pass = first_pass;
+
+                let request = WorldRenderRequest::new(
+                    (first_pass + passes) as i64,
+                    config.ray_max_depth,
+                    config.ray_step,
+                    width,
+                    height,
+                )
+                .with_scene_hash(scene_hash)
+                .with_resume(start);
+                let (samples, _) = world.render_to_checkpoint(request)?;
+                samples.write_to(&mut writer)?;
+            }
+            Message::Done => return Ok(()),
+            Message::Scene { .. } => return Err(invalid_data("Received a second scene")),
+        }
+    }
+}
+
+fn image_size(config: &Configuration) -> (usize, usize) {
+    let width = config.width as usize;
+    (width, (width as Real / config.aspect_ratio) as usize)
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Message) -> io::Result<()> {
+    let bytes = serde_json::to_vec(message)?;
+    if bytes.len() > MAX_MESSAGE_SIZE {
+        return Err(invalid_data("The message is too large to send"));
+    }
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)?;
+    writer.flush()
+}
+
+fn read_message<R: Read>(reader: &mut R) -> io::Result<Message> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(invalid_data(&format!(
+            "A message of {} bytes is larger than the maximum of {}",
+            length, MAX_MESSAGE_SIZE
+        )));
+    }
+
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,476 @@
+// Imports glTF 2.0 assets, .gltf with its buffers and images or a single .glb.
+//
+// Every mesh of the default scene is placed with its node's transform and rendered with the
+// metallic-roughness model, base colour, metallic-roughness and emissive textures included.
+// The first camera in the node hierarchy and KHR_lights_punctual point and spot lights are
+// read too. Skins, morph targets, animations, normal maps and alpha are left out with a
+// warning, as are directional lights, which can't be hit by a ray.
+
+use crate::aabb::Aabb;
+use crate::camera::{Camera, CameraModel};
+use crate::defs::Real;
+use crate::material::{MetallicRoughness, Scatterer};
+use crate::math::{Color3, Matrix4, Point3, Vector3};
+use crate::mesh::{Mesh, TriangleMesh};
+use crate::scene::World;
+use crate::shapes::{Intersectable, ShapeGroup, Sphere};
+use crate::texture::{self, ImageTexture, Wrap};
+
+use gltf::khr_lights_punctual::Kind;
+use gltf::material::AlphaMode;
+use gltf::mesh::Mode;
+use gltf::texture::WrappingMode;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// relative to the diagonal of the asset's bounds
+const POINT_LIGHT_RADIUS: Real = 0.002;
+
+/// The shapes, camera and lights of a glTF asset.
+pub struct GltfAsset {
+    /// The meshes, and a small emitting sphere for every point light.
+    pub shapes: Vec<Arc<dyn Intersectable>>,
+    camera: Option<(Matrix4, CameraModel, Option<Real>)>,
+    bounds: Option<Aabb>,
+    /// Everything in the asset that was left out, each reported once.
+    pub warnings: Vec<String>,
+}
+
+impl GltfAsset {
+    /// The aspect ratio the asset's camera asks for, if it has a camera and asks for one.
+    pub fn aspect_ratio(&self) -> Option<Real> {
+        self.camera
+            .as_ref()
+            .and_then(|(_, _, aspect_ratio)| *aspect_ratio)
+    }
+
+    /// The asset's camera, or one looking at the whole asset along -z if it doesn't have one.
+    pub fn camera(&self, aspect_ratio: Real) -> Camera {
+        let (camera_to_world, model) = match &self.camera {
+            Some((camera_to_world, model, _)) => (*camera_to_world, *model),
+            None => {
+                let (center, radius) = match &self.bounds {
+                    Some(bounds) => (
+                        (bounds.min + bounds.max) * 0.5,
+                        (bounds.max - bounds.min).magnitude() * 0.5,
+                    ),
+                    None => (Point3::default(), 1.0),
+                };
+                let fov_deg: Real = 40.0;
+                let distance = radius / (fov_deg.to_radians() * 0.5).sin();
+                let origin = center + Vector3::new(0.0, 0.0, distance);
+                let camera_to_world = Matrix4::translate(origin[0], origin[1], origin[2]);
+                (camera_to_world, CameraModel::Perspective { fov_deg })
+            }
+        };
+
+        // glTF cameras look down -z with y up
+        let origin = camera_to_world.transform_point(&Point3::default());
+        let look_at = camera_to_world.transform_point(&Point3::new(0.0, 0.0, -1.0));
+        let up = camera_to_world.transform_vector(&Vector3::new(0.0, 1.0, 0.0));
+
+        Camera::with_model(origin, look_at, up, aspect_ratio, model, 1.0, 0.0)
+    }
+
+    /// All the shapes as one, to add the asset to a scene built from a configuration.
+    pub fn into_group(self) -> ShapeGroup {
+        ShapeGroup {
+            shapes: self.shapes,
+        }
+    }
+
+    /// A world with the asset's camera, at the aspect ratio its camera asks for if it doesn't
+    /// ask for one itself.
+    pub fn into_world(self, aspect_ratio: Real) -> World {
+        let aspect_ratio = self.aspect_ratio().unwrap_or(aspect_ratio);
+        let mut world = World::new(self.camera(aspect_ratio));
+        for shape in self.shapes {
+            world.add_shared_shape(shape);
+        }
+
+        world
+    }
+}
+
+/// Imports the default scene of a glTF asset, or its first scene if there's no default, with
+/// `transform` applied on top of the asset's own transforms.
+pub fn load<P: AsRef<Path>>(path: P, transform: &Matrix4) -> io::Result<GltfAsset> {
+    let (document, buffers, images) =
+        gltf::import(path).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    let mut importer = Importer {
+        buffers,
+        images,
+        materials: HashMap::new(),
+        textures: HashMap::new(),
+        shapes: Vec::new(),
+        camera: None,
+        point_lights: Vec::new(),
+        bounds: None,
+        warnings: Vec::new(),
+        warned: HashSet::new(),
+    };
+
+    if document.animations().next().is_some() {
+        importer.warn(String::from(
+            "Animations aren't supported, using the rest pose",
+        ));
+    }
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "asset has no scenes"))?;
+    for node in scene.nodes() {
+        importer.node(&node, transform)?;
+    }
+
+    Ok(importer.finish())
+}
+
+struct Importer {
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<gltf::image::Data>,
+    // by material index, None is the default material
+    materials: HashMap<Option<usize>, Arc<dyn Scatterer>>,
+    // by image index and whether it's sRGB encoded
+    textures: HashMap<(usize, bool), Arc<ImageTexture>>,
+    shapes: Vec<Arc<dyn Intersectable>>,
+    camera: Option<(Matrix4, CameraModel, Option<Real>)>,
+    point_lights: Vec<(Point3, Color3)>,
+    bounds: Option<Aabb>,
+    warnings: Vec<String>,
+    warned: HashSet<String>,
+}
+
+impl Importer {
+    fn warn(&mut self, message: String) {
+        if self.warned.insert(message.clone()) {
+            warn!("{}", message);
+            self.warnings.push(message);
+        }
+    }
+
+    fn node(&mut self, node: &gltf::Node, parent: &Matrix4) -> io::Result<()> {
+        let columns: Vec<Real> = node
+            .transform()
+            .matrix()
+            .iter()
+            .flatten()
+            .map(|&value| value as Real)
+            .collect();
+        let transform = *parent * Matrix4::from_columns(&columns);
+
+        if let Some(mesh) = node.mesh() {
+            if node.skin().is_some() {
+                self.warn(String::from("Skins aren't supported, using the bind pose"));
+            }
+            self.mesh(&mesh, &transform)?;
+        }
+        if let Some(camera) = node.camera() {
+            self.camera(&camera, &transform);
+        }
+        if let Some(light) = node.light() {
+            self.light(&light, &transform);
+        }
+
+        for child in node.children() {
+            self.node(&child, &transform)?;
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::unnecessary_cast)] // only needed when Real is f32
+    fn mesh(&mut self, mesh: &gltf::Mesh, transform: &Matrix4) -> io::Result<()> {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                self.warn(format!(
+                    "Primitives drawn as {:?} aren't supported and are left out",
+                    primitive.mode()
+                ));
+                continue;
+            }
+            if primitive.morph_targets().next().is_some() {
+                self.warn(String::from(
+                    "Morph targets aren't supported, using the base mesh",
+                ));
+            }
+
+            let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+            let positions: Vec<Point3> = match reader.read_positions() {
+                Some(positions) => positions
+                    .map(|p| Point3::new(p[0] as Real, p[1] as Real, p[2] as Real))
+                    .collect(),
+                None => continue,
+            };
+            let normals: Vec<Vector3> = reader
+                .read_normals()
+                .map(|normals| {
+                    normals
+                        .map(|n| Vector3::new(n[0] as Real, n[1] as Real, n[2] as Real))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let material = primitive.material();
+            let uv_set = material
+                .pbr_metallic_roughness()
+                .base_color_texture()
+                .map_or(0, |info| info.tex_coord());
+            let uvs: Vec<[Real; 2]> = reader
+                .read_tex_coords(uv_set)
+                .map(|uvs| {
+                    uvs.into_f32()
+                        .map(|uv| [uv[0] as Real, uv[1] as Real])
+                        .collect()
+                })
+                .unwrap_or_default();
+            // COLOR_0 multiplies the base colour
+            let colors: Vec<Color3> = reader
+                .read_colors(0)
+                .map(|colors| {
+                    colors
+                        .into_rgb_f32()
+                        .map(|c| Color3::new(c[0] as Real, c[1] as Real, c[2] as Real))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let indices: Vec<usize> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().map(|index| index as usize).collect(),
+                None => (0..positions.len()).collect(),
+            };
+            if indices.iter().any(|&index| index >= positions.len()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "a triangle refers to a vertex that isn't there",
+                ));
+            }
+
+            let mut mesh = Mesh {
+                normals: if normals.len() == positions.len() {
+                    normals
+                } else {
+                    Vec::new()
+                },
+                uvs: if uvs.len() == positions.len() {
+                    uvs
+                } else {
+                    Vec::new()
+                },
+                colors: if colors.len() == positions.len() {
+                    colors
+                } else {
+                    Vec::new()
+                },
+                positions,
+                triangles: indices
+                    .chunks_exact(3)
+                    .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                    .collect(),
+            }
+            .transformed(transform);
+            // mirroring turns counter clockwise triangles clockwise
+            if transform.swaps_handedness() {
+                mesh.flip_winding();
+            }
+
+            if let Some(bounds) = mesh.bounds() {
+                self.bounds = Some(match self.bounds {
+                    Some(existing) => existing.surrounding(&bounds),
+                    None => bounds,
+                });
+            }
+            let material = self.material(&material);
+            self.shapes
+                .push(Arc::new(TriangleMesh::new(mesh, material)));
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::unnecessary_cast)] // only needed when Real is f32
+    fn material(&mut self, material: &gltf::Material) -> Arc<dyn Scatterer> {
+        if let Some(existing) = self.materials.get(&material.index()) {
+            return existing.clone();
+        }
+
+        if material.alpha_mode() != AlphaMode::Opaque {
+            self.warn(String::from("Transparency from alpha isn't supported"));
+        }
+        if material.normal_texture().is_some() {
+            self.warn(String::from("Normal maps aren't supported"));
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let emissive = material.emissive_factor();
+        let emissive_strength = material.emissive_strength().unwrap_or(1.0) as Real;
+        let transmission = material.transmission();
+
+        let scatterer = Arc::new(MetallicRoughness {
+            base_color: Color3::new(r as Real, g as Real, b as Real),
+            base_color_texture: pbr
+                .base_color_texture()
+                .map(|info| self.texture(&info.texture(), true)),
+            metallic: pbr.metallic_factor() as Real,
+            roughness: pbr.roughness_factor() as Real,
+            metallic_roughness_texture: pbr
+                .metallic_roughness_texture()
+                .map(|info| self.texture(&info.texture(), false)),
+            emission: Color3::new(
+                emissive[0] as Real,
+                emissive[1] as Real,
+                emissive[2] as Real,
+            ) * emissive_strength,
+            emission_texture: material
+                .emissive_texture()
+                .map(|info| self.texture(&info.texture(), true)),
+            transmission: transmission.map_or(0.0, |t| t.transmission_factor() as Real),
+            refraction_index: material.ior().unwrap_or(1.5) as Real,
+            double_sided: material.double_sided(),
+        });
+
+        self.materials.insert(material.index(), scatterer.clone());
+        scatterer
+    }
+
+    // colour textures are sRGB encoded, the metallic-roughness texture is linear
+    fn texture(&mut self, texture: &gltf::Texture, srgb: bool) -> Arc<ImageTexture> {
+        let index = texture.source().index();
+        if let Some(existing) = self.textures.get(&(index, srgb)) {
+            return existing.clone();
+        }
+
+        let sampler = texture.sampler();
+        let image = &self.images[index];
+        let texture = Arc::new(
+            image_texture(image, srgb).with_wrap(wrap(sampler.wrap_s()), wrap(sampler.wrap_t())),
+        );
+
+        self.textures.insert((index, srgb), texture.clone());
+        texture
+    }
+
+    #[allow(clippy::unnecessary_cast)] // only needed when Real is f32
+    fn camera(&mut self, camera: &gltf::Camera, transform: &Matrix4) {
+        if self.camera.is_some() {
+            self.warn(String::from("Only the first camera is used"));
+            return;
+        }
+
+        let (model, aspect_ratio) = match camera.projection() {
+            gltf::camera::Projection::Perspective(perspective) => (
+                CameraModel::Perspective {
+                    fov_deg: (perspective.yfov() as Real).to_degrees(),
+                },
+                perspective.aspect_ratio().map(|ratio| ratio as Real),
+            ),
+            gltf::camera::Projection::Orthographic(orthographic) => (
+                CameraModel::Orthographic {
+                    view_height: 2.0 * orthographic.ymag() as Real,
+                },
+                Some((orthographic.xmag() / orthographic.ymag()) as Real),
+            ),
+        };
+
+        self.camera = Some((*transform, model, aspect_ratio));
+    }
+
+    #[allow(clippy::unnecessary_cast)] // only needed when Real is f32
+    fn light(&mut self, light: &gltf::khr_lights_punctual::Light, transform: &Matrix4) {
+        match light.kind() {
+            Kind::Point => {}
+            Kind::Spot { .. } => self.warn(String::from(
+                "Spot lights are rendered as point lights shining everywhere",
+            )),
+            Kind::Directional => {
+                self.warn(String::from(
+                    "Directional lights aren't supported and are left out",
+                ));
+                return;
+            }
+        }
+
+        let [r, g, b] = light.color();
+        let intensity = Color3::new(r as Real, g as Real, b as Real) * light.intensity() as Real;
+        let position = transform.transform_point(&Point3::default());
+        self.point_lights.push((position, intensity));
+    }
+
+    fn finish(mut self) -> GltfAsset {
+        let scale = self
+            .bounds
+            .map_or(1.0, |bounds| (bounds.max - bounds.min).magnitude());
+        let radius = POINT_LIGHT_RADIUS * scale.max(1e-3);
+        for (position, intensity) in self.point_lights.iter() {
+            let light = Sphere::point_light(*position, *intensity, radius);
+            self.shapes.push(Arc::new(light));
+        }
+
+        GltfAsset {
+            shapes: self.shapes,
+            camera: self.camera,
+            bounds: self.bounds,
+            warnings: self.warnings,
+        }
+    }
+}
+
+fn wrap(mode: WrappingMode) -> Wrap {
+    match mode {
+        WrappingMode::Repeat => Wrap::Repeat,
+        WrappingMode::MirroredRepeat => Wrap::MirroredRepeat,
+        WrappingMode::ClampToEdge => Wrap::ClampToEdge,
+    }
+}
+
+#[allow(clippy::unnecessary_cast)] // only needed when Real is f32
+fn image_texture(image: &gltf::image::Data, srgb: bool) -> ImageTexture {
+    use gltf::image::Format;
+
+    // channels and bytes per channel
+    let (channels, size) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let channel = |bytes: &[u8]| -> Real {
+        let value = match size {
+            1 => bytes[0] as Real / 255.0,
+            2 => u16::from_le_bytes([bytes[0], bytes[1]]) as Real / 65535.0,
+            _ => return f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as Real,
+        };
+        if srgb {
+            texture::srgb_to_linear(value)
+        } else {
+            value
+        }
+    };
+
+    let texels = image
+        .pixels
+        .chunks_exact(channels * size)
+        .map(|pixel| {
+            let value = |index: usize| channel(&pixel[index.min(channels - 1) * size..]);
+            // a single channel is grey, two are grey and alpha
+            if channels < 3 {
+                Color3::new(value(0), value(0), value(0))
+            } else {
+                Color3::new(value(0), value(1), value(2))
+            }
+        })
+        .collect();
+
+    ImageTexture::new(image.width as usize, image.height as usize, texels)
+}
@@ -0,0 +1,364 @@
+// Comparing two renders of the same scene, as done by `crayfish diff` and the regression tests.
+//
+// Images are compared the way crayfish displays them: linear radiance, as stored in EXR files,
+// is gamma corrected and clamped first, so a PNG and an EXR of the same render compare as equal
+// up to the PNG's rounding.
+//
+// The FLIP-like error follows the idea of NVIDIA's FLIP without its full viewing model: the
+// colour difference is measured with HyAB in CIELAB and differences in edges are weighted up
+// the way FLIP's feature term is.
+
+use exr::prelude::{read_first_rgba_layer_from_file, Vec2};
+use image::{Rgb, RgbImage};
+use std::io;
+use std::path::Path;
+
+const SSIM_SIGMA: f64 = 1.5;
+
+/// An image with its channels gamma corrected and between 0 and 1.
+#[derive(Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<[f64; 3]>,
+}
+
+impl Image {
+    /// `bytes` is tightly packed RGB, top row first, like [`crate::Canvas::to_u8_vec`].
+    pub fn from_rgb8(width: usize, height: usize, bytes: &[u8]) -> Image {
+        assert_eq!(bytes.len(), width * height * 3);
+
+        let pixels = bytes
+            .chunks(3)
+            .map(|pixel| {
+                [
+                    pixel[0] as f64 / 255.0,
+                    pixel[1] as f64 / 255.0,
+                    pixel[2] as f64 / 255.0,
+                ]
+            })
+            .collect();
+
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Loads an EXR file of linear radiance, or any other image as 8 bit RGB, depending on the
+    /// file extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let path = path.as_ref();
+        let is_exr = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"));
+
+        if !is_exr {
+            let image = image::open(path).map_err(io::Error::other)?.to_rgb8();
+            return Ok(Image::from_rgb8(
+                image.width() as usize,
+                image.height() as usize,
+                image.as_raw(),
+            ));
+        }
+
+        let image = read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| Image {
+                width: resolution.width(),
+                height: resolution.height(),
+                pixels: vec![[0.0; 3]; resolution.width() * resolution.height()],
+            },
+            |image: &mut Image, Vec2(x, y), (r, g, b, _): (f32, f32, f32, f32)| {
+                image.pixels[y * image.width + x] = to_gamma([r as f64, g as f64, b as f64]);
+            },
+        )
+        .map_err(io::Error::other)?;
+
+        Ok(image.layer_data.channel_data.pixels)
+    }
+
+    /// Back to tightly packed 8 bit RGB.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| pixel.iter().map(|channel| (channel * 255.0).round() as u8))
+            .collect()
+    }
+
+    /// Blurs the image in linear space with a gaussian of `sigma` pixels. Blurring both images
+    /// before comparing them averages away the sampling noise of a render, so two renders that
+    /// took different samples of the same scene compare as equal.
+    pub fn blurred(&self, sigma: f64) -> Image {
+        let mut channels = [Vec::new(), Vec::new(), Vec::new()];
+        for (channel, values) in channels.iter_mut().enumerate() {
+            let linear: Vec<f64> = self
+                .pixels
+                .iter()
+                .map(|pixel| pixel[channel] * pixel[channel])
+                .collect();
+            *values = gaussian_blur(&linear, self.width, self.height, sigma);
+        }
+
+        let pixels = (0..self.pixels.len())
+            .map(|index| to_gamma([channels[0][index], channels[1][index], channels[2][index]]))
+            .collect();
+
+        Image { pixels, ..*self }
+    }
+
+    fn pixel(&self, x: isize, y: isize) -> [f64; 3] {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+}
+
+pub struct Difference {
+    /// The mean squared error over every channel of every pixel.
+    pub mse: f64,
+    /// In decibels, infinite for identical images.
+    pub psnr: f64,
+    /// The mean structural similarity of the luma, 1 for identical images.
+    pub ssim: f64,
+    /// The mean of `flip_errors`, between 0 and 1.
+    pub flip: f64,
+    /// The FLIP-like error of every pixel, top row first.
+    pub flip_errors: Vec<f64>,
+}
+
+impl Difference {
+    pub fn rmse(&self) -> f64 {
+        self.mse.sqrt()
+    }
+}
+
+/// Panics if the images aren't the same size.
+pub fn compare(actual: &Image, expected: &Image) -> Difference {
+    assert_eq!(
+        (actual.width, actual.height),
+        (expected.width, expected.height),
+        "The images have different sizes"
+    );
+
+    let squared_error: f64 = actual
+        .pixels
+        .iter()
+        .zip(expected.pixels.iter())
+        .flat_map(|(a, b)| (0..3).map(move |channel| (a[channel] - b[channel]).powi(2)))
+        .sum();
+    let mse = squared_error / (actual.pixels.len() * 3) as f64;
+
+    let flip_errors = flip_errors(actual, expected);
+    let flip = flip_errors.iter().sum::<f64>() / flip_errors.len() as f64;
+
+    Difference {
+        mse,
+        psnr: psnr(mse),
+        ssim: ssim(actual, expected),
+        flip,
+        flip_errors,
+    }
+}
+
+fn psnr(mse: f64) -> f64 {
+    10.0 * (1.0 / mse).log10()
+}
+
+/// How far a render is from converging, estimated from its difference to a reference of the
+/// same scene rendered with more samples. The noise of a render falls with the number of
+/// samples, so the reference's own noise can be accounted for and the samples needed for a
+/// given quality extrapolated.
+pub struct Convergence {
+    /// The estimated mean squared error against the fully converged image.
+    pub mse: f64,
+    pub psnr: f64,
+    variance_per_sample: f64,
+}
+
+impl Convergence {
+    pub fn estimate(difference: &Difference, samples: u64, reference_samples: u64) -> Convergence {
+        let (samples, reference_samples) = (samples.max(1) as f64, reference_samples as f64);
+
+        // the difference holds the variance of both renders, sigma^2 / n + sigma^2 / m
+        let variance_per_sample =
+            difference.mse * samples * reference_samples / (samples + reference_samples).max(1.0);
+        let mse = variance_per_sample / samples;
+
+        Convergence {
+            mse,
+            psnr: psnr(mse),
+            variance_per_sample,
+        }
+    }
+
+    /// The samples per pixel a render would need to reach `psnr` decibels.
+    pub fn samples_for_psnr(&self, psnr: f64) -> f64 {
+        self.variance_per_sample / 10.0f64.powf(-psnr / 10.0)
+    }
+}
+
+// crayfish saves with a gamma of 2
+fn to_linear(pixel: [f64; 3]) -> [f64; 3] {
+    [
+        pixel[0] * pixel[0],
+        pixel[1] * pixel[1],
+        pixel[2] * pixel[2],
+    ]
+}
+
+fn to_gamma(pixel: [f64; 3]) -> [f64; 3] {
+    [
+        pixel[0].clamp(0.0, 1.0).sqrt(),
+        pixel[1].clamp(0.0, 1.0).sqrt(),
+        pixel[2].clamp(0.0, 1.0).sqrt(),
+    ]
+}
+
+fn gaussian_blur(values: &[f64], width: usize, height: usize, sigma: f64) -> Vec<f64> {
+    let radius = (sigma * 3.0).ceil() as isize;
+    let weights: Vec<f64> = (-radius..=radius)
+        .map(|offset| (-(offset * offset) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f64 = weights.iter().sum();
+
+    let at = |values: &[f64], x: isize, y: isize| {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        values[y * width + x]
+    };
+
+    let mut horizontal = Vec::with_capacity(values.len());
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let sum: f64 = (-radius..=radius)
+                .zip(weights.iter())
+                .map(|(offset, weight)| at(values, x + offset, y) * weight)
+                .sum();
+            horizontal.push(sum / total);
+        }
+    }
+
+    let mut blurred = Vec::with_capacity(values.len());
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let sum: f64 = (-radius..=radius)
+                .zip(weights.iter())
+                .map(|(offset, weight)| at(&horizontal, x, y + offset) * weight)
+                .sum();
+            blurred.push(sum / total);
+        }
+    }
+
+    blurred
+}
+
+// Wang et al. with a gaussian window, computed on the luma of the gamma corrected values
+fn ssim(actual: &Image, expected: &Image) -> f64 {
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+
+    let luma = |image: &Image| -> Vec<f64> {
+        image
+            .pixels
+            .iter()
+            .map(|pixel| 0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2])
+            .collect()
+    };
+    let (x, y) = (luma(actual), luma(expected));
+    let product =
+        |a: &[f64], b: &[f64]| -> Vec<f64> { a.iter().zip(b.iter()).map(|(a, b)| a * b).collect() };
+    let blur = |values: &[f64]| gaussian_blur(values, actual.width, actual.height, SSIM_SIGMA);
+
+    let (mean_x, mean_y) = (blur(&x), blur(&y));
+    let (mean_xx, mean_yy, mean_xy) = (
+        blur(&product(&x, &x)),
+        blur(&product(&y, &y)),
+        blur(&product(&x, &y)),
+    );
+
+    let total: f64 = (0..x.len())
+        .map(|i| {
+            let variance_x = mean_xx[i] - mean_x[i] * mean_x[i];
+            let variance_y = mean_yy[i] - mean_y[i] * mean_y[i];
+            let covariance = mean_xy[i] - mean_x[i] * mean_y[i];
+
+            ((2.0 * mean_x[i] * mean_y[i] + C1) * (2.0 * covariance + C2))
+                / ((mean_x[i] * mean_x[i] + mean_y[i] * mean_y[i] + C1)
+                    * (variance_x + variance_y + C2))
+        })
+        .sum();
+
+    total / x.len() as f64
+}
+
+fn flip_errors(actual: &Image, expected: &Image) -> Vec<f64> {
+    let max_distance = hyab(
+        &linear_to_lab([0.0, 1.0, 0.0]),
+        &linear_to_lab([0.0, 0.0, 1.0]),
+    );
+
+    let mut errors = Vec::with_capacity(actual.pixels.len());
+    for y in 0..actual.height as isize {
+        for x in 0..actual.width as isize {
+            let actual_lab = linear_to_lab(to_linear(actual.pixel(x, y)));
+            let expected_lab = linear_to_lab(to_linear(expected.pixel(x, y)));
+            let color_error = (hyab(&actual_lab, &expected_lab) / max_distance).min(1.0);
+
+            let feature_error =
+                ((edge_strength(actual, x, y) - edge_strength(expected, x, y)).abs()).min(1.0);
+
+            // edges that appear or disappear make a colour difference more visible
+            errors.push(color_error.powf(0.7).powf(1.0 - feature_error));
+        }
+    }
+
+    errors
+}
+
+fn linear_to_lab(rgb: [f64; 3]) -> [f64; 3] {
+    // linear sRGB to XYZ, relative to the D65 white point
+    let x = (0.4124 * rgb[0] + 0.3576 * rgb[1] + 0.1805 * rgb[2]) / 0.9505;
+    let y = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+    let z = (0.0193 * rgb[0] + 0.1192 * rgb[1] + 0.9505 * rgb[2]) / 1.0890;
+
+    let f = |t: f64| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn hyab(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    (a[0] - b[0]).abs() + ((a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+// the sobel gradient of the lightness, scaled so a black to white step is about 1
+fn edge_strength(image: &Image, x: isize, y: isize) -> f64 {
+    let lightness =
+        |dx: isize, dy: isize| linear_to_lab(to_linear(image.pixel(x + dx, y + dy)))[0] / 100.0;
+
+    let gradient_x = (lightness(1, -1) + 2.0 * lightness(1, 0) + lightness(1, 1))
+        - (lightness(-1, -1) + 2.0 * lightness(-1, 0) + lightness(-1, 1));
+    let gradient_y = (lightness(-1, 1) + 2.0 * lightness(0, 1) + lightness(1, 1))
+        - (lightness(-1, -1) + 2.0 * lightness(0, -1) + lightness(1, -1));
+
+    (gradient_x * gradient_x + gradient_y * gradient_y).sqrt() / 4.0
+}
+
+/// Shows a per pixel error between 0 and 1 in false colour, black where the images agree
+/// through red and yellow to white where they differ the most.
+pub fn heatmap(width: usize, height: usize, errors: &[f64]) -> RgbImage {
+    RgbImage::from_fn(width as u32, height as u32, |x, y| {
+        let error = errors[y as usize * width + x as usize].clamp(0.0, 1.0);
+        let channel = |start: f64| ((error * 3.0 - start).clamp(0.0, 1.0) * 255.0) as u8;
+
+        Rgb([channel(0.0), channel(1.0), channel(2.0)])
+    })
+}
@@ -0,0 +1,80 @@
+//! Crayfish is a small path tracer.
+//!
+//! Scenes can either be loaded from a scene file with [`scenefile::load`] and turned into a
+//! [`World`] with [`WorldBuilder::from_config`], or constructed directly in code:
+//!
+//! ```
+//! use crayfish::material::Lambertian;
+//! use crayfish::shapes::Sphere;
+//! use crayfish::{Camera, Color3, Point3, Vector3, World, WorldRenderRequest};
+//! use std::sync::Arc;
+//!
+//! let camera = Camera::new(
+//!     Point3::new(0.0, 0.0, 3.0),
+//!     Point3::new(0.0, 0.0, 0.0),
+//!     Vector3::new(0.0, 1.0, 0.0),
+//!     1.0,
+//!     40.0,
+//!     3.0,
+//!     0.0,
+//! );
+//!
+//! let mut world = World::new(camera);
+//! world.add_shape(Sphere {
+//!     center: Point3::new(0.0, 0.0, 0.0),
+//!     radius: 1.0,
+//!     material: Arc::new(Lambertian {
+//!         diffuse: Color3::new(0.8, 0.3, 0.3),
+//!     }),
+//! });
+//!
+//! let canvas = world.render(WorldRenderRequest::new(4, 8, 1, 16, 16));
+//! assert_eq!(canvas.to_u8_vec().len(), 16 * 16 * 3);
+//! ```
+//!
+//! The resulting [`Canvas`] can be written to disk with [`Canvas::save`].
+//!
+//! New kinds of shapes and materials are added by implementing [`Intersectable`] and
+//! [`Scatterer`], registering them with a [`WorldBuilder`] makes them usable from scene files.
+
+pub mod aabb;
+pub mod animation;
+pub mod camera;
+pub mod checkpoint;
+pub mod configuration;
+pub mod defs;
+pub mod display;
+pub mod distributed;
+pub mod gltfimport;
+pub mod imagediff;
+pub mod material;
+pub mod math;
+pub mod mesh;
+#[cfg(feature = "packet")]
+pub mod packet;
+pub mod pbrt;
+pub mod ply;
+pub mod random;
+pub mod records;
+pub mod scene;
+pub mod scenefile;
+pub mod sceneformat;
+pub mod service;
+pub mod shapes;
+pub mod simd;
+pub mod stats;
+pub mod stl;
+pub mod texture;
+pub mod volume;
+pub mod worldbuilder;
+
+pub use camera::{Camera, CameraModel};
+pub use configuration::Configuration;
+pub use defs::Real;
+pub use display::{Accumulator, Canvas};
+pub use material::Scatterer;
+pub use math::{Color3, Point3, Ray, Vector3};
+pub use scene::{Background, CancellationToken, RenderProgress, World, WorldRenderRequest};
+pub use shapes::Intersectable;
+pub use stats::RenderStats;
+pub use worldbuilder::WorldBuilder;
@@ -1,28 +1,34 @@
-mod camera;
-mod configuration;
-mod defs;
-mod display;
-mod material;
-mod math;
-mod records;
-mod scene;
-mod shapes;
-mod worldbuilder;
-
-use camera::Camera;
-use configuration::Configuration;
-use defs::Real;
-use material::Material;
-use math::{Color3, Point3, Vector3};
+use crayfish::animation::{config_at_frame, frame_output_path};
+use crayfish::checkpoint::Checkpoint;
+use crayfish::configuration::{self, FrameRange};
+use crayfish::distributed::{self, Coordinator};
+use crayfish::gltfimport;
+use crayfish::imagediff::{self, Convergence, Image};
+use crayfish::material::{Dielectric, Lambertian, Metal};
+use crayfish::math::Matrix4;
+use crayfish::pbrt;
+use crayfish::scenefile;
+use crayfish::service::RenderService;
+use crayfish::shapes::Sphere;
+use crayfish::{
+    Camera, CancellationToken, Canvas, Color3, Configuration, Point3, Real, RenderProgress,
+    RenderStats, Vector3, World, WorldBuilder, WorldRenderRequest,
+};
+use log::{info, warn, LevelFilter, Log, Metadata, Record};
+#[cfg(feature = "window")]
 use minifb::{Key, Window, WindowOptions};
 use rand::Rng;
-use scene::{World, WorldRenderRequest};
-use shapes::Shape;
-use worldbuilder::WorldBuilder;
-
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use std::{fs::File, io::BufReader};
 
+#[allow(dead_code)]
 fn random_scene() -> World {
     let camera = Camera::new(
         Vector3::new(13.0, 2.0, 3.0),
@@ -36,10 +42,10 @@ fn random_scene() -> World {
 
     let mut world = World::new(camera);
 
-    let ground_material = Material::Lambertian {
+    let ground_material = Arc::new(Lambertian {
         diffuse: Color3::new(0.5, 0.5, 0.5),
-    };
-    let ground = Shape::Sphere {
+    });
+    let ground = Sphere {
         center: Vector3::new(0.0, -1000.0, 0.0),
         radius: 1000.0,
         material: ground_material,
@@ -60,8 +66,8 @@ fn random_scene() -> World {
             if (center - Point3::new(4.0, 0.2, 0.0)).magnitude() > 0.9 {
                 if rand_material_choice < 0.8 {
                     let diffuse = Color3::new_random(0.0, 1.0) * Color3::new_random(0.0, 1.0);
-                    let material = Material::Lambertian { diffuse };
-                    let sphere = Shape::Sphere {
+                    let material = Arc::new(Lambertian { diffuse });
+                    let sphere = Sphere {
                         center,
                         radius: 0.2,
                         material,
@@ -71,8 +77,8 @@ fn random_scene() -> World {
                 } else if rand_material_choice < 0.95 {
                     let diffuse = Color3::new_random(0.5, 1.0);
                     let fuzz = rng.gen_range(0.0..0.5);
-                    let material = Material::Metal { diffuse, fuzz };
-                    let sphere = Shape::Sphere {
+                    let material = Arc::new(Metal { diffuse, fuzz });
+                    let sphere = Sphere {
                         center,
                         radius: 0.2,
                         material,
@@ -80,10 +86,10 @@ fn random_scene() -> World {
 
                     world.add_shape(sphere);
                 } else {
-                    let material = Material::Dielectric {
+                    let material = Arc::new(Dielectric {
                         refraction_index: 1.5,
-                    };
-                    let sphere = Shape::Sphere {
+                    });
+                    let sphere = Sphere {
                         center,
                         radius: 0.2,
                         material,
@@ -95,31 +101,31 @@ fn random_scene() -> World {
         }
     }
 
-    let material1 = Material::Dielectric {
+    let material1 = Arc::new(Dielectric {
         refraction_index: 1.5,
-    };
-    let sphere1 = Shape::Sphere {
+    });
+    let sphere1 = Sphere {
         center: Point3::new(0.0, 1.0, 0.0),
         radius: 1.0,
         material: material1,
     };
     world.add_shape(sphere1);
 
-    let material2 = Material::Lambertian {
+    let material2 = Arc::new(Lambertian {
         diffuse: Color3::new(0.4, 0.2, 0.1),
-    };
-    let sphere2 = Shape::Sphere {
+    });
+    let sphere2 = Sphere {
         center: Point3::new(-4.0, 1.0, 0.0),
         radius: 1.0,
         material: material2,
     };
     world.add_shape(sphere2);
 
-    let material3 = Material::Metal {
+    let material3 = Arc::new(Metal {
         diffuse: Color3::new(0.7, 0.6, 0.5),
         fuzz: 0.0,
-    };
-    let sphere3 = Shape::Sphere {
+    });
+    let sphere3 = Sphere {
         center: Point3::new(4.0, 1.0, 0.0),
         radius: 1.0,
         material: material3,
@@ -129,93 +135,520 @@ fn random_scene() -> World {
     world
 }
 
+#[allow(dead_code)]
 fn ray_tracing_in_one_weekend_scene() {
     let mut now = Instant::now();
     let world = random_scene();
-    println!("World completed. Took {}ms", now.elapsed().as_millis());
+    info!("World completed. Took {}ms", now.elapsed().as_millis());
 
     now = Instant::now();
-    println!("Rendering scene");
+    info!("Rendering scene");
     let width = 200;
     let aspect_ratio = 16.0 / 9.0;
     let height = (width as Real / aspect_ratio) as usize;
     let canvas = world.render(WorldRenderRequest::new(100, 50, 1, width, height));
-    println!("Scene rendered. Took {}ms", now.elapsed().as_millis());
+    info!("Scene rendered. Took {}ms", now.elapsed().as_millis());
 
-    now = Instant::now();
-    println!("Constructing window and buffer");
-    let mut window = Window::new(
-        "Crayfish Render",
-        width,
-        height,
-        WindowOptions {
-            resize: true,
-            ..WindowOptions::default()
-        },
-    )
-    .expect("Unable to open Window");
+    save_canvas(
+        &canvas,
+        "C:\\Users\\User\\Pictures\\crayfish_renders\\output.png",
+    );
+    show_canvas(&canvas);
+}
 
-    window.limit_update_rate(Some(Duration::from_millis(16)));
-    window.topmost(true);
+fn render_from_config(path: &str, resume: bool) {
+    let cancellation = cancel_on_ctrl_c();
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("pbrt") => {
+            if resume {
+                warn!("Checkpoints aren't supported for PBRT scenes, ignoring --resume");
+            }
+            return render_pbrt(path, &cancellation);
+        }
+        Some("gltf") | Some("glb") => {
+            if resume {
+                warn!("Checkpoints aren't supported for glTF assets, ignoring --resume");
+            }
+            return render_gltf(path, &cancellation);
+        }
+        _ => {}
+    }
 
-    let window_buffer = canvas.to_u32_vec();
-    window
-        .update_with_buffer(&window_buffer, width, height)
+    let config = scenefile::load(path);
+
+    match &config.frames {
+        Some(frames) => {
+            if resume || config.checkpoint_interval_seconds.is_some() {
+                warn!("Checkpoints are only supported for still images, ignoring them");
+            }
+            render_animation(&config, frames, &cancellation)
+        }
+        None => render_still(&config, &cancellation, resume),
+    }
+}
+
+// renders the scene with workers started elsewhere with `crayfish work <address>`
+fn coordinate(address: &str, path: &str) {
+    let config = scenefile::load(path);
+    let now = Instant::now();
+
+    let coordinator = Coordinator::bind(address, config.clone()).unwrap();
+    let canvas = coordinator.run().unwrap();
+    info!("Scene rendered. Took {}ms", now.elapsed().as_millis());
+
+    save_canvas(&canvas, &config.output_path);
+}
+
+// answers the http api until ctrl+c, flags are `--concurrency <n>`, `--queue-limit <n>` and
+// `--assets <directory>`
+fn serve(args: &[String]) {
+    let mut address = "127.0.0.1:8080";
+    let mut concurrency = None;
+    let mut queue_limit = None;
+    let mut assets = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut number = || {
+            args.next()
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or_else(|| panic!("{} needs a number", arg))
+        };
+        match arg.as_str() {
+            "--concurrency" => concurrency = Some(number()),
+            "--queue-limit" => queue_limit = Some(number()),
+            "--assets" => {
+                assets = Some(args.next().expect("--assets needs a directory").clone());
+            }
+            _ => address = arg,
+        }
+    }
+
+    let mut service = RenderService::bind(address)
+        .unwrap_or_else(|error| panic!("Unable to listen on {}: {}", address, error))
+        .with_shutdown(cancel_on_ctrl_c());
+    if let Some(concurrency) = concurrency {
+        service = service.with_concurrency(concurrency);
+    }
+    if let Some(queue_limit) = queue_limit {
+        service = service.with_queue_limit(queue_limit);
+    }
+    if let Some(assets) = assets {
+        service = service.with_asset_directory(assets);
+    }
+
+    service.run().unwrap();
+}
+
+// compares two images and writes a heatmap of where they differ, with the sample counts of
+// both it also estimates how far the first is from converging
+fn diff_images(args: &[String]) {
+    const DEFAULT_TARGET_PSNR: f64 = 40.0;
+
+    let mut paths = Vec::new();
+    let mut heatmap_path = None;
+    let mut samples = None;
+    let mut reference_samples = None;
+    let mut target_psnr = DEFAULT_TARGET_PSNR;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("{} needs a value", arg))
+                .as_str()
+        };
+        let number = |value: &str| {
+            value
+                .parse::<f64>()
+                .unwrap_or_else(|_| panic!("{} needs a number", arg))
+        };
+        match arg.as_str() {
+            "--heatmap" => heatmap_path = Some(value().to_string()),
+            "--samples" => samples = Some(number(value()) as u64),
+            "--reference-samples" => reference_samples = Some(number(value()) as u64),
+            "--target-psnr" => target_psnr = number(value()),
+            _ => paths.push(arg.as_str()),
+        }
+    }
+
+    if paths.len() != 2 {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let load = |path: &str| {
+        Image::load(path).unwrap_or_else(|error| panic!("Unable to load {}: {}", path, error))
+    };
+    let (image, reference) = (load(paths[0]), load(paths[1]));
+    if (image.width, image.height) != (reference.width, reference.height) {
+        panic!(
+            "{} is {}x{} but {} is {}x{}",
+            paths[0], image.width, image.height, paths[1], reference.width, reference.height
+        );
+    }
+
+    let difference = imagediff::compare(&image, &reference);
+    println!("MSE   {:.6}", difference.mse);
+    println!("PSNR  {:.2} dB", difference.psnr);
+    println!("SSIM  {:.4}", difference.ssim);
+    println!("FLIP  {:.4}", difference.flip);
+
+    if let (Some(samples), Some(reference_samples)) = (samples, reference_samples) {
+        let convergence = Convergence::estimate(&difference, samples, reference_samples);
+        println!(
+            "Against the converged image about MSE {:.6}, PSNR {:.2} dB",
+            convergence.mse, convergence.psnr
+        );
+        println!(
+            "About {:.0} samples per pixel would reach {} dB",
+            convergence.samples_for_psnr(target_psnr).ceil(),
+            target_psnr
+        );
+    }
+
+    let heatmap_path = heatmap_path.unwrap_or_else(|| {
+        Path::new(paths[0])
+            .with_extension("diff.png")
+            .to_string_lossy()
+            .into_owned()
+    });
+    imagediff::heatmap(image.width, image.height, &difference.flip_errors)
+        .save(&heatmap_path)
         .unwrap();
+    info!("Heatmap saved to {}", heatmap_path);
+}
 
-    println!(
-        "Window and buffer constructed. Took {}ms",
-        now.elapsed().as_millis()
+// rewrites a scene file in the format given by the output's extension
+fn convert_scene(input: &str, output: &str) {
+    scenefile::convert(Path::new(input), Path::new(output))
+        .unwrap_or_else(|e| panic!("Unable to convert {}: {}", input, e));
+    info!("Converted {} to {}", input, output);
+}
+
+// prints the JSON Schema of scene files, or writes it to a file when given a path
+fn write_schema(args: &[String]) {
+    let schema = serde_json::to_string_pretty(&configuration::json_schema()).unwrap();
+    match args.first() {
+        Some(path) => {
+            fs::write(path, schema).unwrap_or_else(|e| panic!("Unable to write {}: {}", path, e))
+        }
+        None => println!("{}", schema),
+    }
+}
+
+fn checkpoint_path(config: &Configuration) -> PathBuf {
+    Path::new(&config.output_path).with_extension("checkpoint")
+}
+
+// adds the samples of every input checkpoint together and saves the result as a checkpoint
+// and as an image next to it
+fn merge_checkpoints(output: &str, inputs: &[String]) {
+    let mut merged: Option<Checkpoint> = None;
+
+    for input in inputs {
+        let checkpoint = Checkpoint::load(input)
+            .unwrap_or_else(|error| panic!("Unable to load checkpoint {}: {}", input, error));
+        info!("Merging {}", input);
+
+        match merged.as_mut() {
+            Some(merged) => merged
+                .merge(&checkpoint)
+                .unwrap_or_else(|error| panic!("Unable to merge {}: {}", input, error)),
+            None => merged = Some(checkpoint),
+        }
+    }
+
+    let merged = merged.expect("No checkpoints to merge");
+    merged.save(output).unwrap();
+    info!("Merged checkpoint saved to {}", output);
+
+    let image_path = Path::new(output).with_extension("png");
+    save_canvas(
+        &merged.accumulator.to_canvas(),
+        image_path.to_str().unwrap(),
     );
+}
 
-    now = Instant::now();
-    println!("Saving as image");
-    image::save_buffer(
-        "C:\\Users\\User\\Pictures\\crayfish_renders\\output.png",
-        &canvas.to_u8_vec(),
-        width as u32,
-        height as u32,
-        image::ColorType::Rgb8,
+// the first ctrl+c stops the render so the image so far can be saved, a second one exits
+// straight away
+fn cancel_on_ctrl_c() -> CancellationToken {
+    let cancellation = CancellationToken::new();
+    let handler_cancellation = cancellation.clone();
+
+    ctrlc::set_handler(move || {
+        if handler_cancellation.is_cancelled() {
+            process::exit(130);
+        }
+        handler_cancellation.cancel();
+    })
+    .expect("Unable to set the Ctrl+C handler");
+
+    cancellation
+}
+
+fn render_request(
+    config: &Configuration,
+    width: usize,
+    height: usize,
+    cancellation: &CancellationToken,
+) -> WorldRenderRequest {
+    let request = WorldRenderRequest::new(
+        config.samples_per_pixel,
+        config.ray_max_depth,
+        config.ray_step,
+        width,
+        height,
     )
-    .unwrap();
-    println!("Image saved. Took {}ms", now.elapsed().as_millis());
+    .with_progress(progress_bar())
+    .with_cancellation(cancellation.clone())
+    .with_scene_hash(scenefile::scene_hash(config));
+    let request = match config.seed {
+        Some(seed) => request.with_seed(seed),
+        None => request,
+    };
 
-    println!("Opening window");
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        window.update();
+    match config.time_budget_seconds {
+        Some(seconds) => request.with_time_budget(Duration::from_secs_f64(seconds)),
+        None => request,
     }
 }
 
-fn render_from_config() {
+fn render_still(config: &Configuration, cancellation: &CancellationToken, resume: bool) {
     let mut now = Instant::now();
-    println!("Constructing world from config");
-    let config_file = File::open("scene_config.json").unwrap();
-    let reader = BufReader::new(config_file);
-    let config: Configuration = serde_json::from_reader(reader).unwrap();
+    info!("Constructing world from config");
 
     let width = config.width as usize;
     let height = (width as Real / config.aspect_ratio) as usize;
 
-    let world = WorldBuilder::from_config(&config);
-    println!("World completed. Took {}ms", now.elapsed().as_millis());
+    let world = WorldBuilder::from_config(config);
+    let build_time = now.elapsed();
+    info!("World completed. Took {}ms", build_time.as_millis());
+
+    let mut request = render_request(config, width, height, cancellation);
+    if let Some(seconds) = config.checkpoint_interval_seconds {
+        request =
+            request.with_checkpoint(checkpoint_path(config), Duration::from_secs_f64(seconds));
+    }
+    if resume {
+        let path = checkpoint_path(config);
+        let checkpoint = Checkpoint::load(&path).unwrap_or_else(|error| {
+            panic!("Unable to load checkpoint {}: {}", path.display(), error)
+        });
+        request = request.with_resume(checkpoint);
+    }
+
+    let (state, mut stats) = world.render_to_checkpoint(request).unwrap_or_else(|error| {
+        let path = checkpoint_path(config);
+        panic!("Unable to resume from {}: {}", path.display(), error)
+    });
+    let canvas = state.accumulator.to_canvas();
+    if stats.cancelled {
+        eprintln!();
+        warn!("Render cancelled, saving the image so far");
+    }
+    info!(
+        "Scene rendered. Took {}ms",
+        stats.phases[0].milliseconds as u128
+    );
+    stats.add_phase("build", build_time);
 
     now = Instant::now();
-    println!("Rendering scene");
-    let canvas = world.render(WorldRenderRequest::new(
-        config.samples_per_pixel,
-        config.ray_max_depth,
-        config.ray_step,
-        width,
+    save_canvas(&canvas, &config.output_path);
+    stats.add_phase("save", now.elapsed());
+
+    report_stats(config, &stats, &config.output_path);
+    if !stats.cancelled {
+        show_canvas(&canvas);
+    }
+}
+
+// renders a PBRT scene with the film, sampler and integrator settings it asks for, the
+// importer has already warned about everything it left out
+fn render_pbrt(path: &str, cancellation: &CancellationToken) {
+    let now = Instant::now();
+    info!("Importing PBRT scene");
+    let scene =
+        pbrt::load(path).unwrap_or_else(|error| panic!("Unable to import {}: {}", path, error));
+    info!(
+        "Scene imported with {} warnings. Took {}ms",
+        scene.warnings.len(),
+        now.elapsed().as_millis()
+    );
+
+    let request = WorldRenderRequest::new(
+        scene.samples_per_pixel,
+        scene.ray_max_depth,
+        1,
+        scene.width,
+        scene.height,
+    )
+    .with_progress(progress_bar())
+    .with_cancellation(cancellation.clone());
+
+    let (canvas, stats) = scene.world.render_with_stats(request);
+    if stats.cancelled {
+        eprintln!();
+        warn!("Render cancelled, saving the image so far");
+    }
+    info!("{}", stats.to_string().trim_end());
+
+    save_canvas(&canvas, &scene.output_path);
+    if !stats.cancelled {
+        show_canvas(&canvas);
+    }
+}
+
+// what a glTF asset rendered on its own is rendered with, glTF has no render settings
+const GLTF_WIDTH: usize = 640;
+const GLTF_ASPECT_RATIO: Real = 16.0 / 9.0;
+const GLTF_SAMPLES_PER_PIXEL: i64 = 64;
+const GLTF_RAY_MAX_DEPTH: i64 = 16;
+
+// renders a glTF asset through its own camera, or one looking at the whole asset if it has
+// none, and saves the image next to it as a png
+fn render_gltf(path: &str, cancellation: &CancellationToken) {
+    let now = Instant::now();
+    info!("Importing glTF asset");
+    let asset = gltfimport::load(path, &Matrix4::identity())
+        .unwrap_or_else(|error| panic!("Unable to import {}: {}", path, error));
+    info!(
+        "Asset imported with {} warnings. Took {}ms",
+        asset.warnings.len(),
+        now.elapsed().as_millis()
+    );
+
+    let aspect_ratio = asset.aspect_ratio().unwrap_or(GLTF_ASPECT_RATIO);
+    let height = (GLTF_WIDTH as Real / aspect_ratio) as usize;
+    let world = asset.into_world(aspect_ratio);
+
+    let request = WorldRenderRequest::new(
+        GLTF_SAMPLES_PER_PIXEL,
+        GLTF_RAY_MAX_DEPTH,
+        1,
+        GLTF_WIDTH,
         height,
-    ));
-    println!("Scene rendered. Took {}ms", now.elapsed().as_millis());
+    )
+    .with_progress(progress_bar())
+    .with_cancellation(cancellation.clone());
 
-    now = Instant::now();
-    println!("Constructing window and buffer");
+    let (canvas, stats) = world.render_with_stats(request);
+    if stats.cancelled {
+        eprintln!();
+        warn!("Render cancelled, saving the image so far");
+    }
+    info!("{}", stats.to_string().trim_end());
+
+    let output_path = Path::new(path).with_extension("png");
+    save_canvas(&canvas, output_path.to_str().unwrap());
+    if !stats.cancelled {
+        show_canvas(&canvas);
+    }
+}
+
+fn render_animation(config: &Configuration, frames: &FrameRange, cancellation: &CancellationToken) {
+    let width = config.width as usize;
+    let height = (width as Real / config.aspect_ratio) as usize;
+
+    for frame in frames.start..=frames.end {
+        let now = Instant::now();
+        info!("Rendering frame {} of {}", frame, frames.end);
+
+        let build_start = Instant::now();
+        let world = WorldBuilder::from_config(&config_at_frame(config, frame));
+        let build_time = build_start.elapsed();
+
+        let (canvas, mut stats) =
+            world.render_with_stats(render_request(config, width, height, cancellation));
+        if stats.cancelled {
+            eprintln!();
+            warn!("Render cancelled, saving frame {} so far", frame);
+        }
+        stats.add_phase("build", build_time);
+
+        let output_path = frame_output_path(&config.output_path, frame);
+        let save_start = Instant::now();
+        save_canvas(&canvas, &output_path);
+        stats.add_phase("save", save_start.elapsed());
+
+        info!(
+            "Frame {} saved to {}. Took {}ms",
+            frame,
+            output_path,
+            now.elapsed().as_millis()
+        );
+        report_stats(config, &stats, &output_path);
+
+        if stats.cancelled {
+            break;
+        }
+    }
+}
+
+// prints the summary and, if the config asks for it, writes it as json next to the image
+fn report_stats(config: &Configuration, stats: &RenderStats, output_path: &str) {
+    info!("{}", stats.to_string().trim_end());
+
+    if config.write_stats.unwrap_or(false) {
+        let stats_path = Path::new(output_path).with_extension("stats.json");
+        fs::write(&stats_path, stats.to_json()).unwrap();
+        info!("Statistics saved to {}", stats_path.display());
+    }
+}
+
+// draws a single line bar on stderr, only redrawn when the percentage changes so logs that
+// capture every carriage return stay short
+fn progress_bar() -> impl Fn(&RenderProgress) + Send + Sync {
+    const WIDTH: usize = 40;
+    let last_percent = AtomicUsize::new(usize::MAX);
+
+    move |progress: &RenderProgress| {
+        let percent = (progress.fraction() * 100.0) as usize;
+        if last_percent.swap(percent, Ordering::Relaxed) == percent {
+            return;
+        }
+
+        let filled = (progress.fraction() * WIDTH as f64) as usize;
+        let eta = match progress.estimated_remaining() {
+            Some(remaining) => format!("ETA {}s", remaining.as_secs()),
+            None => String::from("ETA --"),
+        };
+
+        let mut stderr = io::stderr();
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {:3}% {:<12}",
+            "#".repeat(filled),
+            " ".repeat(WIDTH - filled),
+            percent,
+            eta
+        );
+        if percent == 100 {
+            let _ = writeln!(stderr);
+        }
+        let _ = stderr.flush();
+    }
+}
+
+fn save_canvas(canvas: &Canvas, path: &str) {
+    let now = Instant::now();
+    info!("Saving as image");
+    canvas.save(path).unwrap();
+    info!("Image saved. Took {}ms", now.elapsed().as_millis());
+}
+
+#[cfg(feature = "window")]
+fn show_canvas(canvas: &Canvas) {
+    let now = Instant::now();
+    info!("Constructing window and buffer");
     let mut window = Window::new(
         "Crayfish Render",
-        width,
-        height,
+        canvas.width,
+        canvas.height,
         WindowOptions {
             resize: true,
             ..WindowOptions::default()
@@ -228,34 +661,97 @@ fn render_from_config() {
 
     let window_buffer = canvas.to_u32_vec();
     window
-        .update_with_buffer(&window_buffer, width, height)
+        .update_with_buffer(&window_buffer, canvas.width, canvas.height)
         .unwrap();
 
-    println!(
+    info!(
         "Window and buffer constructed. Took {}ms",
         now.elapsed().as_millis()
     );
 
-    now = Instant::now();
-    println!("Saving as image");
-
-    image::save_buffer(
-        "C:\\Users\\User\\Pictures\\crayfish_renders\\output.png",
-        &canvas.to_u8_vec(),
-        width as u32,
-        height as u32,
-        image::ColorType::Rgb8,
-    )
-    .unwrap();
-    println!("Image saved. Took {}ms", now.elapsed().as_millis());
-
-    println!("Opening window");
+    info!("Opening window");
     while window.is_open() && !window.is_key_down(Key::Escape) {
         window.update();
     }
 }
 
+#[cfg(not(feature = "window"))]
+fn show_canvas(_canvas: &Canvas) {}
+
+// log lines go to stderr, the level comes from CRAYFISH_LOG and defaults to info
+struct CliLogger;
+
+impl Log for CliLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{:<5}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CliLogger = CliLogger;
+
+fn init_logger() {
+    let level = env::var("CRAYFISH_LOG")
+        .ok()
+        .and_then(|level| LevelFilter::from_str(&level).ok())
+        .unwrap_or(LevelFilter::Info);
+
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(level);
+}
+
+const USAGE: &str = "Usage:
+    crayfish [--resume] [scene file]
+    crayfish merge <output checkpoint> <checkpoint>...
+    crayfish coordinate <address> [scene file]
+    crayfish work <coordinator address>
+    crayfish serve [address] [--concurrency <n>] [--queue-limit <n>] [--assets <directory>]
+    crayfish diff <image> <reference> [--heatmap <path>]
+                  [--samples <n> --reference-samples <n> [--target-psnr <dB>]]
+    crayfish convert <scene file> <output scene file>
+    crayfish schema [output file]
+
+Scene files can be JSON, TOML, YAML (.yaml, .yml) or RON, picked by extension. PBRT scenes
+(.pbrt) are imported as far as crayfish supports them and rendered with their own settings.
+glTF assets (.gltf, .glb) are rendered through their own camera into a png next to them.";
+
 fn main() {
+    init_logger();
     // ray_tracing_in_one_weekend_scene();
-    render_from_config();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("merge") if args.len() >= 3 => merge_checkpoints(&args[1], &args[2..]),
+        Some("coordinate") if args.len() >= 2 => coordinate(
+            &args[1],
+            args.get(2)
+                .map(String::as_str)
+                .unwrap_or("scene_config.json"),
+        ),
+        Some("work") if args.len() >= 2 => distributed::run_worker(&args[1]).unwrap(),
+        Some("serve") => serve(&args[1..]),
+        Some("diff") => diff_images(&args[1..]),
+        Some("convert") if args.len() >= 3 => convert_scene(&args[1], &args[2]),
+        Some("schema") => write_schema(&args[1..]),
+        Some("merge") | Some("coordinate") | Some("work") | Some("convert") | Some("--help")
+        | Some("-h") => {
+            println!("{}", USAGE)
+        }
+        _ => {
+            let resume = args.iter().any(|arg| arg == "--resume");
+            let scene = args
+                .iter()
+                .find(|arg| !arg.starts_with("--"))
+                .map(String::as_str)
+                .unwrap_or("scene_config.json");
+            render_from_config(scene, resume);
+        }
+    }
 }
@@ -1,17 +1,23 @@
-use crate::defs::Real;
-use crate::math::{Color3, Ray, Vector3};
+use crate::defs::{consts, Real};
+use crate::math::{offset_ray_origin, Color3, Ray, Vector3};
+use crate::random;
 use crate::records::IntersectionRecord;
+use crate::texture::ImageTexture;
 
 use rand::Rng;
+use std::sync::Arc;
 
-pub enum Material {
-    Lambertian { diffuse: Color3 },
-    Metal { diffuse: Color3, fuzz: Real },
-    Dielectric { refraction_index: Real },
-}
-
-pub trait Scatterer {
+/// Decides how light arriving at an intersection continues. Implement this to add a material
+/// of your own.
+pub trait Scatterer: Send + Sync {
+    /// Returns the scattered ray and how much it's attenuated, or `None` if the ray is absorbed.
     fn scatter(&self, ray: &Ray, intersection: &IntersectionRecord) -> Option<MaterialInteraction>;
+
+    /// Light given off by the surface towards where the ray came from, most materials don't
+    /// emit any.
+    fn emitted(&self, _ray: &Ray, _intersection: &IntersectionRecord) -> Color3 {
+        Color3::default()
+    }
 }
 
 pub struct MaterialInteraction {
@@ -19,24 +25,186 @@ pub struct MaterialInteraction {
     pub scattered_ray: Ray,
 }
 
-impl Scatterer for Material {
+pub struct Lambertian {
+    pub diffuse: Color3,
+}
+
+pub struct Metal {
+    pub diffuse: Color3,
+    pub fuzz: Real,
+}
+
+pub struct Dielectric {
+    pub refraction_index: Real,
+}
+
+pub struct Isotropic {
+    pub albedo: Color3,
+}
+
+pub struct HenyeyGreenstein {
+    pub albedo: Color3,
+    pub anisotropy: Real,
+}
+
+/// Emits `emission` from the front face of a surface, or both faces if `two_sided`, and
+/// absorbs everything that hits it.
+pub struct DiffuseLight {
+    pub emission: Color3,
+    pub two_sided: bool,
+}
+
+/// The metallic-roughness model of glTF. Metals reflect in their base colour, everything
+/// else is diffuse in its base colour under a thin clear coat, and transmissive surfaces
+/// refract like glass. Textures are multiplied with their factors at the hit's uv, the
+/// metallic-roughness texture has roughness in green and metalness in blue.
+pub struct MetallicRoughness {
+    pub base_color: Color3,
+    pub base_color_texture: Option<Arc<ImageTexture>>,
+    pub metallic: Real,
+    pub roughness: Real,
+    pub metallic_roughness_texture: Option<Arc<ImageTexture>>,
+    pub emission: Color3,
+    pub emission_texture: Option<Arc<ImageTexture>>,
+    pub transmission: Real,
+    pub refraction_index: Real,
+    /// Whether the back faces emit too.
+    pub double_sided: bool,
+}
+
+impl Default for MetallicRoughness {
+    // the defaults glTF gives a material that leaves everything out
+    fn default() -> Self {
+        MetallicRoughness {
+            base_color: Color3::new(1.0, 1.0, 1.0),
+            base_color_texture: None,
+            metallic: 1.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            emission: Color3::default(),
+            emission_texture: None,
+            transmission: 0.0,
+            refraction_index: 1.5,
+            double_sided: false,
+        }
+    }
+}
+
+impl Scatterer for Lambertian {
+    fn scatter(&self, ray: &Ray, intersection: &IntersectionRecord) -> Option<MaterialInteraction> {
+        lambertian(&(self.diffuse * intersection.color), ray, intersection)
+    }
+}
+
+impl Scatterer for Metal {
+    fn scatter(&self, ray: &Ray, intersection: &IntersectionRecord) -> Option<MaterialInteraction> {
+        metal(&self.diffuse, self.fuzz, ray, intersection)
+    }
+}
+
+impl Scatterer for Dielectric {
+    fn scatter(&self, ray: &Ray, intersection: &IntersectionRecord) -> Option<MaterialInteraction> {
+        dielectric(self.refraction_index, ray, intersection)
+    }
+}
+
+impl Scatterer for Isotropic {
+    fn scatter(&self, ray: &Ray, intersection: &IntersectionRecord) -> Option<MaterialInteraction> {
+        isotropic(&self.albedo, ray, intersection)
+    }
+}
+
+impl Scatterer for HenyeyGreenstein {
+    fn scatter(&self, ray: &Ray, intersection: &IntersectionRecord) -> Option<MaterialInteraction> {
+        henyey_greenstein(&self.albedo, self.anisotropy, ray, intersection)
+    }
+}
+
+impl Scatterer for DiffuseLight {
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        _intersection: &IntersectionRecord,
+    ) -> Option<MaterialInteraction> {
+        None
+    }
+
+    fn emitted(&self, _ray: &Ray, intersection: &IntersectionRecord) -> Color3 {
+        if intersection.front_face || self.two_sided {
+            self.emission
+        } else {
+            Color3::default()
+        }
+    }
+}
+
+impl Scatterer for MetallicRoughness {
     fn scatter(&self, ray: &Ray, intersection: &IntersectionRecord) -> Option<MaterialInteraction> {
-        match self {
-            Material::Lambertian { diffuse } => lambertian(diffuse, ray, intersection),
-            Material::Metal { diffuse, fuzz } => metal(diffuse, *fuzz, ray, intersection),
-            Material::Dielectric { refraction_index } => {
-                dielectric(*refraction_index, ray, intersection)
-            }
+        let texture = |texture: &Option<Arc<ImageTexture>>| match texture {
+            Some(texture) => texture.sample(intersection.uv),
+            None => Color3::new(1.0, 1.0, 1.0),
+        };
+        let base_color = self.base_color * texture(&self.base_color_texture) * intersection.color;
+        let metallic_roughness = texture(&self.metallic_roughness_texture);
+        let metallic = self.metallic * metallic_roughness[2];
+        let roughness = self.roughness * metallic_roughness[1];
+
+        // each lobe is picked as often as it contributes, so none need weighting
+        let mut rng = random::rng();
+        if rng.gen_range(0.0..1.0) < metallic {
+            return metal(&base_color, roughness, ray, intersection);
+        }
+        if rng.gen_range(0.0..1.0) < self.transmission {
+            return dielectric(self.refraction_index, ray, intersection).map(|interaction| {
+                MaterialInteraction {
+                    attenuation: base_color,
+                    ..interaction
+                }
+            });
+        }
+
+        let cos_theta = Real::min(-ray.direction.as_normal().dot(&intersection.normal), 1.0);
+        let coat = reflectance(cos_theta, 0.0, 1.0 / 1.5);
+        if rng.gen_range(0.0..1.0) < coat {
+            return metal(&Color3::new(1.0, 1.0, 1.0), roughness, ray, intersection);
+        }
+
+        lambertian(&base_color, ray, intersection)
+    }
+
+    fn emitted(&self, _ray: &Ray, intersection: &IntersectionRecord) -> Color3 {
+        if !intersection.front_face && !self.double_sided {
+            return Color3::default();
+        }
+
+        match &self.emission_texture {
+            Some(texture) => self.emission * texture.sample(intersection.uv),
+            None => self.emission,
         }
     }
 }
 
+// starts a ray on the side of the surface it's heading to, refracted rays go below the normal.
+// the point is first moved past the shape's own error bound, the offset then covers the
+// rounding of that and of the point itself
+fn surface_ray(intersection: &IntersectionRecord, direction: Vector3) -> Ray {
+    let normal = if direction.dot(&intersection.normal) < 0.0 {
+        -intersection.normal
+    } else {
+        intersection.normal
+    };
+
+    let point = intersection.point + normal * intersection.error_bound;
+    Ray::new(offset_ray_origin(&point, &normal), direction)
+}
+
 fn lambertian(
     diffuse: &Color3,
     _ray: &Ray,
     intersection: &IntersectionRecord,
 ) -> Option<MaterialInteraction> {
-    let mut scatter_direction = intersection.normal + Vector3::random_in_unit_sphere();
+    // a point on the unit sphere touching the surface gives directions distributed by cosine
+    let mut scatter_direction = intersection.normal + Vector3::random_unit_vector();
 
     if scatter_direction.is_near_zero() {
         scatter_direction = intersection.normal;
@@ -44,7 +212,7 @@ fn lambertian(
 
     Some(MaterialInteraction {
         attenuation: *diffuse,
-        scattered_ray: Ray::new(intersection.point, scatter_direction),
+        scattered_ray: surface_ray(intersection, scatter_direction),
     })
 }
 
@@ -55,16 +223,15 @@ fn metal(
     intersection: &IntersectionRecord,
 ) -> Option<MaterialInteraction> {
     let reflected = ray.direction.as_normal().reflect(&intersection.normal);
+    let scattered = reflected + (Vector3::random_in_unit_sphere() * fuzz);
 
-    if reflected.dot(&intersection.normal) <= 0.0 {
+    // fuzz that would send the ray into the surface absorbs it instead
+    if scattered.dot(&intersection.normal) <= 0.0 {
         return None;
     }
 
     Some(MaterialInteraction {
-        scattered_ray: Ray::new(
-            intersection.point,
-            reflected + (Vector3::random_in_unit_sphere() * fuzz),
-        ),
+        scattered_ray: surface_ray(intersection, scattered),
         attenuation: *diffuse,
     })
 }
@@ -75,7 +242,7 @@ fn dielectric(
     intersection: &IntersectionRecord,
 ) -> Option<MaterialInteraction> {
     let refraction_ratio = match intersection.front_face {
-        true => (1.0 / refraction_index),
+        true => 1.0 / refraction_index,
         false => refraction_index,
     };
 
@@ -85,22 +252,85 @@ fn dielectric(
 
     let cannot_refract = refraction_ratio * sin_theta > 1.0;
 
-    let new_direction;
-    let mut rng = rand::thread_rng();
+    let mut rng = random::rng();
 
-    if cannot_refract || {
-        let mut r0 = (1.0 - refraction_ratio) / (1.0 + refraction_ratio);
-        r0 = r0 * r0;
-        r0 + (1.0 - r0) * (1.0 - cos_theta).powf(5.0)
-    } > rng.gen_range(0.0..1.0)
+    let new_direction = if cannot_refract
+        || reflectance(cos_theta, sin_theta, refraction_ratio) > rng.gen_range(0.0..1.0)
     {
-        new_direction = direction.reflect(&intersection.normal);
+        direction.reflect(&intersection.normal)
     } else {
-        new_direction = direction.refract(&intersection.normal, refraction_ratio);
-    }
+        direction.refract(&intersection.normal, refraction_ratio)
+    };
 
     Some(MaterialInteraction {
         attenuation: Color3::new(1.0, 1.0, 1.0),
-        scattered_ray: Ray::new(intersection.point, new_direction),
+        scattered_ray: surface_ray(intersection, new_direction),
+    })
+}
+
+// schlick's approximation, taken with the cosine on the side with the lower refraction index
+// so light leaving the material is reflected as often as light entering it the same way
+fn reflectance(cos_theta: Real, sin_theta: Real, refraction_ratio: Real) -> Real {
+    let cos_outside = if refraction_ratio > 1.0 {
+        let sin_outside = refraction_ratio * sin_theta;
+        (1.0 - sin_outside * sin_outside).sqrt()
+    } else {
+        cos_theta
+    };
+
+    let mut r0 = (1.0 - refraction_ratio) / (1.0 + refraction_ratio);
+    r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cos_outside).powf(5.0)
+}
+
+fn isotropic(
+    albedo: &Color3,
+    _ray: &Ray,
+    intersection: &IntersectionRecord,
+) -> Option<MaterialInteraction> {
+    Some(MaterialInteraction {
+        attenuation: *albedo,
+        scattered_ray: Ray::new(intersection.point, Vector3::random_unit_vector()),
+    })
+}
+
+fn henyey_greenstein(
+    albedo: &Color3,
+    anisotropy: Real,
+    ray: &Ray,
+    intersection: &IntersectionRecord,
+) -> Option<MaterialInteraction> {
+    let mut rng = random::rng();
+    let xi: Real = rng.gen_range(0.0..1.0);
+
+    // sample the cosine of the angle between the incoming and scattered direction,
+    // positive anisotropy favours forward scattering, negative favours back scattering
+    let cos_theta = if anisotropy.abs() < 0.001 {
+        1.0 - 2.0 * xi
+    } else {
+        let g = anisotropy;
+        let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        (1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)
+    };
+    let cos_theta = Real::max(-1.0, Real::min(cos_theta, 1.0));
+    let sin_theta = Real::max(0.0, 1.0 - cos_theta * cos_theta).sqrt();
+    let phi: Real = 2.0 * consts::PI * rng.gen_range(0.0..1.0);
+
+    let forward = ray.direction.as_normal();
+    let helper = if forward[0].abs() > 0.9 {
+        Vector3::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    };
+    let tangent = forward.cross(&helper).as_normal();
+    let bitangent = forward.cross(&tangent);
+
+    let scattered_direction = (tangent * (sin_theta * phi.cos()))
+        + (bitangent * (sin_theta * phi.sin()))
+        + (forward * cos_theta);
+
+    Some(MaterialInteraction {
+        attenuation: *albedo,
+        scattered_ray: Ray::new(intersection.point, scattered_direction),
     })
 }
@@ -1,26 +1,29 @@
 use crate::defs::Real;
+use crate::random;
+use crate::simd::Real4;
 
-use rand::{self, Rng};
+use rand::Rng;
 use std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Sub};
+// the fourth lane is padding so operations can be done with simd, it's never read
 #[derive(Copy, Clone)]
 pub struct Vector3 {
-    data: [Real; 3],
+    data: Real4,
 }
 
 impl Vector3 {
     pub fn new(x: Real, y: Real, z: Real) -> Vector3 {
-        Vector3 { data: [x, y, z] }
+        Vector3 {
+            data: Real4::new(x, y, z, 0.0),
+        }
     }
 
     pub fn new_random(min: Real, max: Real) -> Vector3 {
-        let mut rng = rand::thread_rng();
-        Vector3 {
-            data: [
-                rng.gen_range(min..=max),
-                rng.gen_range(min..=max),
-                rng.gen_range(min..=max),
-            ],
-        }
+        let mut rng = random::rng();
+        Self::new(
+            rng.gen_range(min..=max),
+            rng.gen_range(min..=max),
+            rng.gen_range(min..=max),
+        )
     }
 
     pub fn random_in_unit_sphere() -> Vector3 {
@@ -33,10 +36,14 @@ impl Vector3 {
         }
     }
 
+    pub fn random_unit_vector() -> Vector3 {
+        Self::random_in_unit_sphere().as_normal()
+    }
+
     pub fn random_in_unit_disk() -> Vector3 {
         loop {
             let mut p = Self::new_random(-1.0, 1.0);
-            p.data[2] = 0.0;
+            p.data.0[2] = 0.0;
 
             if p.magnitude_squared() >= 1.0 {
                 continue;
@@ -46,20 +53,16 @@ impl Vector3 {
         }
     }
 
-    pub fn default() -> Vector3 {
-        Self::new(0.0, 0.0, 0.0)
-    }
-
     pub fn magnitude(&self) -> Real {
         Real::sqrt(self.magnitude_squared())
     }
 
     pub fn magnitude_squared(&self) -> Real {
-        self[0] * self[0] + self[1] * self[1] + self[2] * self[2]
+        (self.data * self.data).sum3()
     }
 
     pub fn dot(&self, other: &Vector3) -> Real {
-        self[0] * other[0] + self[1] * other[1] + self[2] * other[2]
+        (self.data * other.data).sum3()
     }
 
     pub fn as_normal(&self) -> Vector3 {
@@ -67,13 +70,11 @@ impl Vector3 {
     }
 
     pub fn cross(&self, other: &Vector3) -> Vector3 {
-        Vector3 {
-            data: [
-                self[1] * other[2] - self[2] * other[1],
-                self[2] * other[0] - self[0] * other[2],
-                self[0] * other[1] - self[1] * other[0],
-            ],
-        }
+        Self::new(
+            self[1] * other[2] - self[2] * other[1],
+            self[2] * other[0] - self[0] * other[2],
+            self[0] * other[1] - self[1] * other[0],
+        )
     }
 
     pub fn is_near_zero(&self) -> bool {
@@ -87,7 +88,7 @@ impl Vector3 {
     }
 
     pub fn refract(&self, normal: &Vector3, refraction_index: Real) -> Vector3 {
-        let cos_theta = Real::min(-self.dot(&normal), 1.0);
+        let cos_theta = Real::min(-self.dot(normal), 1.0);
 
         let perpendicular_component = (*self + (*normal * cos_theta)) * refraction_index;
         let parallel_component =
@@ -97,12 +98,18 @@ impl Vector3 {
     }
 }
 
+impl Default for Vector3 {
+    fn default() -> Vector3 {
+        Self::new(0.0, 0.0, 0.0)
+    }
+}
+
 impl Neg for Vector3 {
     type Output = Vector3;
 
     fn neg(self) -> Vector3 {
         Vector3 {
-            data: [-self[0], -self[1], -self[2]],
+            data: Real4::default() - self.data,
         }
     }
 }
@@ -111,7 +118,7 @@ impl Index<usize> for Vector3 {
     type Output = Real;
 
     fn index(&self, i: usize) -> &Real {
-        &self.data[i]
+        &self.data.0[i]
     }
 }
 
@@ -120,20 +127,14 @@ impl Add for Vector3 {
 
     fn add(self, other: Vector3) -> Vector3 {
         Vector3 {
-            data: [
-                self[0] + other.data[0],
-                self[1] + other.data[1],
-                self[2] + other.data[2],
-            ],
+            data: self.data + other.data,
         }
     }
 }
 
 impl AddAssign for Vector3 {
     fn add_assign(&mut self, other: Vector3) {
-        self.data[0] += other.data[0];
-        self.data[1] += other.data[1];
-        self.data[2] += other.data[2];
+        self.data = self.data + other.data;
     }
 }
 
@@ -142,11 +143,7 @@ impl Sub for Vector3 {
 
     fn sub(self, other: Vector3) -> Vector3 {
         Vector3 {
-            data: [
-                self[0] - other.data[0],
-                self[1] - other.data[1],
-                self[2] - other.data[2],
-            ],
+            data: self.data - other.data,
         }
     }
 }
@@ -156,20 +153,14 @@ impl Sub<Vector3> for &Vector3 {
 
     fn sub(self, other: Vector3) -> Vector3 {
         Vector3 {
-            data: [
-                self[0] - other.data[0],
-                self[1] - other.data[1],
-                self[2] - other.data[2],
-            ],
+            data: self.data - other.data,
         }
     }
 }
 
 impl MulAssign for Vector3 {
     fn mul_assign(&mut self, other: Vector3) {
-        self.data[0] *= other.data[0];
-        self.data[1] *= other.data[1];
-        self.data[2] *= other.data[2];
+        self.data = self.data * other.data;
     }
 }
 
@@ -178,7 +169,7 @@ impl Mul for Vector3 {
 
     fn mul(self, other: Vector3) -> Vector3 {
         Vector3 {
-            data: [self[0] * other[0], self[1] * other[1], self[2] * other[2]],
+            data: self.data * other.data,
         }
     }
 }
@@ -188,7 +179,7 @@ impl Mul<Real> for Vector3 {
 
     fn mul(self, other: Real) -> Vector3 {
         Vector3 {
-            data: [self[0] * other, self[1] * other, self[2] * other],
+            data: self.data * Real4::splat(other),
         }
     }
 }
@@ -199,7 +190,7 @@ impl Div<Real> for Vector3 {
     fn div(self, other: Real) -> Vector3 {
         let inv = 1.0 / other;
         Vector3 {
-            data: [self[0] * inv, self[1] * inv, self[2] * inv],
+            data: self.data * Real4::splat(inv),
         }
     }
 }
@@ -210,22 +201,59 @@ impl Div<Real> for &Vector3 {
     fn div(self, other: Real) -> Vector3 {
         let inv = 1.0 / other;
         Vector3 {
-            data: [self[0] * inv, self[1] * inv, self[2] * inv],
+            data: self.data * Real4::splat(inv),
         }
     }
 }
 
 impl DivAssign for Vector3 {
     fn div_assign(&mut self, other: Vector3) {
-        self.data[0] /= other.data[0];
-        self.data[1] /= other.data[1];
-        self.data[2] /= other.data[2];
+        self.data = self.data / other.data;
     }
 }
 
 pub type Point3 = Vector3;
 pub type Color3 = Vector3;
 
+// "A Fast and Robust Method for Avoiding Self-Intersection", Wächter and Binder.
+// the point is pushed along the normal by a fixed number of ulps so the offset scales with
+// the magnitude of the coordinates, close to the origin where ulps get tiny a fixed distance
+// is used instead. The paper's constants are for f32: the ulps scale with the precision by
+// themselves, the fixed distance is written in machine epsilons (1/65536 for f32) so in f64
+// it shrinks with the rounding errors too
+const OFFSET_ORIGIN: Real = 1.0 / 32.0;
+const OFFSET_FLOAT_SCALE: Real = 128.0 * Real::EPSILON;
+const OFFSET_INT_SCALE: Real = 256.0;
+
+/// Moves a point on a surface off it along `normal`, far enough that a ray starting there
+/// won't intersect the surface it left. `normal` must point to the side the ray leaves to.
+pub fn offset_ray_origin(point: &Point3, normal: &Vector3) -> Point3 {
+    let offset = |p: Real, n: Real| {
+        if p.abs() < OFFSET_ORIGIN {
+            return p + OFFSET_FLOAT_SCALE * n;
+        }
+
+        let ulps = (OFFSET_INT_SCALE * n) as i64;
+        add_ulps(p, if p < 0.0 { -ulps } else { ulps })
+    };
+
+    Point3::new(
+        offset(point[0], normal[0]),
+        offset(point[1], normal[1]),
+        offset(point[2], normal[2]),
+    )
+}
+
+#[cfg(not(feature = "f64"))]
+fn add_ulps(value: Real, ulps: i64) -> Real {
+    Real::from_bits((value.to_bits() as i32).wrapping_add(ulps as i32) as u32)
+}
+
+#[cfg(feature = "f64")]
+fn add_ulps(value: Real, ulps: i64) -> Real {
+    Real::from_bits((value.to_bits() as i64).wrapping_add(ulps) as u64)
+}
+
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vector3,
@@ -240,3 +268,204 @@ impl Ray {
         self.origin + (self.direction * t)
     }
 }
+
+/// A 4x4 matrix for affine transforms, stored row major and applied to column vectors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub rows: [[Real; 4]; 4],
+}
+
+impl Default for Matrix4 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Matrix4 {
+    pub fn identity() -> Matrix4 {
+        Matrix4::scale(1.0, 1.0, 1.0)
+    }
+
+    pub fn translate(x: Real, y: Real, z: Real) -> Matrix4 {
+        Matrix4 {
+            rows: [
+                [1.0, 0.0, 0.0, x],
+                [0.0, 1.0, 0.0, y],
+                [0.0, 0.0, 1.0, z],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn scale(x: Real, y: Real, z: Real) -> Matrix4 {
+        Matrix4 {
+            rows: [
+                [x, 0.0, 0.0, 0.0],
+                [0.0, y, 0.0, 0.0],
+                [0.0, 0.0, z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    /// Rotates counter clockwise by `angle_deg` around `axis` when looking down the axis.
+    pub fn rotate(angle_deg: Real, axis: &Vector3) -> Matrix4 {
+        let a = axis.as_normal();
+        let (sin, cos) = angle_deg.to_radians().sin_cos();
+        let mut rows = Matrix4::identity().rows;
+
+        for (i, row) in rows.iter_mut().take(3).enumerate() {
+            for (j, value) in row.iter_mut().take(3).enumerate() {
+                let k = 3 - i - j;
+                let cross = match (i, j) {
+                    _ if i == j => 0.0,
+                    (0, 1) | (1, 2) | (2, 0) => -a[k],
+                    _ => a[k],
+                };
+                let identity = if i == j { 1.0 } else { 0.0 };
+                *value = a[i] * a[j] * (1.0 - cos) + identity * cos + cross * sin;
+            }
+        }
+
+        Matrix4 { rows }
+    }
+
+    /// The quaternion (x, y, z, w) as a rotation, it doesn't need to be normalized.
+    pub fn from_quaternion(x: Real, y: Real, z: Real, w: Real) -> Matrix4 {
+        let length = (x * x + y * y + z * z + w * w).sqrt();
+        let (x, y, z, w) = (x / length, y / length, z / length, w / length);
+
+        Matrix4 {
+            rows: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - z * w),
+                    2.0 * (x * z + y * w),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + z * w),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - x * w),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - y * w),
+                    2.0 * (y * z + x * w),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    /// Reads 16 values that list the matrix column by column.
+    pub fn from_columns(values: &[Real]) -> Matrix4 {
+        Matrix4::from_rows(values).transpose()
+    }
+
+    /// Reads 16 values that list the matrix row by row.
+    pub fn from_rows(values: &[Real]) -> Matrix4 {
+        let mut rows = [[0.0; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            row.copy_from_slice(&values[i * 4..i * 4 + 4]);
+        }
+
+        Matrix4 { rows }
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut rows = [[0.0; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.rows[j][i];
+            }
+        }
+
+        Matrix4 { rows }
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, `None` for a singular matrix.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut left = self.rows;
+        let mut right = Matrix4::identity().rows;
+
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&a, &b| left[a][column].abs().total_cmp(&left[b][column].abs()))
+                .unwrap();
+            if left[pivot][column] == 0.0 {
+                return None;
+            }
+            left.swap(column, pivot);
+            right.swap(column, pivot);
+
+            let scale = 1.0 / left[column][column];
+            for j in 0..4 {
+                left[column][j] *= scale;
+                right[column][j] *= scale;
+            }
+
+            for row in 0..4 {
+                let factor = left[row][column];
+                if row == column || factor == 0.0 {
+                    continue;
+                }
+                for j in 0..4 {
+                    left[row][j] -= factor * left[column][j];
+                    right[row][j] -= factor * right[column][j];
+                }
+            }
+        }
+
+        Some(Matrix4 { rows: right })
+    }
+
+    pub fn transform_point(&self, point: &Point3) -> Point3 {
+        let m = &self.rows;
+        let row = |i: usize| m[i][0] * point[0] + m[i][1] * point[1] + m[i][2] * point[2] + m[i][3];
+        let w = row(3);
+
+        Point3::new(row(0) / w, row(1) / w, row(2) / w)
+    }
+
+    pub fn transform_vector(&self, vector: &Vector3) -> Vector3 {
+        let m = &self.rows;
+        let row = |i: usize| m[i][0] * vector[0] + m[i][1] * vector[1] + m[i][2] * vector[2];
+
+        Vector3::new(row(0), row(1), row(2))
+    }
+
+    /// Normals are transformed by the inverse transpose so they stay perpendicular to the
+    /// surface, the result isn't normalized.
+    pub fn transform_normal(&self, normal: &Vector3) -> Vector3 {
+        let inverse = self.inverse().unwrap_or_default();
+        inverse.transpose().transform_vector(normal)
+    }
+
+    /// Whether the transform mirrors, turning a right handed coordinate system left handed.
+    pub fn swaps_handedness(&self) -> bool {
+        let m = &self.rows;
+        let determinant = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+
+        determinant < 0.0
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut rows = [[0.0; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.rows[i][k] * other.rows[k][j]).sum();
+            }
+        }
+
+        Matrix4 { rows }
+    }
+}
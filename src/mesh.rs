@@ -0,0 +1,394 @@
+// Triangle meshes. A Mesh is the plain data loaders produce, a TriangleMesh gives it a material
+// and a bounding volume hierarchy over its triangles so it can be rendered.
+
+use crate::aabb::Aabb;
+use crate::defs::Real;
+use crate::material::Scatterer;
+use crate::math::{Color3, Matrix4, Point3, Ray, Vector3};
+use crate::records::IntersectionRecord;
+use crate::shapes::Intersectable;
+use crate::stats::{self, Counter};
+
+use std::sync::Arc;
+
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+/// Vertices and the triangles between them. `normals`, `uvs` and `colors` are either empty or
+/// have an entry for every position.
+#[derive(Clone, Default)]
+pub struct Mesh {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<[Real; 2]>,
+    /// Linear colours that tint the material's base colour.
+    pub colors: Vec<Color3>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    pub fn transformed(&self, transform: &Matrix4) -> Mesh {
+        Mesh {
+            positions: self
+                .positions
+                .iter()
+                .map(|position| transform.transform_point(position))
+                .collect(),
+            normals: self
+                .normals
+                .iter()
+                .map(|normal| transform.transform_normal(normal).as_normal())
+                .collect(),
+            uvs: self.uvs.clone(),
+            colors: self.colors.clone(),
+            triangles: self.triangles.clone(),
+        }
+    }
+
+    /// Replaces the normals with ones averaged from the triangles around each vertex, weighted
+    /// by their area, so the mesh is shaded smooth across its edges.
+    pub fn compute_smooth_normals(&mut self) {
+        let mut normals = vec![Vector3::default(); self.positions.len()];
+        for &[a, b, c] in self.triangles.iter() {
+            let positions = &self.positions;
+            // the cross product is twice the area long
+            let normal = (positions[b] - positions[a]).cross(&(positions[c] - positions[a]));
+            normals[a] += normal;
+            normals[b] += normal;
+            normals[c] += normal;
+        }
+
+        self.normals = normals
+            .into_iter()
+            .map(|normal| {
+                if normal.is_near_zero() {
+                    normal
+                } else {
+                    normal.as_normal()
+                }
+            })
+            .collect();
+    }
+
+    /// Turns every vertex into a small octahedron, so point clouds without faces can be
+    /// rendered. Colours are kept, normals and texture coordinates aren't.
+    pub fn points_as_octahedra(&self, radius: Real) -> Mesh {
+        const CORNERS: [[Real; 3]; 6] = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        // counter clockwise seen from outside
+        const FACES: [[usize; 3]; 8] = [
+            [0, 2, 4],
+            [2, 1, 4],
+            [1, 3, 4],
+            [3, 0, 4],
+            [2, 0, 5],
+            [1, 2, 5],
+            [3, 1, 5],
+            [0, 3, 5],
+        ];
+
+        let mut mesh = Mesh::default();
+        for (point, position) in self.positions.iter().enumerate() {
+            let first = mesh.positions.len();
+            for corner in CORNERS.iter() {
+                let offset = Vector3::new(corner[0], corner[1], corner[2]) * radius;
+                mesh.positions.push(*position + offset);
+                if !self.colors.is_empty() {
+                    mesh.colors.push(self.colors[point]);
+                }
+            }
+            for face in FACES.iter() {
+                mesh.triangles
+                    .push([first + face[0], first + face[1], first + face[2]]);
+            }
+        }
+
+        mesh
+    }
+
+    /// Reverses the order of every triangle's vertices, which turns its front face around.
+    pub fn flip_winding(&mut self) {
+        for triangle in self.triangles.iter_mut() {
+            triangle.swap(1, 2);
+        }
+    }
+
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.positions
+            .iter()
+            .map(|position| Aabb::new(*position, *position))
+            .reduce(|a, b| a.surrounding(&b))
+    }
+}
+
+/// A mesh that can be rendered. The front face of a triangle is the one its vertices go round
+/// counter clockwise on, unless the mesh has normals, then it's the side they're on.
+pub struct TriangleMesh {
+    mesh: Mesh,
+    material: Arc<dyn Scatterer>,
+    nodes: Vec<Node>,
+    // triangles in the order the leaves refer to them
+    order: Vec<usize>,
+}
+
+struct Node {
+    bounds: Aabb,
+    // leaves cover order[first..first + count], inner nodes have their first child next to
+    // them and the second at first
+    first: usize,
+    count: usize,
+}
+
+struct Hit {
+    t: Real,
+    triangle: usize,
+    barycentric: [Real; 3],
+}
+
+impl TriangleMesh {
+    /// Panics if a triangle refers to a vertex that isn't there.
+    pub fn new(mesh: Mesh, material: Arc<dyn Scatterer>) -> TriangleMesh {
+        let vertex_count = mesh.positions.len();
+        assert!(
+            mesh.triangles.iter().flatten().all(|&i| i < vertex_count),
+            "Triangle refers to a vertex the mesh doesn't have"
+        );
+
+        let mut triangle_mesh = TriangleMesh {
+            order: (0..mesh.triangles.len()).collect(),
+            mesh,
+            material,
+            nodes: Vec::new(),
+        };
+        if !triangle_mesh.order.is_empty() {
+            triangle_mesh.build(0, triangle_mesh.order.len());
+        }
+
+        triangle_mesh
+    }
+
+    pub fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+
+    fn triangle_bounds(&self, triangle: usize) -> Aabb {
+        let [a, b, c] = self.mesh.triangles[triangle];
+        let positions = &self.mesh.positions;
+
+        Aabb::new(positions[a], positions[a])
+            .surrounding(&Aabb::new(positions[b], positions[b]))
+            .surrounding(&Aabb::new(positions[c], positions[c]))
+    }
+
+    fn centroid(&self, triangle: usize) -> Point3 {
+        let [a, b, c] = self.mesh.triangles[triangle];
+        let positions = &self.mesh.positions;
+
+        (positions[a] + positions[b] + positions[c]) / 3.0
+    }
+
+    // splits at the median of the triangle centroids along the axis they're most spread along
+    fn build(&mut self, first: usize, count: usize) -> usize {
+        let bounds = self.order[first..first + count]
+            .iter()
+            .map(|&triangle| self.triangle_bounds(triangle))
+            .reduce(|a, b| a.surrounding(&b))
+            .unwrap();
+
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: padded(bounds),
+            first,
+            count,
+        });
+        if count <= MAX_LEAF_TRIANGLES {
+            return index;
+        }
+
+        let centroids = self.order[first..first + count]
+            .iter()
+            .map(|&triangle| {
+                let centroid = self.centroid(triangle);
+                Aabb::new(centroid, centroid)
+            })
+            .reduce(|a, b| a.surrounding(&b))
+            .unwrap();
+        let extent = centroids.max - centroids.min;
+        let axis = if extent[0] > extent[1] && extent[0] > extent[2] {
+            0
+        } else if extent[1] > extent[2] {
+            1
+        } else {
+            2
+        };
+
+        let half = count / 2;
+        let mut order = std::mem::take(&mut self.order);
+        order[first..first + count].select_nth_unstable_by(half, |&a, &b| {
+            self.centroid(a)[axis].total_cmp(&self.centroid(b)[axis])
+        });
+        self.order = order;
+
+        self.build(first, half);
+        let second = self.build(first + half, count - half);
+        self.nodes[index].first = second;
+        self.nodes[index].count = 0;
+
+        index
+    }
+
+    fn hit_triangle(&self, triangle: usize, ray: &Ray, t_min: Real, t_max: Real) -> Option<Hit> {
+        let [a, b, c] = self.mesh.triangles[triangle];
+        let positions = &self.mesh.positions;
+        let (p0, p1, p2) = (positions[a], positions[b], positions[c]);
+
+        // "Fast, Minimum Storage Ray/Triangle Intersection", Möller and Trumbore
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let p = ray.direction.cross(&edge2);
+        let determinant = edge1.dot(&p);
+        if determinant == 0.0 || !determinant.is_finite() {
+            return None;
+        }
+
+        let inverse_determinant = 1.0 / determinant;
+        let to_origin = ray.origin - p0;
+        let u = to_origin.dot(&p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = to_origin.cross(&edge1);
+        let v = ray.direction.dot(&q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(&q) * inverse_determinant;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        Some(Hit {
+            t,
+            triangle,
+            barycentric: [1.0 - u - v, u, v],
+        })
+    }
+
+    fn record(&self, ray: &Ray, hit: Hit) -> IntersectionRecord<'_> {
+        let [a, b, c] = self.mesh.triangles[hit.triangle];
+        let [b0, b1, b2] = hit.barycentric;
+        let positions = &self.mesh.positions;
+
+        // the point from the barycentric coordinates is on the triangle up to rounding,
+        // "Physically Based Rendering" 3.9.6 bounds that rounding
+        let point = positions[a] * b0 + positions[b] * b1 + positions[c] * b2;
+        let magnitude = |p: &Point3| p[0].abs().max(p[1].abs()).max(p[2].abs());
+        let error_bound = gamma(7)
+            * (b0.abs() * magnitude(&positions[a])
+                + b1.abs() * magnitude(&positions[b])
+                + b2.abs() * magnitude(&positions[c]));
+
+        let mut geometric_normal = (positions[b] - positions[a])
+            .cross(&(positions[c] - positions[a]))
+            .as_normal();
+        let shading_normal = if self.mesh.normals.is_empty() {
+            geometric_normal
+        } else {
+            let normals = &self.mesh.normals;
+            let interpolated = normals[a] * b0 + normals[b] * b1 + normals[c] * b2;
+            if interpolated.is_near_zero() {
+                geometric_normal
+            } else {
+                let interpolated = interpolated.as_normal();
+                if interpolated.dot(&geometric_normal) < 0.0 {
+                    geometric_normal = -geometric_normal;
+                }
+                interpolated
+            }
+        };
+
+        let front_face = ray.direction.dot(&geometric_normal) < 0.0;
+        let normal = if front_face {
+            shading_normal
+        } else {
+            -shading_normal
+        };
+
+        let mut record =
+            IntersectionRecord::new(point, normal, hit.t, front_face, self.material.as_ref())
+                .with_error_bound(error_bound);
+        if !self.mesh.uvs.is_empty() {
+            let uvs = &self.mesh.uvs;
+            record = record.with_uv([
+                uvs[a][0] * b0 + uvs[b][0] * b1 + uvs[c][0] * b2,
+                uvs[a][1] * b0 + uvs[b][1] * b1 + uvs[c][1] * b2,
+            ]);
+        }
+        if !self.mesh.colors.is_empty() {
+            let colors = &self.mesh.colors;
+            record = record.with_color(colors[a] * b0 + colors[b] * b1 + colors[c] * b2);
+        }
+
+        record
+    }
+}
+
+impl Intersectable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord<'_>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut closest: Option<Hit> = None;
+        let mut closest_t = t_max;
+        let mut stack = vec![0];
+        let (mut nodes_visited, mut triangles_tested) = (0, 0);
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            nodes_visited += 1;
+            if !node.bounds.hit(ray, t_min, closest_t) {
+                continue;
+            }
+
+            if node.count == 0 {
+                stack.push(node.first);
+                stack.push(index + 1);
+                continue;
+            }
+
+            triangles_tested += node.count as u64;
+            for &triangle in self.order[node.first..node.first + node.count].iter() {
+                if let Some(hit) = self.hit_triangle(triangle, ray, t_min, closest_t) {
+                    closest_t = hit.t;
+                    closest = Some(hit);
+                }
+            }
+        }
+
+        stats::record(Counter::BvhNodesVisited, nodes_visited);
+        stats::record(Counter::IntersectionTests, triangles_tested);
+        closest.map(|hit| self.record(ray, hit))
+    }
+}
+
+// a flat triangle along an axis has a box without thickness, which rays can't enter
+fn padded(bounds: Aabb) -> Aabb {
+    let extent = bounds.max - bounds.min;
+    let padding = 1e-4 * extent[0].max(extent[1]).max(extent[2]).max(Real::EPSILON);
+    let padding = Vector3::new(padding, padding, padding);
+
+    Aabb::new(bounds.min - padding, bounds.max + padding)
+}
+
+fn gamma(n: i32) -> Real {
+    let epsilon = Real::EPSILON * 0.5;
+    (n as Real * epsilon) / (1.0 - n as Real * epsilon)
+}
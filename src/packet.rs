@@ -0,0 +1,124 @@
+// Intersection of four rays at a time, stored as one simd lane per ray.
+
+use crate::aabb::Aabb;
+use crate::defs::Real;
+use crate::math::{Point3, Ray, Vector3};
+use crate::simd::Real4;
+
+/// Four rays stored component wise, e.g. `origin[0]` holds the x coordinate of every origin.
+pub struct RayPacket4 {
+    pub origin: [Real4; 3],
+    pub direction: [Real4; 3],
+    inverse_direction: [Real4; 3],
+}
+
+impl RayPacket4 {
+    pub fn new(rays: &[Ray; 4]) -> RayPacket4 {
+        let component = |axis: usize, value: &dyn Fn(&Ray) -> Vector3| {
+            Real4::new(
+                value(&rays[0])[axis],
+                value(&rays[1])[axis],
+                value(&rays[2])[axis],
+                value(&rays[3])[axis],
+            )
+        };
+
+        let origin = [
+            component(0, &|r| r.origin),
+            component(1, &|r| r.origin),
+            component(2, &|r| r.origin),
+        ];
+        let direction = [
+            component(0, &|r| r.direction),
+            component(1, &|r| r.direction),
+            component(2, &|r| r.direction),
+        ];
+        let one = Real4::splat(1.0);
+
+        RayPacket4 {
+            origin,
+            direction,
+            inverse_direction: [one / direction[0], one / direction[1], one / direction[2]],
+        }
+    }
+
+    pub fn ray(&self, lane: usize) -> Ray {
+        Ray::new(
+            Point3::new(
+                self.origin[0].0[lane],
+                self.origin[1].0[lane],
+                self.origin[2].0[lane],
+            ),
+            Vector3::new(
+                self.direction[0].0[lane],
+                self.direction[1].0[lane],
+                self.direction[2].0[lane],
+            ),
+        )
+    }
+}
+
+/// Returns the closest `t` in [`t_min`, `t_max`] for each ray, or infinity where it missed.
+pub fn hit_sphere4(
+    packet: &RayPacket4,
+    center: &Point3,
+    radius: Real,
+    t_min: Real,
+    t_max: Real,
+) -> Real4 {
+    let oc = [
+        packet.origin[0] - Real4::splat(center[0]),
+        packet.origin[1] - Real4::splat(center[1]),
+        packet.origin[2] - Real4::splat(center[2]),
+    ];
+    let d = &packet.direction;
+
+    let a = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+    let half_b = oc[0] * d[0] + oc[1] * d[1] + oc[2] * d[2];
+    let c = oc[0] * oc[0] + oc[1] * oc[1] + oc[2] * oc[2] - Real4::splat(radius * radius);
+
+    // same precision improvements as the single ray test, see hit_sphere in shapes.rs
+    let closest = [
+        oc[0] - d[0] * (half_b / a),
+        oc[1] - d[1] * (half_b / a),
+        oc[2] - d[2] * (half_b / a),
+    ];
+    let closest_squared =
+        closest[0] * closest[0] + closest[1] * closest[1] + closest[2] * closest[2];
+    let discriminant = a * (Real4::splat(radius * radius) - closest_squared);
+    let disc_sqrt = discriminant.max(Real4::default()).sqrt();
+
+    let zero = Real4::default();
+    let q = zero - (half_b + disc_sqrt.copysign(half_b));
+    let first = c / q;
+    let second = q / a;
+    let (near, far) = (first.min(second), first.max(second));
+
+    // the same choice as the single ray test makes, in every lane at once
+    let (t_min, t_max) = (Real4::splat(t_min), Real4::splat(t_max));
+    let near_in_range = t_min.less_equal(near) & near.less_equal(t_max);
+    let far_in_range = t_min.less_equal(far) & far.less_equal(t_max);
+    let hit = zero.less_equal(discriminant) & q.not_equal(zero);
+
+    let missed = Real4::splat(Real::INFINITY);
+    let t = near_in_range.select(near, far_in_range.select(far, missed));
+    hit.select(t, missed)
+}
+
+/// Returns which of the rays hit the box within [`t_min`, `t_max`].
+pub fn hit_aabb4(packet: &RayPacket4, aabb: &Aabb, t_min: Real, t_max: Real) -> [bool; 4] {
+    let mut t_entry = Real4::splat(t_min);
+    let mut t_exit = Real4::splat(t_max);
+
+    for axis in 0..3 {
+        let t0 =
+            (Real4::splat(aabb.min[axis]) - packet.origin[axis]) * packet.inverse_direction[axis];
+        let t1 =
+            (Real4::splat(aabb.max[axis]) - packet.origin[axis]) * packet.inverse_direction[axis];
+
+        t_entry = t_entry.max(t0.min(t1));
+        t_exit = t_exit.min(t0.max(t1));
+    }
+
+    t_entry.lanes_less_than(t_exit)
+}
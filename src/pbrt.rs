@@ -0,0 +1,1355 @@
+// Imports scenes written in the PBRT-v3 and PBRT-v4 scene description language into a World.
+//
+// Only part of the language is understood: the camera, film, sample count and path depth,
+// spheres and triangle meshes (inline or from PLY files), matte, metal, mirror and glass
+// materials and their v4 equivalents, infinite, point and diffuse area lights, transforms,
+// attribute blocks, named materials, object instances and includes. Everything else is
+// skipped or approximated with a warning rather than failing the import, so a scene written
+// for PBRT renders as closely as crayfish can manage.
+//
+// PBRT's coordinate system is left handed. The scene is mirrored along x on import, unless the
+// camera transform already mirrors it, so images come out the same way round as PBRT's.
+// Point lights become small emitting spheres since only surfaces can be hit by rays.
+
+use crate::aabb::Aabb;
+use crate::camera::{Camera, CameraModel};
+use crate::defs::Real;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal, Scatterer};
+use crate::math::{Color3, Matrix4, Point3, Vector3};
+use crate::mesh::{Mesh, TriangleMesh};
+use crate::ply;
+use crate::scene::{Background, World};
+use crate::sceneformat::SceneError;
+use crate::shapes::{Intersectable, Sphere};
+
+use log::warn;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// relative to the diagonal of the scene's bounds
+const POINT_LIGHT_RADIUS: Real = 0.002;
+
+// parameters that only tune how PBRT samples, ignoring them doesn't change the image
+const SAMPLING_PARAMETERS: [&str; 2] = ["nsamples", "samples"];
+
+// reflectance at normal incidence of the metals PBRT has named spectra for
+const METALS: [(&str, [Real; 3]); 6] = [
+    ("Ag", [0.97, 0.96, 0.92]),
+    ("Al", [0.91, 0.92, 0.92]),
+    ("Au", [1.0, 0.78, 0.34]),
+    ("Cu", [0.96, 0.64, 0.54]),
+    ("CuZn", [0.96, 0.85, 0.56]),
+    ("Ti", [0.54, 0.50, 0.46]),
+];
+
+/// A scene imported from PBRT, with the render settings it asked for.
+pub struct PbrtScene {
+    pub world: World,
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: i64,
+    pub ray_max_depth: i64,
+    pub output_path: String,
+    /// Everything in the scene that was skipped or only approximated, each reported once.
+    pub warnings: Vec<String>,
+}
+
+/// Imports a PBRT scene file along with the files it includes. Fails on syntax errors and
+/// missing files, anything crayfish can't render is only a warning.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<PbrtScene> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut importer = Importer::new();
+    importer.output_path = path.with_extension("png").to_string_lossy().into_owned();
+    importer.run(&text, directory, 0)?;
+
+    Ok(importer.finish())
+}
+
+/// Imports a PBRT scene from text, included files are looked for in `directory`.
+pub fn parse(text: &str, directory: &Path) -> io::Result<PbrtScene> {
+    let mut importer = Importer::new();
+    importer.run(text, directory, 0)?;
+
+    Ok(importer.finish())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Number(f64),
+    Open,
+    Close,
+}
+
+struct Located {
+    token: Token,
+    line: usize,
+    column: usize,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Located>, SceneError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    let advance = |c: char, line: &mut usize, column: &mut usize| {
+        if c == '\n' {
+            *line += 1;
+            *column = 1;
+        } else {
+            *column += 1;
+        }
+    };
+
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_column) = (line, column);
+
+        let token = if c.is_whitespace() {
+            advance(c, &mut line, &mut column);
+            chars.next();
+            continue;
+        } else if c == '#' {
+            while let Some(c) = chars.next_if(|&c| c != '\n') {
+                advance(c, &mut line, &mut column);
+            }
+            continue;
+        } else if c == '[' || c == ']' {
+            advance(c, &mut line, &mut column);
+            chars.next();
+            if c == '[' {
+                Token::Open
+            } else {
+                Token::Close
+            }
+        } else if c == '"' {
+            advance(c, &mut line, &mut column);
+            chars.next();
+            let mut value = String::new();
+            loop {
+                let c = chars.next().ok_or_else(|| {
+                    SceneError::at(start_line, start_column, "string is never closed")
+                })?;
+                advance(c, &mut line, &mut column);
+                match c {
+                    '"' => break,
+                    '\\' => {
+                        let escaped = chars.next().unwrap_or('\\');
+                        advance(escaped, &mut line, &mut column);
+                        value.push(match escaped {
+                            'n' => '\n',
+                            't' => '\t',
+                            other => other,
+                        });
+                    }
+                    _ => value.push(c),
+                }
+            }
+            Token::Str(value)
+        } else {
+            let mut word = String::new();
+            while let Some(c) =
+                chars.next_if(|&c| !c.is_whitespace() && !matches!(c, '"' | '[' | ']' | '#'))
+            {
+                advance(c, &mut line, &mut column);
+                word.push(c);
+            }
+
+            if word.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '-' | '+' | '.')) {
+                let number = word.parse().map_err(|_| {
+                    SceneError::at(
+                        start_line,
+                        start_column,
+                        &format!("invalid number {}", word),
+                    )
+                })?;
+                Token::Number(number)
+            } else {
+                Token::Word(word)
+            }
+        };
+
+        tokens.push(Located {
+            token,
+            line: start_line,
+            column: start_column,
+        });
+    }
+
+    Ok(tokens)
+}
+
+// v4 writes booleans without quotes, every other bare word starts a directive
+fn is_directive(token: &Token) -> bool {
+    matches!(token, Token::Word(word) if word != "true" && word != "false")
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Number(f64),
+    Str(String),
+    Bool(bool),
+}
+
+struct Parameter {
+    kind: String,
+    name: String,
+    values: Vec<Value>,
+    used: Cell<bool>,
+}
+
+// a directive's "type name" value pairs, remembering which were looked at so the rest can be
+// reported as unsupported
+struct Parameters {
+    list: Vec<Parameter>,
+}
+
+impl Parameters {
+    fn parse(tokens: &[Located]) -> Result<Parameters, String> {
+        let mut list = Vec::new();
+        let mut tokens = tokens.iter().map(|located| &located.token).peekable();
+
+        while let Some(token) = tokens.next() {
+            let declaration = match token {
+                Token::Str(declaration) => declaration,
+                other => return Err(format!("expected a parameter, found {:?}", other)),
+            };
+            let words: Vec<&str> = declaration.split_whitespace().collect();
+            let (kind, name) = match words.as_slice() {
+                [kind, name] => (kind.to_string(), name.to_string()),
+                _ => return Err(format!("invalid parameter declaration \"{}\"", declaration)),
+            };
+
+            let mut values = Vec::new();
+            let mut push = |token: &Token| -> Result<(), String> {
+                values.push(match token {
+                    Token::Number(number) => Value::Number(*number),
+                    Token::Str(string) => Value::Str(string.clone()),
+                    Token::Word(word) => Value::Bool(word == "true"),
+                    other => return Err(format!("unexpected {:?} in {}", other, name)),
+                });
+                Ok(())
+            };
+            match tokens.next() {
+                Some(Token::Open) => loop {
+                    match tokens.next() {
+                        Some(Token::Close) => break,
+                        Some(token) => push(token)?,
+                        None => return Err(format!("values of {} are never closed", name)),
+                    }
+                },
+                Some(token) => push(token)?,
+                None => return Err(format!("{} has no value", name)),
+            }
+
+            list.push(Parameter {
+                kind,
+                name,
+                values,
+                used: Cell::new(false),
+            });
+        }
+
+        Ok(Parameters { list })
+    }
+
+    fn find(&self, name: &str) -> Option<&Parameter> {
+        let parameter = self.list.iter().find(|parameter| parameter.name == name)?;
+        parameter.used.set(true);
+        Some(parameter)
+    }
+
+    fn numbers(&self, name: &str) -> Option<Vec<f64>> {
+        let parameter = self.find(name)?;
+        let numbers = parameter.values.iter().filter_map(|value| match value {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        });
+
+        Some(numbers.collect())
+    }
+
+    fn float(&self, name: &str, default: Real) -> Real {
+        self.numbers(name)
+            .and_then(|numbers| numbers.first().map(|&number| number as Real))
+            .unwrap_or(default)
+    }
+
+    fn integer(&self, name: &str, default: i64) -> i64 {
+        self.numbers(name)
+            .and_then(|numbers| numbers.first().map(|&number| number as i64))
+            .unwrap_or(default)
+    }
+
+    fn string(&self, name: &str) -> Option<String> {
+        match self.find(name)?.values.first() {
+            Some(Value::Str(string)) => Some(string.clone()),
+            _ => None,
+        }
+    }
+
+    fn bool(&self, name: &str, default: bool) -> bool {
+        match self
+            .find(name)
+            .and_then(|parameter| parameter.values.first())
+        {
+            Some(Value::Bool(value)) => *value,
+            Some(Value::Str(value)) => value == "true",
+            _ => default,
+        }
+    }
+
+    fn points(&self, names: &[&str]) -> Option<Vec<Point3>> {
+        let numbers = names.iter().find_map(|name| self.numbers(name))?;
+        let points = numbers
+            .chunks_exact(3)
+            .map(|p| Point3::new(p[0] as Real, p[1] as Real, p[2] as Real));
+
+        Some(points.collect())
+    }
+
+    fn unused(&self) -> impl Iterator<Item = &Parameter> {
+        self.list.iter().filter(|parameter| !parameter.used.get())
+    }
+}
+
+// a shape kept in PBRT's world space until it's known where it ends up, objects are placed
+// once for every instance of them
+#[derive(Clone)]
+enum Primitive {
+    Sphere {
+        center: Point3,
+        radius: Real,
+        material: Arc<dyn Scatterer>,
+    },
+    // wound so the front face is the one PBRT considers the outside
+    Mesh {
+        mesh: Mesh,
+        material: Arc<dyn Scatterer>,
+    },
+}
+
+#[derive(Clone)]
+struct AreaLight {
+    emission: Color3,
+    two_sided: bool,
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+    transform: Matrix4,
+    reverse_orientation: bool,
+    // None for PBRT's "interface" material, shapes with it only bound media
+    material: Option<Arc<dyn Scatterer>>,
+    area_light: Option<AreaLight>,
+}
+
+#[derive(Clone)]
+enum Texture {
+    Constant(Color3),
+    Unsupported(Color3),
+}
+
+struct CameraDescription {
+    kind: String,
+    parameters: Parameters,
+    camera_to_world: Matrix4,
+}
+
+struct Importer {
+    state: GraphicsState,
+    attribute_stack: Vec<GraphicsState>,
+    transform_stack: Vec<Matrix4>,
+    // motion blur isn't supported, only the transform at the start of the frame is used
+    transforms_apply: bool,
+    named_materials: HashMap<String, Option<Arc<dyn Scatterer>>>,
+    named_coordinate_systems: HashMap<String, Matrix4>,
+    textures: HashMap<String, Texture>,
+    objects: HashMap<String, Vec<Primitive>>,
+    current_object: Option<(String, Vec<Primitive>)>,
+
+    camera: Option<CameraDescription>,
+    width: usize,
+    height: usize,
+    samples_per_pixel: i64,
+    ray_max_depth: i64,
+    output_path: String,
+
+    primitives: Vec<Primitive>,
+    instances: Vec<(String, Matrix4)>,
+    background: Color3,
+    point_lights: Vec<(Point3, Color3)>,
+
+    warnings: Vec<String>,
+    warned: HashSet<String>,
+}
+
+impl Importer {
+    fn new() -> Importer {
+        Importer {
+            state: GraphicsState {
+                transform: Matrix4::identity(),
+                reverse_orientation: false,
+                material: Some(lambertian(Color3::new(0.5, 0.5, 0.5))),
+                area_light: None,
+            },
+            attribute_stack: Vec::new(),
+            transform_stack: Vec::new(),
+            transforms_apply: true,
+            named_materials: HashMap::new(),
+            named_coordinate_systems: HashMap::new(),
+            textures: HashMap::new(),
+            objects: HashMap::new(),
+            current_object: None,
+            camera: None,
+            width: 1280,
+            height: 720,
+            samples_per_pixel: 16,
+            ray_max_depth: 6,
+            output_path: String::from("pbrt.png"),
+            primitives: Vec::new(),
+            instances: Vec::new(),
+            background: Color3::default(),
+            point_lights: Vec::new(),
+            warnings: Vec::new(),
+            warned: HashSet::new(),
+        }
+    }
+
+    fn warn(&mut self, message: String) {
+        if self.warned.insert(message.clone()) {
+            warn!("{}", message);
+            self.warnings.push(message);
+        }
+    }
+
+    fn warn_unused(&mut self, context: &str, parameters: &Parameters) {
+        let unused: Vec<String> = parameters
+            .unused()
+            .filter(|parameter| !SAMPLING_PARAMETERS.contains(&parameter.name.as_str()))
+            .map(|parameter| parameter.name.clone())
+            .collect();
+
+        for name in unused {
+            self.warn(format!("{}: parameter \"{}\" is ignored", context, name));
+        }
+    }
+
+    fn run(&mut self, text: &str, directory: &Path, depth: usize) -> Result<(), SceneError> {
+        let tokens = tokenize(text)?;
+        let mut i = 0;
+
+        while i < tokens.len() {
+            let Located {
+                token,
+                line,
+                column,
+            } = &tokens[i];
+            let directive = match token {
+                Token::Word(word) => word.as_str(),
+                other => {
+                    let message = format!("expected a directive, found {:?}", other);
+                    return Err(SceneError::at(*line, *column, &message));
+                }
+            };
+            i += 1;
+
+            // the only directive taking a bare word
+            if directive == "ActiveTransform" {
+                if let Some(Token::Word(which)) = tokens.get(i).map(|located| &located.token) {
+                    self.transforms_apply = which != "EndTime";
+                    if which != "StartTime" {
+                        self.warn(String::from(
+                            "Motion blur isn't supported, the scene is rendered at the start time",
+                        ));
+                    }
+                }
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            while i < tokens.len() && !is_directive(&tokens[i].token) {
+                i += 1;
+            }
+
+            self.directive(directive, &tokens[start..i], directory, depth)
+                .map_err(|message| SceneError::at(*line, *column, &message))?;
+        }
+
+        Ok(())
+    }
+
+    fn directive(
+        &mut self,
+        directive: &str,
+        arguments: &[Located],
+        directory: &Path,
+        depth: usize,
+    ) -> Result<(), String> {
+        match directive {
+            "Identity" => self.set_transform(Matrix4::identity()),
+            "Translate" => {
+                let v = numbers(arguments, 3)?;
+                self.concat(Matrix4::translate(v[0], v[1], v[2]));
+            }
+            "Scale" => {
+                let v = numbers(arguments, 3)?;
+                self.concat(Matrix4::scale(v[0], v[1], v[2]));
+            }
+            "Rotate" => {
+                let v = numbers(arguments, 4)?;
+                self.concat(Matrix4::rotate(v[0], &Vector3::new(v[1], v[2], v[3])));
+            }
+            "LookAt" => {
+                let v = numbers(arguments, 9)?;
+                self.concat(look_at(&v));
+            }
+            "Transform" => self.set_transform(Matrix4::from_columns(&numbers(arguments, 16)?)),
+            "ConcatTransform" => self.concat(Matrix4::from_columns(&numbers(arguments, 16)?)),
+            "CoordinateSystem" => {
+                let (names, _) = split(arguments, 1)?;
+                self.named_coordinate_systems
+                    .insert(names[0].clone(), self.state.transform);
+            }
+            "CoordSysTransform" => {
+                let (names, _) = split(arguments, 1)?;
+                match self.named_coordinate_systems.get(&names[0]) {
+                    Some(transform) => self.state.transform = *transform,
+                    None => self.warn(format!("Unknown coordinate system {}", names[0])),
+                }
+            }
+            "TransformTimes" => {}
+            "ReverseOrientation" => {
+                self.state.reverse_orientation = !self.state.reverse_orientation
+            }
+
+            "AttributeBegin" => self.attribute_stack.push(self.state.clone()),
+            "AttributeEnd" => {
+                self.state = self
+                    .attribute_stack
+                    .pop()
+                    .ok_or("AttributeEnd without AttributeBegin")?;
+            }
+            "TransformBegin" => self.transform_stack.push(self.state.transform),
+            "TransformEnd" => {
+                self.state.transform = self
+                    .transform_stack
+                    .pop()
+                    .ok_or("TransformEnd without TransformBegin")?;
+            }
+            "WorldBegin" => {
+                self.state.transform = Matrix4::identity();
+                self.named_coordinate_systems
+                    .insert(String::from("world"), Matrix4::identity());
+            }
+            "WorldEnd" => {}
+
+            "Camera" => {
+                let (kinds, parameters) = split(arguments, 1)?;
+                let camera_to_world = self.state.transform.inverse().ok_or("singular camera")?;
+                self.named_coordinate_systems
+                    .insert(String::from("camera"), camera_to_world);
+                self.camera = Some(CameraDescription {
+                    kind: kinds[0].clone(),
+                    parameters,
+                    camera_to_world,
+                });
+            }
+            "Film" => {
+                let (kinds, parameters) = split(arguments, 1)?;
+                self.film(&kinds[0], &parameters);
+            }
+            "Sampler" => {
+                let (kinds, parameters) = split(arguments, 1)?;
+                self.samples_per_pixel = parameters.integer("pixelsamples", 16);
+                self.warn_unused(&format!("Sampler \"{}\"", kinds[0]), &parameters);
+            }
+            "Integrator" => {
+                let (kinds, parameters) = split(arguments, 1)?;
+                // the camera ray is one more than the bounces PBRT counts
+                self.ray_max_depth = parameters.integer("maxdepth", 5) + 1;
+                if !matches!(kinds[0].as_str(), "path" | "volpath") {
+                    self.warn(format!(
+                        "The {} integrator isn't supported, using crayfish's path tracer",
+                        kinds[0]
+                    ));
+                }
+                self.warn_unused(&format!("Integrator \"{}\"", kinds[0]), &parameters);
+            }
+            "PixelFilter" => {
+                let (kinds, _) = split(arguments, 1)?;
+                if kinds[0] != "box" {
+                    self.warn(format!(
+                        "The {} pixel filter isn't supported, using a box filter",
+                        kinds[0]
+                    ));
+                }
+            }
+            "Accelerator" | "Option" => {}
+            "ColorSpace" => {
+                let (spaces, _) = split(arguments, 1)?;
+                if spaces[0] != "srgb" {
+                    self.warn(format!("Colour space {} is treated as sRGB", spaces[0]));
+                }
+            }
+
+            "Texture" => {
+                let (names, parameters) = split(arguments, 3)?;
+                let texture = self.texture(&names[2], &parameters, directory);
+                self.textures.insert(names[0].clone(), texture);
+            }
+            "Material" => {
+                let (kinds, parameters) = split(arguments, 1)?;
+                self.state.material = self.material(&kinds[0], &parameters);
+            }
+            "MakeNamedMaterial" => {
+                let (names, parameters) = split(arguments, 1)?;
+                let kind = parameters.string("type").unwrap_or_default();
+                let material = self.material(&kind, &parameters);
+                self.named_materials.insert(names[0].clone(), material);
+            }
+            "NamedMaterial" => {
+                let (names, _) = split(arguments, 1)?;
+                match self.named_materials.get(&names[0]) {
+                    Some(material) => self.state.material = material.clone(),
+                    None => return Err(format!("unknown material {}", names[0])),
+                }
+            }
+
+            "Shape" => {
+                let (kinds, parameters) = split(arguments, 1)?;
+                self.shape(&kinds[0], &parameters, directory)?;
+            }
+            "LightSource" => {
+                let (kinds, parameters) = split(arguments, 1)?;
+                self.light(&kinds[0], &parameters);
+            }
+            "AreaLightSource" => {
+                let (kinds, parameters) = split(arguments, 1)?;
+                if kinds[0] != "diffuse" {
+                    self.warn(format!("Area light {} is treated as diffuse", kinds[0]));
+                }
+                let emission = self.color(&parameters, "L", Color3::new(1.0, 1.0, 1.0))
+                    * self.scale(&parameters);
+                self.state.area_light = Some(AreaLight {
+                    emission,
+                    two_sided: parameters.bool("twosided", false),
+                });
+                self.warn_unused(&format!("AreaLightSource \"{}\"", kinds[0]), &parameters);
+            }
+
+            "ObjectBegin" => {
+                let (names, _) = split(arguments, 1)?;
+                self.attribute_stack.push(self.state.clone());
+                self.current_object = Some((names[0].clone(), Vec::new()));
+            }
+            "ObjectEnd" => {
+                let (name, primitives) = self
+                    .current_object
+                    .take()
+                    .ok_or("ObjectEnd without ObjectBegin")?;
+                self.objects.insert(name, primitives);
+                self.state = self.attribute_stack.pop().ok_or("unbalanced ObjectEnd")?;
+            }
+            "ObjectInstance" => {
+                let (names, _) = split(arguments, 1)?;
+                self.instances
+                    .push((names[0].clone(), self.state.transform));
+            }
+
+            "MakeNamedMedium" | "MediumInterface" => self.warn(String::from(
+                "Participating media aren't supported and are left out",
+            )),
+            "Attribute" => self.warn(String::from(
+                "Attribute defaults aren't supported and are left out",
+            )),
+
+            "Include" | "Import" => {
+                let (names, _) = split(arguments, 1)?;
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(String::from("includes nested too deeply"));
+                }
+                let path = directory.join(&names[0]);
+                let text = fs::read_to_string(&path)
+                    .map_err(|error| format!("unable to read {}: {}", path.display(), error))?;
+                let nested_directory = path.parent().unwrap_or(directory).to_path_buf();
+                self.run(&text, &nested_directory, depth + 1)
+                    .map_err(|error| format!("{}: {}", path.display(), error))?;
+            }
+
+            _ => self.warn(format!("{} isn't supported and is left out", directive)),
+        }
+
+        Ok(())
+    }
+
+    fn concat(&mut self, transform: Matrix4) {
+        if self.transforms_apply {
+            self.state.transform = self.state.transform * transform;
+        }
+    }
+
+    fn set_transform(&mut self, transform: Matrix4) {
+        if self.transforms_apply {
+            self.state.transform = transform;
+        }
+    }
+
+    fn film(&mut self, kind: &str, parameters: &Parameters) {
+        // v3's image film has smaller defaults than v4's films
+        let (width, height) = if kind == "image" {
+            (640, 480)
+        } else {
+            (1280, 720)
+        };
+        self.width = parameters.integer("xresolution", width).max(1) as usize;
+        self.height = parameters.integer("yresolution", height).max(1) as usize;
+        if let Some(filename) = parameters.string("filename") {
+            self.output_path = Path::new(&filename)
+                .with_extension("png")
+                .to_string_lossy()
+                .into_owned();
+        }
+
+        self.warn_unused(&format!("Film \"{}\"", kind), parameters);
+    }
+
+    fn scale(&mut self, parameters: &Parameters) -> Color3 {
+        match parameters.list.iter().find(|p| p.name == "scale") {
+            Some(parameter) if parameter.kind == "float" => {
+                let scale = parameters.float("scale", 1.0);
+                Color3::new(scale, scale, scale)
+            }
+            Some(_) => self.color(parameters, "scale", Color3::new(1.0, 1.0, 1.0)),
+            None => Color3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    // an rgb value, a constant texture or the closest crayfish can get to anything else
+    fn color(&mut self, parameters: &Parameters, name: &str, default: Color3) -> Color3 {
+        let parameter = match parameters.find(name) {
+            Some(parameter) => parameter,
+            None => return default,
+        };
+        let numbers: Vec<Real> = parameter
+            .values
+            .iter()
+            .filter_map(|value| match value {
+                Value::Number(number) => Some(*number as Real),
+                _ => None,
+            })
+            .collect();
+
+        match parameter.kind.as_str() {
+            "rgb" | "color" if numbers.len() >= 3 => {
+                Color3::new(numbers[0], numbers[1], numbers[2])
+            }
+            "float" if !numbers.is_empty() => Color3::new(numbers[0], numbers[0], numbers[0]),
+            "texture" => {
+                let texture = match parameter.values.first() {
+                    Some(Value::Str(texture)) => texture.clone(),
+                    _ => return default,
+                };
+                match self.textures.get(&texture) {
+                    Some(Texture::Constant(color)) => *color,
+                    Some(Texture::Unsupported(color)) => *color,
+                    None => {
+                        self.warn(format!("Unknown texture {}", texture));
+                        default
+                    }
+                }
+            }
+            "blackbody" => {
+                let message = format!("Blackbody {} is approximated by white", name);
+                self.warn(message);
+                let scale = if numbers.len() > 1 { numbers[1] } else { 1.0 };
+                Color3::new(scale, scale, scale)
+            }
+            kind => {
+                let message = format!("{} {} isn't supported, using a default", kind, name);
+                self.warn(message);
+                default
+            }
+        }
+    }
+
+    fn texture(&mut self, class: &str, parameters: &Parameters, directory: &Path) -> Texture {
+        let gray = Color3::new(0.5, 0.5, 0.5);
+        let texture = match class {
+            "constant" => Texture::Constant(self.color(parameters, "value", gray)),
+            "imagemap" => {
+                let filename = parameters.string("filename").unwrap_or_default();
+                let average = average_color(&directory.join(&filename));
+                self.warn(format!(
+                    "Image textures aren't supported, {} is replaced by its average colour",
+                    filename
+                ));
+                Texture::Unsupported(average.unwrap_or(gray))
+            }
+            _ => {
+                self.warn(format!("{} textures aren't supported, using grey", class));
+                return Texture::Unsupported(gray);
+            }
+        };
+
+        self.warn_unused(&format!("Texture \"{}\"", class), parameters);
+        texture
+    }
+
+    fn material(&mut self, kind: &str, parameters: &Parameters) -> Option<Arc<dyn Scatterer>> {
+        let gray = Color3::new(0.5, 0.5, 0.5);
+        let context = format!("Material \"{}\"", kind);
+
+        let material: Arc<dyn Scatterer> = match kind {
+            "matte" => lambertian(self.color(parameters, "Kd", gray)),
+            "diffuse" => lambertian(self.color(parameters, "reflectance", gray)),
+            "metal" | "conductor" => {
+                let color = self.metal_color(parameters);
+                let roughness = self.roughness(parameters);
+                Arc::new(Metal {
+                    diffuse: color,
+                    fuzz: roughness,
+                })
+            }
+            "mirror" => Arc::new(Metal {
+                diffuse: self.color(parameters, "Kr", Color3::new(0.9, 0.9, 0.9)),
+                fuzz: 0.0,
+            }),
+            "glass" | "dielectric" | "thindielectric" => {
+                if kind == "thindielectric" {
+                    self.warn(String::from("Thin dielectrics are rendered as solid glass"));
+                }
+                let index = match parameters.numbers("eta") {
+                    Some(eta) => eta.first().map_or(1.5, |&eta| eta as Real),
+                    None => parameters.float("index", 1.5),
+                };
+                Arc::new(Dielectric {
+                    refraction_index: index,
+                })
+            }
+            "" | "none" | "interface" => return None,
+            other => {
+                self.warn(format!(
+                    "Material {} isn't supported, using its diffuse colour",
+                    other
+                ));
+                let diffuse = self.color(parameters, "Kd", gray);
+                let reflectance = self.color(parameters, "reflectance", diffuse);
+                for parameter in parameters.list.iter() {
+                    parameter.used.set(true);
+                }
+                lambertian(reflectance)
+            }
+        };
+
+        parameters.find("type");
+        self.warn_unused(&context, parameters);
+        Some(material)
+    }
+
+    fn metal_color(&mut self, parameters: &Parameters) -> Color3 {
+        if parameters.find("reflectance").is_some() {
+            return self.color(parameters, "reflectance", Color3::new(0.9, 0.9, 0.9));
+        }
+
+        // a named spectrum such as "metal-Au-eta" or a v3 file such as "spds/metals/Au.eta.spd"
+        let named = |name: &str| match parameters.find(name)?.values.first() {
+            Some(Value::Str(spectrum)) => Some(spectrum.clone()),
+            _ => None,
+        };
+        if let Some(spectrum) = named("eta").or_else(|| named("k")) {
+            named("k");
+            let metal = METALS.iter().find(|(symbol, _)| {
+                spectrum.contains(&format!("-{}-", symbol))
+                    || spectrum.contains(&format!("/{}.", symbol))
+            });
+            return match metal {
+                Some((_, [r, g, b])) => Color3::new(*r, *g, *b),
+                None => {
+                    self.warn(format!("Unknown metal {}, using copper", spectrum));
+                    Color3::new(0.96, 0.64, 0.54)
+                }
+            };
+        }
+
+        // fresnel reflectance at normal incidence, copper when nothing is given
+        let eta = self.color(parameters, "eta", Color3::new(0.2004, 0.9240, 1.1022));
+        let k = self.color(parameters, "k", Color3::new(3.9129, 2.4528, 2.1421));
+        let reflectance = |eta: Real, k: Real| {
+            ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k)
+        };
+
+        Color3::new(
+            reflectance(eta[0], k[0]),
+            reflectance(eta[1], k[1]),
+            reflectance(eta[2], k[2]),
+        )
+    }
+
+    // fuzz stands in for a microfacet roughness, they only roughly correspond
+    fn roughness(&mut self, parameters: &Parameters) -> Real {
+        let roughness = match parameters.numbers("roughness") {
+            Some(roughness) => roughness.first().map_or(0.0, |&r| r as Real),
+            None => {
+                let u = parameters.float("uroughness", 0.0);
+                let v = parameters.float("vroughness", u);
+                (u + v) * 0.5
+            }
+        };
+        parameters.find("remaproughness");
+
+        roughness.clamp(0.0, 1.0)
+    }
+
+    fn shape(
+        &mut self,
+        kind: &str,
+        parameters: &Parameters,
+        directory: &Path,
+    ) -> Result<(), String> {
+        let context = format!("Shape \"{}\"", kind);
+        let material = match (&self.state.area_light, &self.state.material) {
+            (Some(light), _) => Some(Arc::new(DiffuseLight {
+                emission: light.emission,
+                two_sided: light.two_sided,
+            }) as Arc<dyn Scatterer>),
+            (None, material) => material.clone(),
+        };
+        let material = match material {
+            Some(material) => material,
+            None => {
+                self.warn(String::from(
+                    "Shapes with the interface material only bound media and are left out",
+                ));
+                return Ok(());
+            }
+        };
+        let transform = self.state.transform;
+
+        let primitive = match kind {
+            "sphere" => {
+                if self.state.area_light.is_some() && self.state.reverse_orientation {
+                    self.warn(String::from(
+                        "Spheres emit outwards even with ReverseOrientation",
+                    ));
+                }
+                let radius = parameters.float("radius", 1.0);
+                let center = transform.transform_point(&Point3::default());
+                let axes = [
+                    Vector3::new(radius, 0.0, 0.0),
+                    Vector3::new(0.0, radius, 0.0),
+                    Vector3::new(0.0, 0.0, radius),
+                ];
+                let radii: Vec<Real> = axes
+                    .iter()
+                    .map(|axis| transform.transform_vector(axis).magnitude())
+                    .collect();
+                if (radii[0] - radii[1]).abs() > 1e-3 * radii[0]
+                    || (radii[0] - radii[2]).abs() > 1e-3 * radii[0]
+                {
+                    self.warn(String::from(
+                        "Spheres scaled unevenly are rendered as round spheres",
+                    ));
+                }
+                Primitive::Sphere {
+                    center,
+                    radius: (radii[0] + radii[1] + radii[2]) / 3.0,
+                    material,
+                }
+            }
+            "trianglemesh" | "loopsubdiv" => {
+                if kind == "loopsubdiv" {
+                    parameters.find("levels");
+                    self.warn(String::from(
+                        "Subdivision surfaces are rendered as their control mesh",
+                    ));
+                }
+                let mesh = triangle_mesh(parameters)?;
+                Primitive::Mesh {
+                    mesh: self.oriented(mesh),
+                    material,
+                }
+            }
+            "plymesh" => {
+                let filename = parameters
+                    .string("filename")
+                    .ok_or("plymesh without filename")?;
+                let path = directory.join(&filename);
+                let mesh = ply::load(&path)
+                    .map_err(|error| format!("unable to load {}: {}", path.display(), error))?;
+                Primitive::Mesh {
+                    mesh: self.oriented(mesh),
+                    material,
+                }
+            }
+            other => {
+                self.warn(format!("Shape {} isn't supported and is left out", other));
+                return Ok(());
+            }
+        };
+
+        self.warn_unused(&context, parameters);
+        match &mut self.current_object {
+            Some((_, primitives)) => primitives.push(primitive),
+            None => self.primitives.push(primitive),
+        }
+
+        Ok(())
+    }
+
+    // moves a mesh to world space, wound so PBRT's idea of its outside is the front face
+    fn oriented(&self, mesh: Mesh) -> Mesh {
+        let mut mesh = mesh.transformed(&self.state.transform);
+        if self.state.transform.swaps_handedness() != self.state.reverse_orientation {
+            mesh.flip_winding();
+        }
+
+        mesh
+    }
+
+    fn light(&mut self, kind: &str, parameters: &Parameters) {
+        let white = Color3::new(1.0, 1.0, 1.0);
+        let context = format!("LightSource \"{}\"", kind);
+
+        match kind {
+            "infinite" => {
+                if let Some(map) = parameters
+                    .string("mapname")
+                    .or_else(|| parameters.string("filename"))
+                {
+                    self.warn(format!(
+                        "Environment maps aren't supported, {} is replaced by a uniform colour",
+                        map
+                    ));
+                }
+                let color = self.color(parameters, "L", white) * self.scale(parameters);
+                self.background += color;
+            }
+            "point" => {
+                let intensity = self.color(parameters, "I", white) * self.scale(parameters);
+                let from = parameters
+                    .points(&["from"])
+                    .and_then(|points| points.first().copied())
+                    .unwrap_or_default();
+                let position = self.state.transform.transform_point(&from);
+                self.point_lights.push((position, intensity));
+            }
+            other => {
+                self.warn(format!(
+                    "{} lights aren't supported and are left out",
+                    other
+                ));
+                return;
+            }
+        }
+
+        self.warn_unused(&context, parameters);
+    }
+
+    fn create_camera(&mut self, mirror: &Matrix4) -> Camera {
+        let aspect_ratio = self.width as Real / self.height as Real;
+        let description = self.camera.take().unwrap_or_else(|| CameraDescription {
+            kind: String::from("perspective"),
+            parameters: Parameters { list: Vec::new() },
+            camera_to_world: Matrix4::identity(),
+        });
+        let parameters = &description.parameters;
+        let camera_to_world = *mirror * description.camera_to_world;
+
+        let origin = camera_to_world.transform_point(&Point3::default());
+        let look_at = camera_to_world.transform_point(&Point3::new(0.0, 0.0, 1.0));
+        let up = camera_to_world.transform_vector(&Vector3::new(0.0, 1.0, 0.0));
+
+        let model = match description.kind.as_str() {
+            "perspective" => {
+                // PBRT's field of view is along the shorter side of the image
+                let fov = parameters.float("fov", 90.0);
+                let fov_deg = if aspect_ratio >= 1.0 {
+                    fov
+                } else {
+                    let half_height = (fov.to_radians() * 0.5).tan() / aspect_ratio;
+                    (2.0 * half_height.atan()).to_degrees()
+                };
+                CameraModel::Perspective { fov_deg }
+            }
+            "orthographic" => CameraModel::Orthographic {
+                view_height: if aspect_ratio >= 1.0 {
+                    2.0
+                } else {
+                    2.0 / aspect_ratio
+                },
+            },
+            "environment" | "spherical" => {
+                if parameters.string("mapping").as_deref() == Some("equalarea") {
+                    self.warn(String::from(
+                        "Equal area spherical cameras are rendered equirectangular",
+                    ));
+                }
+                CameraModel::Equirectangular
+            }
+            other => {
+                self.warn(format!(
+                    "The {} camera isn't supported, using a perspective camera",
+                    other
+                ));
+                for parameter in parameters.list.iter() {
+                    parameter.used.set(true);
+                }
+                CameraModel::Perspective { fov_deg: 90.0 }
+            }
+        };
+
+        let lens_radius = parameters.float("lensradius", 0.0);
+        let focus_distance = parameters.float("focaldistance", 1e6);
+        self.warn_unused(&format!("Camera \"{}\"", description.kind), parameters);
+
+        Camera::with_model(
+            origin,
+            look_at,
+            up,
+            aspect_ratio,
+            model,
+            focus_distance,
+            lens_radius * 2.0,
+        )
+    }
+
+    fn finish(mut self) -> PbrtScene {
+        if self.current_object.is_some() {
+            self.warn(String::from("ObjectBegin without ObjectEnd"));
+        }
+
+        // mirroring along x turns PBRT's left handed space right handed, a camera transform
+        // that mirrors already does
+        let mirror = match &self.camera {
+            Some(camera) if camera.camera_to_world.swaps_handedness() => Matrix4::identity(),
+            _ => Matrix4::scale(-1.0, 1.0, 1.0),
+        };
+
+        let camera = self.create_camera(&mirror);
+        let mut world = World::new(camera);
+        world.set_background(Background::Uniform(self.background));
+
+        let mut placed: Vec<(Primitive, Matrix4)> = self
+            .primitives
+            .drain(..)
+            .map(|primitive| (primitive, mirror))
+            .collect();
+        for (name, transform) in std::mem::take(&mut self.instances) {
+            match self.objects.get(&name) {
+                Some(primitives) => placed.extend(
+                    primitives
+                        .iter()
+                        .map(|primitive| (primitive.clone(), mirror * transform)),
+                ),
+                None => self.warn(format!("Unknown object {}", name)),
+            }
+        }
+
+        let mut bounds: Option<Aabb> = None;
+        for (primitive, transform) in placed {
+            let shape: Arc<dyn Intersectable> = match primitive {
+                Primitive::Sphere {
+                    center,
+                    radius,
+                    material,
+                } => {
+                    let center = transform.transform_point(&center);
+                    let radius = radius
+                        * transform
+                            .transform_vector(&Vector3::new(1.0, 0.0, 0.0))
+                            .magnitude();
+                    let extent = Vector3::new(radius, radius, radius);
+                    bounds = surrounding(bounds, Aabb::new(center - extent, center + extent));
+                    Arc::new(Sphere {
+                        center,
+                        radius,
+                        material,
+                    })
+                }
+                Primitive::Mesh { mesh, material } => {
+                    let mut mesh = mesh.transformed(&transform);
+                    if transform.swaps_handedness() {
+                        mesh.flip_winding();
+                    }
+                    if let Some(mesh_bounds) = mesh.bounds() {
+                        bounds = surrounding(bounds, mesh_bounds);
+                    }
+                    Arc::new(TriangleMesh::new(mesh, material))
+                }
+            };
+            world.add_shared_shape(shape);
+        }
+
+        let scale = bounds.map_or(1.0, |bounds| (bounds.max - bounds.min).magnitude());
+        let radius = POINT_LIGHT_RADIUS * scale.max(1e-3);
+        for (position, intensity) in self.point_lights.iter() {
+            let center = mirror.transform_point(position);
+            world.add_shape(Sphere::point_light(center, *intensity, radius));
+        }
+        if !self.point_lights.is_empty() {
+            self.warn(String::from(
+                "Point lights are rendered as small spheres, expect more noise than PBRT",
+            ));
+        }
+
+        PbrtScene {
+            world,
+            width: self.width,
+            height: self.height,
+            samples_per_pixel: self.samples_per_pixel,
+            ray_max_depth: self.ray_max_depth,
+            output_path: self.output_path,
+            warnings: self.warnings,
+        }
+    }
+}
+
+// the leading strings of a directive and the parameters after them
+fn split(arguments: &[Located], count: usize) -> Result<(Vec<String>, Parameters), String> {
+    let mut names = Vec::new();
+    for located in arguments.iter().take(count) {
+        match &located.token {
+            Token::Str(name) => names.push(name.clone()),
+            other => return Err(format!("expected a string, found {:?}", other)),
+        }
+    }
+    if names.len() < count {
+        return Err(format!("expected {} strings", count));
+    }
+
+    Ok((names, Parameters::parse(&arguments[count..])?))
+}
+
+fn numbers(arguments: &[Located], count: usize) -> Result<Vec<Real>, String> {
+    let numbers: Vec<Real> = arguments
+        .iter()
+        .filter_map(|located| match located.token {
+            Token::Number(number) => Some(number as Real),
+            _ => None,
+        })
+        .collect();
+
+    if numbers.len() != count {
+        return Err(format!(
+            "expected {} numbers, found {}",
+            count,
+            numbers.len()
+        ));
+    }
+
+    Ok(numbers)
+}
+
+// PBRT's LookAt, which gives the transform from world to camera space
+fn look_at(v: &[Real]) -> Matrix4 {
+    let position = Point3::new(v[0], v[1], v[2]);
+    let target = Point3::new(v[3], v[4], v[5]);
+    let up = Vector3::new(v[6], v[7], v[8]).as_normal();
+
+    let direction = (target - position).as_normal();
+    let right = up.cross(&direction).as_normal();
+    let new_up = direction.cross(&right);
+
+    let camera_to_world = Matrix4::from_rows(&[
+        right[0],
+        new_up[0],
+        direction[0],
+        position[0],
+        right[1],
+        new_up[1],
+        direction[1],
+        position[1],
+        right[2],
+        new_up[2],
+        direction[2],
+        position[2],
+        0.0,
+        0.0,
+        0.0,
+        1.0,
+    ]);
+
+    camera_to_world.inverse().unwrap_or_default()
+}
+
+fn triangle_mesh(parameters: &Parameters) -> Result<Mesh, String> {
+    let positions = parameters
+        .points(&["P"])
+        .ok_or("trianglemesh without positions")?;
+    let indices: Vec<usize> = match parameters.numbers("indices") {
+        Some(indices) => indices.iter().map(|&index| index as usize).collect(),
+        None if positions.len() == 3 => vec![0, 1, 2],
+        None => return Err(String::from("trianglemesh without indices")),
+    };
+    if !indices.len().is_multiple_of(3) || indices.iter().any(|&index| index >= positions.len()) {
+        return Err(String::from("trianglemesh indices don't make triangles"));
+    }
+
+    let normals = parameters.points(&["N"]).unwrap_or_default();
+    let uvs: Vec<[Real; 2]> = ["uv", "st"]
+        .iter()
+        .find_map(|name| parameters.numbers(name))
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|uv| [uv[0] as Real, uv[1] as Real])
+        .collect();
+
+    Ok(Mesh {
+        normals: if normals.len() == positions.len() {
+            normals
+        } else {
+            Vec::new()
+        },
+        uvs: if uvs.len() == positions.len() {
+            uvs
+        } else {
+            Vec::new()
+        },
+        positions,
+        colors: Vec::new(),
+        triangles: indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect(),
+    })
+}
+
+fn lambertian(diffuse: Color3) -> Arc<dyn Scatterer> {
+    Arc::new(Lambertian { diffuse })
+}
+
+fn surrounding(bounds: Option<Aabb>, other: Aabb) -> Option<Aabb> {
+    Some(match bounds {
+        Some(bounds) => bounds.surrounding(&other),
+        None => other,
+    })
+}
+
+// in linear space, assuming the image is stored with a gamma of 2.2
+fn average_color(path: &Path) -> Option<Color3> {
+    let image = image::open(path).ok()?.to_rgb8();
+    let pixel_count = (image.width() * image.height()).max(1) as f64;
+    let mut sum = [0.0f64; 3];
+    for pixel in image.pixels() {
+        for (channel, value) in sum.iter_mut().zip(pixel.0.iter()) {
+            *channel += (*value as f64 / 255.0).powf(2.2);
+        }
+    }
+
+    Some(Color3::new(
+        (sum[0] / pixel_count) as Real,
+        (sum[1] / pixel_count) as Real,
+        (sum[2] / pixel_count) as Real,
+    ))
+}
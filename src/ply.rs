@@ -0,0 +1,364 @@
+// Reads meshes from PLY files, ASCII or binary. Vertex positions, normals, texture
+// coordinates and colours are read along with the faces, polygons with more than three corners are split
+// into triangles. Elements and properties that aren't needed are skipped.
+
+use crate::defs::Real;
+use crate::math::{Color3, Point3, Vector3};
+use crate::mesh::Mesh;
+use crate::texture::srgb_to_linear;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::SplitAsciiWhitespace;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Copy, Clone)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+enum Property {
+    Scalar(String, Scalar),
+    List(String, Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary {
+        bytes: &'a [u8],
+        position: usize,
+        big_endian: bool,
+    },
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Mesh> {
+    parse(&fs::read(path)?)
+}
+
+pub fn parse(bytes: &[u8]) -> io::Result<Mesh> {
+    let (encoding, elements, body_start) = parse_header(bytes)?;
+    let mut body = match encoding {
+        Encoding::Ascii => {
+            let text = std::str::from_utf8(&bytes[body_start..]).map_err(invalid)?;
+            Body::Ascii(text.split_ascii_whitespace())
+        }
+        _ => Body::Binary {
+            bytes: &bytes[body_start..],
+            position: 0,
+            big_endian: encoding == Encoding::BigEndian,
+        },
+    };
+
+    let mut mesh = Mesh::default();
+    for element in elements.iter() {
+        match element.name.as_str() {
+            "vertex" => read_vertices(&mut body, element, &mut mesh)?,
+            "face" => read_faces(&mut body, element, &mut mesh)?,
+            _ => {
+                for _ in 0..element.count {
+                    read_values(&mut body, element)?;
+                }
+            }
+        }
+    }
+
+    let vertex_count = mesh.positions.len();
+    if mesh
+        .triangles
+        .iter()
+        .flatten()
+        .any(|&index| index >= vertex_count)
+    {
+        return Err(invalid("a face refers to a vertex that isn't there"));
+    }
+
+    Ok(mesh)
+}
+
+fn parse_header(bytes: &[u8]) -> io::Result<(Encoding, Vec<Element>, usize)> {
+    let end_marker = b"end_header";
+    let end = bytes
+        .windows(end_marker.len())
+        .position(|window| window == end_marker)
+        .ok_or_else(|| invalid("no end_header"))?;
+    let body_start = bytes[end..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(bytes.len(), |newline| end + newline + 1);
+
+    let header = std::str::from_utf8(&bytes[..end]).map_err(invalid)?;
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err(invalid("not a PLY file"));
+    }
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        match words.as_slice() {
+            ["format", format, _] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::LittleEndian,
+                    "binary_big_endian" => Encoding::BigEndian,
+                    _ => return Err(invalid(format!("unknown format {}", format))),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(invalid)?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let property = Property::List(name.to_string(), scalar(count)?, scalar(item)?);
+                current(&mut elements)?.properties.push(property);
+            }
+            ["property", kind, name] => {
+                let property = Property::Scalar(name.to_string(), scalar(kind)?);
+                current(&mut elements)?.properties.push(property);
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(invalid(format!("unexpected header line {}", line))),
+        }
+    }
+
+    let encoding = encoding.ok_or_else(|| invalid("no format"))?;
+    Ok((encoding, elements, body_start))
+}
+
+fn current(elements: &mut [Element]) -> io::Result<&mut Element> {
+    elements
+        .last_mut()
+        .ok_or_else(|| invalid("property before any element"))
+}
+
+fn scalar(name: &str) -> io::Result<Scalar> {
+    Ok(match name {
+        "char" | "int8" => Scalar::I8,
+        "uchar" | "uint8" => Scalar::U8,
+        "short" | "int16" => Scalar::I16,
+        "ushort" | "uint16" => Scalar::U16,
+        "int" | "int32" => Scalar::I32,
+        "uint" | "uint32" => Scalar::U32,
+        "float" | "float32" => Scalar::F32,
+        "double" | "float64" => Scalar::F64,
+        _ => return Err(invalid(format!("unknown property type {}", name))),
+    })
+}
+
+impl Scalar {
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // integers are scaled from the range of their type to [0, 1], floats are left alone
+    fn normalize(self, value: f64) -> Option<f64> {
+        match self {
+            Scalar::U8 => Some(value / u8::MAX as f64),
+            Scalar::U16 => Some(value / u16::MAX as f64),
+            Scalar::U32 => Some(value / u32::MAX as f64),
+            Scalar::I8 => Some(value / i8::MAX as f64),
+            Scalar::I16 => Some(value / i16::MAX as f64),
+            Scalar::I32 => Some(value / i32::MAX as f64),
+            Scalar::F32 | Scalar::F64 => None,
+        }
+    }
+}
+
+impl<'a> Body<'a> {
+    // every type fits in a double without losing anything
+    fn read(&mut self, scalar: Scalar) -> io::Result<f64> {
+        match self {
+            Body::Ascii(words) => words
+                .next()
+                .ok_or_else(|| invalid("file ends early"))?
+                .parse()
+                .map_err(invalid),
+            Body::Binary {
+                bytes,
+                position,
+                big_endian,
+            } => {
+                let size = scalar.size();
+                let mut value = [0u8; 8];
+                let source = bytes
+                    .get(*position..*position + size)
+                    .ok_or_else(|| invalid("file ends early"))?;
+                value[..size].copy_from_slice(source);
+                if *big_endian {
+                    value[..size].reverse();
+                }
+                *position += size;
+
+                let bytes2 = [value[0], value[1]];
+                let bytes4 = [value[0], value[1], value[2], value[3]];
+                Ok(match scalar {
+                    Scalar::I8 => value[0] as i8 as f64,
+                    Scalar::U8 => value[0] as f64,
+                    Scalar::I16 => i16::from_le_bytes(bytes2) as f64,
+                    Scalar::U16 => u16::from_le_bytes(bytes2) as f64,
+                    Scalar::I32 => i32::from_le_bytes(bytes4) as f64,
+                    Scalar::U32 => u32::from_le_bytes(bytes4) as f64,
+                    Scalar::F32 => f32::from_le_bytes(bytes4) as f64,
+                    Scalar::F64 => f64::from_le_bytes(value),
+                })
+            }
+        }
+    }
+}
+
+// the values of one element, lists are returned whole
+fn read_values<'p>(body: &mut Body, element: &'p Element) -> io::Result<Vec<(&'p str, Vec<f64>)>> {
+    element
+        .properties
+        .iter()
+        .map(|property| match property {
+            Property::Scalar(name, scalar) => Ok((name.as_str(), vec![body.read(*scalar)?])),
+            Property::List(name, count, item) => {
+                let count = body.read(*count)? as usize;
+                let values = (0..count)
+                    .map(|_| body.read(*item))
+                    .collect::<io::Result<Vec<f64>>>()?;
+                Ok((name.as_str(), values))
+            }
+        })
+        .collect()
+}
+
+fn property_index(element: &Element, names: &[&str]) -> Option<usize> {
+    element
+        .properties
+        .iter()
+        .position(|property| match property {
+            Property::Scalar(name, _) => names.contains(&name.as_str()),
+            Property::List(..) => false,
+        })
+}
+
+#[allow(clippy::unnecessary_cast)] // only needed when Real is f32
+fn read_vertices(body: &mut Body, element: &Element, mesh: &mut Mesh) -> io::Result<()> {
+    let find = |names: &[&[&str]]| -> Option<Vec<usize>> {
+        names
+            .iter()
+            .map(|names| property_index(element, names))
+            .collect()
+    };
+    let positions =
+        find(&[&["x"], &["y"], &["z"]]).ok_or_else(|| invalid("vertices without x, y and z"))?;
+    let normals = find(&[&["nx"], &["ny"], &["nz"]]);
+    let uvs = find(&[
+        &["u", "s", "texture_u", "texture_s"],
+        &["v", "t", "texture_v", "texture_t"],
+    ]);
+    let colors = find(&[
+        &["red", "diffuse_red", "r"],
+        &["green", "diffuse_green", "g"],
+        &["blue", "diffuse_blue", "b"],
+    ]);
+    let scalar_type = |index: usize| match &element.properties[index] {
+        Property::Scalar(_, scalar) => *scalar,
+        Property::List(_, _, item) => *item,
+    };
+
+    for _ in 0..element.count {
+        let values = read_values(body, element)?;
+        let value = |index: usize| values[index].1[0] as Real;
+
+        mesh.positions.push(Point3::new(
+            value(positions[0]),
+            value(positions[1]),
+            value(positions[2]),
+        ));
+        if let Some(normals) = &normals {
+            mesh.normals.push(Vector3::new(
+                value(normals[0]),
+                value(normals[1]),
+                value(normals[2]),
+            ));
+        }
+        if let Some(uvs) = &uvs {
+            mesh.uvs.push([value(uvs[0]), value(uvs[1])]);
+        }
+        if let Some(colors) = &colors {
+            // integer colours are sRGB encoded like the images they come from, float ones
+            // are taken as linear
+            let channel = |index: usize| {
+                let value = values[index].1[0];
+                match scalar_type(index).normalize(value) {
+                    Some(encoded) => srgb_to_linear(encoded.clamp(0.0, 1.0) as Real),
+                    None => value as Real,
+                }
+            };
+            mesh.colors.push(Color3::new(
+                channel(colors[0]),
+                channel(colors[1]),
+                channel(colors[2]),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn read_faces(body: &mut Body, element: &Element, mesh: &mut Mesh) -> io::Result<()> {
+    let indices = element
+        .properties
+        .iter()
+        .position(|property| match property {
+            Property::List(name, _, _) => name == "vertex_indices" || name == "vertex_index",
+            Property::Scalar(..) => false,
+        })
+        .ok_or_else(|| invalid("faces without vertex_indices"))?;
+
+    for _ in 0..element.count {
+        let values = read_values(body, element)?;
+        let corners = values[indices]
+            .1
+            .iter()
+            .map(|&corner| {
+                if corner < 0.0 || corner.fract() != 0.0 {
+                    return Err(invalid(format!("{} isn't a vertex index", corner)));
+                }
+                Ok(corner as usize)
+            })
+            .collect::<io::Result<Vec<usize>>>()?;
+
+        // a fan around the first corner
+        for i in 2..corners.len() {
+            mesh.triangles
+                .push([corners[0], corners[i - 1], corners[i]]);
+        }
+    }
+
+    Ok(())
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,60 @@
+// Random numbers for rendering. Every thread has its own generator that the renderer reseeds
+// at the start of each row of each pass, so a render can be stopped and resumed from a
+// checkpoint and continue with exactly the samples it would have taken.
+
+use rand::{RngCore, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<Pcg64Mcg> = RefCell::new(Pcg64Mcg::from_entropy());
+}
+
+/// A handle to the current thread's generator, use it like `rand::thread_rng()`.
+#[derive(Clone, Copy)]
+pub struct RenderRng;
+
+pub fn rng() -> RenderRng {
+    RenderRng
+}
+
+/// Restarts the current thread's generator from `seed`.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = Pcg64Mcg::seed_from_u64(seed));
+}
+
+/// A seed that differs between runs, for renders that don't ask for a specific one.
+pub fn entropy_seed() -> u64 {
+    Pcg64Mcg::from_entropy().next_u64()
+}
+
+/// Mixes several values into one seed so neighbouring inputs give unrelated sequences.
+pub fn mix_seed(values: &[u64]) -> u64 {
+    // splitmix64 applied to each value in turn
+    let mut state: u64 = 0;
+    for value in values {
+        state = (state ^ value).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        state ^= state >> 31;
+    }
+    state
+}
+
+impl RngCore for RenderRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.with(|rng| rng.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RNG.with(|rng| rng.borrow_mut().next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RNG.with(|rng| rng.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        RNG.with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+    }
+}
@@ -1,13 +1,21 @@
 use crate::defs::Real;
-use crate::material::Material;
-use crate::math::{Point3, Vector3};
+use crate::material::Scatterer;
+use crate::math::{Color3, Point3, Vector3};
 
 pub struct IntersectionRecord<'record> {
     pub point: Point3,
     pub normal: Vector3,
     pub t: Real,
     pub front_face: bool,
-    pub material: &'record Material,
+    pub material: &'record dyn Scatterer,
+    pub emitted: Color3,
+    /// Texture coordinates of the point, zero on shapes that don't have any.
+    pub uv: [Real; 2],
+    /// Tints the material's base colour, e.g. from vertex colours. White unless the shape
+    /// has colours of its own.
+    pub color: Color3,
+    // how far the computed point may be from the true surface, see offset_ray_origin
+    pub error_bound: Real,
 }
 
 impl<'record> IntersectionRecord<'record> {
@@ -16,7 +24,7 @@ impl<'record> IntersectionRecord<'record> {
         normal: Vector3,
         t: Real,
         front_face: bool,
-        material: &'record Material,
+        material: &'record dyn Scatterer,
     ) -> Self {
         Self {
             point,
@@ -24,6 +32,30 @@ impl<'record> IntersectionRecord<'record> {
             t,
             front_face,
             material,
+            emitted: Color3::default(),
+            uv: [0.0, 0.0],
+            color: Color3::new(1.0, 1.0, 1.0),
+            error_bound: 0.0,
         }
     }
+
+    pub fn with_emission(mut self, emitted: Color3) -> Self {
+        self.emitted = emitted;
+        self
+    }
+
+    pub fn with_uv(mut self, uv: [Real; 2]) -> Self {
+        self.uv = uv;
+        self
+    }
+
+    pub fn with_color(mut self, color: Color3) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_error_bound(mut self, error_bound: Real) -> Self {
+        self.error_bound = error_bound;
+        self
+    }
 }
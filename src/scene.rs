@@ -1,23 +1,103 @@
 use crate::camera::Camera;
+use crate::checkpoint::Checkpoint;
 use crate::defs::Real;
 use crate::display::Canvas;
-use crate::material::Scatterer;
 use crate::math::Color3;
 use crate::math::Ray;
+use crate::random;
 use crate::records::IntersectionRecord;
-use crate::shapes::{Intersectable, Shape};
+use crate::shapes::Intersectable;
+use crate::stats::{self, Counter, RenderStats};
 
-use rand::{self, Rng};
+use log::{debug, info, trace, warn};
+use rand::Rng;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+/// Stops a render that's in progress, the render returns the image as far as it got. Clones
+/// share the same state so one can be handed to another thread or a signal handler.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// How far along a render is, see [`WorldRenderRequest::with_progress`].
+/// Rows are counted over every pass, with a time budget `total_rows` only covers the passes
+/// started so far.
+pub struct RenderProgress {
+    pub completed_rows: usize,
+    pub total_rows: usize,
+    pub elapsed: Duration,
+    pub time_budget: Option<Duration>,
+}
+
+impl RenderProgress {
+    /// Between 0 and 1.
+    pub fn fraction(&self) -> f64 {
+        if let Some(budget) = self.time_budget {
+            return (self.elapsed.as_secs_f64() / budget.as_secs_f64()).min(1.0);
+        }
+
+        if self.total_rows == 0 {
+            return 1.0;
+        }
+
+        self.completed_rows as f64 / self.total_rows as f64
+    }
+
+    /// Extrapolated from the time the completed rows took, `None` until a row is done.
+    pub fn estimated_remaining(&self) -> Option<Duration> {
+        if let Some(budget) = self.time_budget {
+            return Some(budget.saturating_sub(self.elapsed));
+        }
+
+        if self.completed_rows == 0 {
+            return None;
+        }
+
+        let per_row = self.elapsed.as_secs_f64() / self.completed_rows as f64;
+        let remaining_rows = self.total_rows.saturating_sub(self.completed_rows);
+        Some(Duration::from_secs_f64(per_row * remaining_rows as f64))
+    }
+}
+
+pub type ProgressCallback = Box<dyn Fn(&RenderProgress) + Send + Sync>;
+
+/// Settings for a single call to [`World::render`].
 pub struct WorldRenderRequest {
     samples_per_pixel: i64,
     ray_max_depth: i64,
     ray_step: i64,
     width: usize,
     height: usize,
+    progress: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
+    time_budget: Option<Duration>,
+    seed: Option<u64>,
+    scene_hash: u64,
+    checkpoint: Option<(PathBuf, Duration)>,
+    resume: Option<Checkpoint>,
 }
 
 impl WorldRenderRequest {
+    /// Every `ray_step`th pixel in each direction is rendered, the rest are left black.
     pub fn new(
         samples_per_pixel: i64,
         ray_max_depth: i64,
@@ -31,13 +111,97 @@ impl WorldRenderRequest {
             ray_step,
             width,
             height,
+            progress: None,
+            cancellation: None,
+            time_budget: None,
+            seed: None,
+            scene_hash: 0,
+            checkpoint: None,
+            resume: None,
         }
     }
+
+    /// Calls `progress` each time a row of the image is finished.
+    pub fn with_progress<F: Fn(&RenderProgress) + Send + Sync + 'static>(
+        mut self,
+        progress: F,
+    ) -> WorldRenderRequest {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Lets the render be stopped early through `cancellation`.
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> WorldRenderRequest {
+        self.cancellation = Some(cancellation);
+        self
+    }
+
+    /// Keeps adding a sample to every pixel until `budget` has passed instead of stopping at
+    /// `samples_per_pixel`.
+    pub fn with_time_budget(mut self, budget: Duration) -> WorldRenderRequest {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Renders the same image every time for a given seed, without one a new seed is picked
+    /// for each render.
+    pub fn with_seed(mut self, seed: u64) -> WorldRenderRequest {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Records which scene is rendered in checkpoints, see [`crate::scenefile::scene_hash`].
+    /// A checkpoint of another scene can't be resumed.
+    pub fn with_scene_hash(mut self, scene_hash: u64) -> WorldRenderRequest {
+        self.scene_hash = scene_hash;
+        self
+    }
+
+    /// Saves a [`Checkpoint`] to `path` whenever `interval` has passed since the last one, and
+    /// once more when the render stops.
+    pub fn with_checkpoint<P: Into<PathBuf>>(
+        mut self,
+        path: P,
+        interval: Duration,
+    ) -> WorldRenderRequest {
+        self.checkpoint = Some((path.into(), interval));
+        self
+    }
+
+    /// Continues from `checkpoint` instead of starting from an empty image. The checkpoint's
+    /// seed is used and its passes count towards `samples_per_pixel`. Rendering fails if it's
+    /// of another size, ray step or scene.
+    pub fn with_resume(mut self, checkpoint: Checkpoint) -> WorldRenderRequest {
+        self.resume = Some(checkpoint);
+        self
+    }
+
+    fn should_stop(&self, started: Instant) -> bool {
+        let cancelled = self
+            .cancellation
+            .as_ref()
+            .is_some_and(|cancellation| cancellation.is_cancelled());
+        let out_of_time = self
+            .time_budget
+            .is_some_and(|budget| started.elapsed() >= budget);
+
+        cancelled || out_of_time
+    }
 }
 
+/// What rays that don't hit any shape see.
+#[derive(Copy, Clone)]
+pub enum Background {
+    /// A gradient from white at the horizon to light blue straight up.
+    Sky,
+    Uniform(Color3),
+}
+
+/// A camera and the shapes it can see.
 pub struct World {
-    shapes: Vec<Shape>,
+    shapes: Vec<Arc<dyn Intersectable>>,
     camera: Camera,
+    background: Background,
 }
 
 impl World {
@@ -45,19 +209,32 @@ impl World {
         World {
             shapes: Vec::new(),
             camera,
+            background: Background::Sky,
         }
     }
 
-    pub fn add_shape(&mut self, shape: Shape) {
+    /// The background defaults to [`Background::Sky`].
+    pub fn set_background(&mut self, background: Background) {
+        self.background = background;
+    }
+
+    /// Adds a shape to the scene, shapes can't be removed once added.
+    pub fn add_shape<S: Intersectable + 'static>(&mut self, shape: S) {
+        self.shapes.push(Arc::new(shape));
+    }
+
+    /// Adds a shape that may also be used elsewhere, e.g. as the boundary of a medium.
+    pub fn add_shared_shape(&mut self, shape: Arc<dyn Intersectable>) {
         self.shapes.push(shape);
     }
 
-    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord> {
+    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord<'_>> {
         let mut closest_intersection: Option<IntersectionRecord> = None;
         let mut closest_t: Real = t_max;
 
+        stats::record(Counter::IntersectionTests, self.shapes.len() as u64);
         for shape in self.shapes.iter() {
-            let shape_intersection = shape.hit(&ray, t_min, closest_t);
+            let shape_intersection = shape.hit(ray, t_min, closest_t);
 
             if let Some(ref intersection) = shape_intersection {
                 closest_t = intersection.t;
@@ -73,15 +250,22 @@ impl World {
             return Color3::default();
         }
 
-        let shape_intersection = self.hit(ray, 0.001, Real::INFINITY);
+        // scattered rays start offset from the surface they left, see offset_ray_origin
+        let shape_intersection = self.hit(ray, 0.0, Real::INFINITY);
 
         if let Some(ref intersection) = shape_intersection {
-            let material_interaction = intersection.material.scatter(&ray, &intersection);
+            let material_interaction = intersection.material.scatter(ray, intersection);
+            let emitted = intersection.emitted + intersection.material.emitted(ray, intersection);
 
             if let Some(m) = material_interaction {
-                return m.attenuation * self.color_at(&m.scattered_ray, depth - 1);
+                stats::record(Counter::ScatterRays, 1);
+                return emitted + m.attenuation * self.color_at(&m.scattered_ray, depth - 1);
             }
-            return Color3::new(0.0, 0.0, 0.0);
+            return emitted;
+        }
+
+        if let Background::Uniform(color) = self.background {
+            return color;
         }
 
         let blue = Color3::new(0.5, 0.7, 1.0);
@@ -93,32 +277,167 @@ impl World {
         white * (1.0 - interp) + (blue * interp)
     }
 
+    /// Renders the scene as seen through the world's camera. Panics if the request resumes
+    /// from a checkpoint that doesn't match it, see [`World::render_to_checkpoint`].
     pub fn render(&self, render_request: WorldRenderRequest) -> Canvas {
-        let mut canvas = Canvas::new(render_request.width, render_request.height);
-
-        let mut rng = rand::thread_rng();
-
-        for y in (0..render_request.height)
-            .rev()
-            .step_by(render_request.ray_step as usize)
-        {
-            println!("Remaining scanlines: {}", y);
-            for x in (0..render_request.width).step_by(render_request.ray_step as usize) {
-                let mut color = Color3::default();
-                for _ in 0..render_request.samples_per_pixel {
+        self.render_with_stats(render_request).0
+    }
+
+    /// Same as [`World::render`] but also reports how many rays were cast and how long it took.
+    pub fn render_with_stats(&self, render_request: WorldRenderRequest) -> (Canvas, RenderStats) {
+        match self.render_to_checkpoint(render_request) {
+            Ok((state, stats)) => (state.accumulator.to_canvas(), stats),
+            Err(error) => panic!("Unable to resume the render: {}", error),
+        }
+    }
+
+    /// Same as [`World::render_with_stats`] but returns the raw samples, so they can be
+    /// resumed or merged with the samples of another render. Fails if the request resumes
+    /// from a checkpoint of another size, ray step or scene.
+    pub fn render_to_checkpoint(
+        &self,
+        mut render_request: WorldRenderRequest,
+    ) -> io::Result<(Checkpoint, RenderStats)> {
+        let now = Instant::now();
+        stats::take_counters();
+
+        let ray_step = render_request.ray_step as u64;
+        let mut state = match render_request.resume.take() {
+            Some(checkpoint) => {
+                checkpoint.check_matches(
+                    render_request.width,
+                    render_request.height,
+                    ray_step,
+                    render_request.scene_hash,
+                )?;
+                info!(
+                    "Resuming from pass {}, row {}",
+                    checkpoint.pass, checkpoint.row
+                );
+                checkpoint
+            }
+            None => {
+                let mut checkpoint = Checkpoint::new(
+                    render_request.width,
+                    render_request.height,
+                    render_request.seed.unwrap_or_else(random::entropy_seed),
+                );
+                checkpoint.ray_step = ray_step;
+                checkpoint.scene_hash = render_request.scene_hash;
+                checkpoint
+            }
+        };
+        state.samples_per_pixel = render_request.samples_per_pixel.max(0) as u64;
+
+        let mut rng = random::rng();
+
+        match render_request.time_budget {
+            Some(budget) => info!(
+                "Rendering {}x{} for {}s",
+                render_request.width,
+                render_request.height,
+                budget.as_secs_f64()
+            ),
+            None => info!(
+                "Rendering {}x{} with {} samples per pixel",
+                render_request.width, render_request.height, render_request.samples_per_pixel
+            ),
+        }
+
+        // every pass adds one sample to each pixel so stopping between rows still leaves an
+        // image where every pixel is an average of the samples it got
+        let step = render_request.ray_step as usize;
+        let rows_per_pass = render_request.height.div_ceil(step);
+        let passes = match render_request.time_budget {
+            Some(_) => u64::MAX,
+            None => render_request.samples_per_pixel.max(0) as u64,
+        };
+
+        let first_pass = state.pass;
+        let first_row = state.row as usize;
+        let mut last_checkpoint = Instant::now();
+        let mut completed_rows = 0;
+        let mut completed_passes = 0;
+        let mut stopped = false;
+
+        'passes: for pass in first_pass..passes {
+            let rows = (0..render_request.height).rev().step_by(step).enumerate();
+            let skipped_rows = if pass == first_pass { first_row } else { 0 };
+
+            for (row, y) in rows.skip(skipped_rows) {
+                if render_request.should_stop(now) {
+                    stopped = true;
+                    break 'passes;
+                }
+
+                // seeding every row means a resumed render takes the same samples it would
+                // have taken had it never stopped
+                random::seed(random::mix_seed(&[state.seed, pass, row as u64]));
+
+                trace!("Pass {}, remaining scanlines: {}", pass, y);
+                for x in (0..render_request.width).step_by(step) {
                     let px: Real = (x as Real + rng.gen_range(0.0..1.0))
                         / (render_request.width as Real - 1.0);
                     let py: Real = (y as Real + rng.gen_range(0.0..1.0))
                         / (render_request.height as Real - 1.0);
 
                     let r = self.camera.get_ray(px, py);
-                    color += self.color_at(&r, render_request.ray_max_depth);
+                    stats::record(Counter::CameraRays, 1);
+                    let color = self.color_at(&r, render_request.ray_max_depth);
+                    state.accumulator.add_sample(x, y, &color);
+                }
+
+                state.row = row as u64 + 1;
+                if state.row as usize == rows_per_pass {
+                    state.pass += 1;
+                    state.row = 0;
                 }
 
-                canvas.set_pixel(x, y, &color, render_request.samples_per_pixel);
+                if let Some((path, interval)) = &render_request.checkpoint {
+                    if last_checkpoint.elapsed() >= *interval {
+                        save_checkpoint(&state, path);
+                        last_checkpoint = Instant::now();
+                    }
+                }
+
+                completed_rows += 1;
+                if let Some(progress) = &render_request.progress {
+                    progress(&RenderProgress {
+                        completed_rows,
+                        total_rows: match render_request.time_budget {
+                            Some(_) => rows_per_pass * (pass - first_pass + 1) as usize,
+                            None => rows_per_pass * (passes - first_pass) as usize - first_row,
+                        },
+                        elapsed: now.elapsed(),
+                        time_budget: render_request.time_budget,
+                    });
+                }
             }
+
+            completed_passes += 1;
+        }
+
+        if let Some((path, _)) = &render_request.checkpoint {
+            save_checkpoint(&state, path);
         }
 
-        canvas
+        let cancelled = stopped
+            && render_request
+                .cancellation
+                .as_ref()
+                .is_some_and(|cancellation| cancellation.is_cancelled());
+
+        let mut stats = RenderStats::new(stats::take_counters(), now.elapsed());
+        stats.completed_passes = completed_passes;
+        stats.cancelled = cancelled;
+        Ok((state, stats))
+    }
+}
+
+// a failed checkpoint shouldn't end a render that's otherwise fine
+fn save_checkpoint(checkpoint: &Checkpoint, path: &PathBuf) {
+    match checkpoint.save(path) {
+        Ok(()) => debug!("Checkpoint saved to {}", path.display()),
+        Err(error) => warn!("Unable to save checkpoint to {}: {}", path.display(), error),
     }
 }
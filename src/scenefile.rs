@@ -0,0 +1,628 @@
+// Loading of scene files, including the parts of the format that are only conveniences for
+// writing scenes by hand. Included files are merged in and generators are expanded into plain
+// shapes, so the loaded configuration only contains what WorldBuilder and the animation code
+// know how to deal with. Named materials and object instances stay as references, WorldBuilder
+// creates each of them once and shares it.
+//
+// Files written for an older version of the format are migrated to the current one before
+// they're deserialized, one version at a time. Scenes and includes can be JSON, TOML, YAML or
+// RON, see sceneformat.
+
+use crate::configuration::{
+    Configuration, Generator, MaterialChoice, MaterialReference, SceneFragment, Shape, Transform,
+    CURRENT_VERSION,
+};
+use crate::defs::Real;
+use crate::sceneformat::{SceneError, SceneFormat};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const MAX_NESTING_DEPTH: usize = 16;
+
+// MIGRATIONS[n] turns a version n scene into a version n + 1 one
+const MIGRATIONS: [fn(&mut Value); CURRENT_VERSION as usize] = [
+    // versioning was introduced without changing anything else
+    |_| {},
+    move_custom_parameters,
+];
+
+/// Loads a scene file, resolving includes, generators and instances. Panics if the file or
+/// any of its includes can't be read or parsed.
+pub fn load(path: &str) -> Configuration {
+    let mut config: Configuration = read_scene(Path::new(path));
+
+    if let Some(includes) = config.include.take() {
+        let mut fragment = SceneFragment::default();
+        include_all(&mut fragment, Path::new(path), &includes, 0);
+
+        merge_into_config(&mut config, fragment);
+    }
+
+    expand(&mut config).unwrap_or_else(|error| panic!("Invalid scene {}: {}", path, error));
+
+    config
+}
+
+/// Replaces generators in the configuration with the shapes they produce and checks that
+/// every instance refers to an object. Fails with a message naming the generator if one of
+/// them can't be expanded.
+pub fn expand(config: &mut Configuration) -> io::Result<()> {
+    let mut shapes = std::mem::take(&mut config.shapes);
+
+    if let Some(generators) = config.generators.take() {
+        for (index, generator) in generators.iter().enumerate() {
+            let generated = generate(config, generator).map_err(|message| {
+                invalid_data(format!(
+                    "generators[{}] ({}): {}",
+                    index, generator.type_field, message
+                ))
+            })?;
+            shapes.extend(generated);
+        }
+    }
+
+    let objects = config.objects.clone().unwrap_or_default();
+    for shape in shapes.iter() {
+        check_instance(shape, &objects, 0).map_err(invalid_data)?;
+    }
+    config.shapes = shapes;
+
+    Ok(())
+}
+
+/// How many shapes the scene will have once its generators are expanded, counting each part
+/// of an object once however often it's instanced. Nothing is generated to count them, so
+/// it's cheap to check before expanding a scene that might be huge.
+pub fn shape_count(config: &Configuration) -> u64 {
+    let parts = config.objects.iter().flat_map(|objects| objects.values());
+    let generated = config.generators.iter().flatten().map(|generator| {
+        let count = |axis: usize| generator.count.get(axis).cloned().unwrap_or(1).max(0) as u64;
+        match &generator.type_field[..] {
+            "grid" => count(0).saturating_mul(count(1)).saturating_mul(count(2)),
+            _ => generator.count.first().cloned().unwrap_or(0).max(0) as u64,
+        }
+    });
+
+    generated
+        .chain(parts.map(|part| part.len() as u64))
+        .fold(config.shapes.len() as u64, u64::saturating_add)
+}
+
+/// Parses a scene without resolving includes, generators or instances, migrating it first if
+/// it was written for an older version of the format.
+pub fn parse(json: &str) -> io::Result<Configuration> {
+    parse_as(SceneFormat::Json, json)
+}
+
+/// Like [`parse`] for a scene or fragment in any of the supported formats.
+pub fn parse_as<T: DeserializeOwned>(format: SceneFormat, text: &str) -> io::Result<T> {
+    let mut scene = format.parse(text)?;
+    migrate(&mut scene)?;
+
+    serde_json::from_value(scene).map_err(|error| {
+        // the value no longer knows where a field came from, deserializing the text again finds
+        // the field or at least the object around it
+        let mut located = SceneError::new(&error.to_string());
+        if let Err(direct) = format.deserialize::<T>(text) {
+            located.line = direct.line;
+            located.column = direct.column;
+        }
+        located.into()
+    })
+}
+
+/// Rewrites a scene file in the format of the output's extension, migrating it on the way.
+/// Includes are left as references, comments are lost.
+pub fn convert(input: &Path, output: &Path) -> io::Result<()> {
+    let text = fs::read_to_string(input)?;
+    let mut scene = SceneFormat::from_path(input)
+        .parse(&text)
+        .map_err(|e| invalid_data(format!("{}: {}", input.display(), e)))?;
+    migrate(&mut scene)?;
+
+    fs::write(output, SceneFormat::from_path(output).write(&scene)?)
+}
+
+/// Brings a scene or an included fragment up to the current version of the format. Fails for
+/// one written for a newer version, which this build may misread.
+pub fn migrate(scene: &mut Value) -> io::Result<()> {
+    let object = match scene.as_object_mut() {
+        Some(object) => object,
+        None => return Ok(()),
+    };
+
+    let version = match object.get("version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| invalid_data(format!("Invalid version {}", version)))?,
+    };
+    if version > CURRENT_VERSION as u64 {
+        return Err(invalid_data(format!(
+            "The scene is version {} of the format but this build only reads up to version {}",
+            version, CURRENT_VERSION
+        )));
+    }
+
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        migration(scene);
+    }
+    if let Some(object) = scene.as_object_mut() {
+        object.insert("version".to_string(), CURRENT_VERSION.into());
+    }
+
+    Ok(())
+}
+
+/// A hash of everything in the scene that changes what the image looks like, so a checkpoint
+/// isn't resumed or merged with the samples of another scene. Render settings like the
+/// output path or the number of samples are left out, files the scene refers to are only
+/// hashed by their path.
+pub fn scene_hash(config: &Configuration) -> u64 {
+    let scene = serde_json::json!({
+        "aspectRatio": config.aspect_ratio,
+        "rayMaxDepth": config.ray_max_depth,
+        "camera": config.camera,
+        "shapes": config.shapes,
+        "include": config.include,
+        "materials": config.materials,
+        "objects": config.objects,
+        "generators": config.generators,
+    });
+
+    // maps are written with their keys sorted since materials and objects come from hash
+    // maps, then the text is hashed with 64 bit FNV-1a
+    let mut text = String::new();
+    write_sorted(&scene, &mut text);
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn write_sorted(value: &Value, text: &mut String) {
+    match value {
+        Value::Object(values) => {
+            let mut keys: Vec<&String> = values.keys().collect();
+            keys.sort();
+            text.push('{');
+            for key in keys {
+                text.push_str(&Value::from(key.as_str()).to_string());
+                text.push(':');
+                write_sorted(&values[key.as_str()], text);
+                text.push(',');
+            }
+            text.push('}');
+        }
+        Value::Array(values) => {
+            text.push('[');
+            for value in values {
+                write_sorted(value, text);
+                text.push(',');
+            }
+            text.push(']');
+        }
+        value => text.push_str(&value.to_string()),
+    }
+}
+
+// version 1 accepted any field on shapes and materials and kept the unknown ones as custom
+// parameters, version 2 only takes them from a "parameters" object
+fn move_custom_parameters(scene: &mut Value) {
+    for shape in children(scene.get_mut("shapes")) {
+        migrate_v1_shape(shape);
+    }
+    for object in children(scene.get_mut("objects")) {
+        for shape in children(Some(object)) {
+            migrate_v1_shape(shape);
+        }
+    }
+    for material in children(scene.get_mut("materials")) {
+        move_unknown_fields(material, &V1_MATERIAL_FIELDS);
+    }
+    for generator in children(scene.get_mut("generators")) {
+        if let Some(shape) = generator.get_mut("shape") {
+            migrate_v1_shape(shape);
+        }
+        for choice in children(generator.get_mut("materials")) {
+            if let Some(material) = choice.get_mut("material") {
+                move_unknown_fields(material, &V1_MATERIAL_FIELDS);
+            }
+        }
+    }
+}
+
+// the fields shapes and materials had in version 1, everything else was a custom parameter.
+// "parameters" counts as a field so scenes that already use the object are left alone.
+const V1_SHAPE_FIELDS: [&str; 15] = [
+    "type",
+    "material",
+    "transform",
+    "object",
+    "boundary",
+    "density",
+    "path",
+    "resolution",
+    "densityScale",
+    "emissionScale",
+    "temperatureScale",
+    "normals",
+    "pointRadius",
+    "keyframes",
+    "parameters",
+];
+const V1_MATERIAL_FIELDS: [&str; 9] = [
+    "type",
+    "diffuse",
+    "fuzz",
+    "refractionIndex",
+    "albedo",
+    "anisotropy",
+    "emission",
+    "twoSided",
+    "parameters",
+];
+
+fn migrate_v1_shape(shape: &mut Value) {
+    move_unknown_fields(shape, &V1_SHAPE_FIELDS);
+    if let Some(boundary) = shape.get_mut("boundary") {
+        migrate_v1_shape(boundary);
+    }
+    // named materials are strings and are left alone
+    if let Some(material) = shape.get_mut("material") {
+        move_unknown_fields(material, &V1_MATERIAL_FIELDS);
+    }
+}
+
+fn move_unknown_fields(value: &mut Value, fields: &[&str]) {
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => return,
+    };
+    // a "parameters" field that isn't an object is left for deserializing to complain about
+    if object
+        .get("parameters")
+        .is_some_and(|parameters| !parameters.is_object())
+    {
+        return;
+    }
+
+    let unknown: Vec<String> = object
+        .keys()
+        .filter(|key| !fields.contains(&key.as_str()))
+        .cloned()
+        .collect();
+    for key in unknown {
+        let parameter = object.remove(&key).unwrap_or_default();
+        object
+            .entry("parameters")
+            .or_insert_with(|| Value::Object(Default::default()))[&key] = parameter;
+    }
+}
+
+// the entries of an array or the values of an object
+fn children(value: Option<&mut Value>) -> Vec<&mut Value> {
+    match value {
+        Some(Value::Array(values)) => values.iter_mut().collect(),
+        Some(Value::Object(values)) => values.values_mut().collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_scene<T: DeserializeOwned>(path: &Path) -> T {
+    let text =
+        fs::read_to_string(path).unwrap_or_else(|_| panic!("Unable to open {}", path.display()));
+
+    parse_as(SceneFormat::from_path(path), &text)
+        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+fn include_all(fragment: &mut SceneFragment, from: &Path, includes: &[String], depth: usize) {
+    assert!(depth < MAX_NESTING_DEPTH, "Includes nested too deeply");
+
+    let base_directory = from.parent().unwrap_or_else(|| Path::new(""));
+
+    for include in includes.iter() {
+        let include_path = base_directory.join(include);
+        let mut included: SceneFragment = read_scene(&include_path);
+
+        if let Some(nested_includes) = included.include.take() {
+            include_all(&mut included, &include_path, &nested_includes, depth + 1);
+        }
+
+        merge_fragments(fragment, included);
+    }
+}
+
+// entries already in the target win over included ones with the same name
+fn merge_maps<T>(target: &mut Option<HashMap<String, T>>, source: Option<HashMap<String, T>>) {
+    if let Some(source) = source {
+        let target = target.get_or_insert_with(HashMap::new);
+        for (name, value) in source {
+            target.entry(name).or_insert(value);
+        }
+    }
+}
+
+fn merge_fragments(target: &mut SceneFragment, source: SceneFragment) {
+    merge_maps(&mut target.materials, source.materials);
+    merge_maps(&mut target.objects, source.objects);
+    target.shapes.extend(source.shapes);
+    target
+        .generators
+        .get_or_insert_with(Vec::new)
+        .extend(source.generators.unwrap_or_default());
+}
+
+fn merge_into_config(config: &mut Configuration, fragment: SceneFragment) {
+    merge_maps(&mut config.materials, fragment.materials);
+    merge_maps(&mut config.objects, fragment.objects);
+    config.shapes.extend(fragment.shapes);
+    config
+        .generators
+        .get_or_insert_with(Vec::new)
+        .extend(fragment.generators.unwrap_or_default());
+}
+
+// instances stay in the scene and are built by WorldBuilder, which shares one copy of each
+// object's geometry between them, so they're only checked here
+fn check_instance(
+    shape: &Shape,
+    objects: &HashMap<String, Vec<Shape>>,
+    depth: usize,
+) -> Result<(), String> {
+    if shape.type_field != "instance" {
+        return Ok(());
+    }
+
+    if depth >= MAX_NESTING_DEPTH {
+        return Err(String::from("instances nested too deeply"));
+    }
+
+    let name = shape
+        .object
+        .as_ref()
+        .ok_or_else(|| String::from("an instance has no object"))?;
+    let object = objects
+        .get(name)
+        .ok_or_else(|| format!("unknown object {}", name))?;
+
+    vector_or(&shape.transform.position, [0.0, 0.0, 0.0])
+        .map_err(|message| format!("instance of {}: position {}", name, message))?;
+    let scale = shape.transform.size.first().cloned().unwrap_or(1.0);
+    if scale == 0.0 || !scale.is_finite() {
+        return Err(format!(
+            "instance of {}: size {} isn't a scale",
+            name, scale
+        ));
+    }
+
+    for part in object.iter() {
+        check_instance(part, objects, depth + 1)?;
+    }
+
+    Ok(())
+}
+
+fn vector_or(values: &[Real], default: [Real; 3]) -> Result<[Real; 3], String> {
+    match values.len() {
+        0 => Ok(default),
+        3 => Ok([values[0], values[1], values[2]]),
+        count => Err(format!("needs 3 values, not {}", count)),
+    }
+}
+
+// shapes without a position or size of their own are at the origin at their natural size
+fn transform_values(transform: &mut Transform, offset: &[Real; 3], scale: Real) {
+    if transform.position.is_empty() {
+        transform.position = offset.to_vec();
+    } else {
+        for (axis, position) in transform.position.iter_mut().enumerate() {
+            *position = offset[axis] + *position * scale;
+        }
+    }
+    if transform.size.is_empty() {
+        transform.size = vec![scale];
+    } else {
+        for size in transform.size.iter_mut() {
+            *size *= scale;
+        }
+    }
+}
+
+fn transform_shape(shape: &mut Shape, offset: &[Real; 3], scale: Real) {
+    transform_values(&mut shape.transform, offset, scale);
+
+    if let Some(boundary) = &mut shape.boundary {
+        transform_shape(boundary, offset, scale);
+    }
+
+    if let Some(keyframes) = &mut shape.keyframes {
+        for keyframe in keyframes.iter_mut() {
+            if let Some(position) = &mut keyframe.position {
+                for (axis, position) in position.iter_mut().enumerate() {
+                    *position = offset[axis] + *position * scale;
+                }
+            }
+            if let Some(size) = &mut keyframe.size {
+                for size in size.iter_mut() {
+                    *size *= scale;
+                }
+            }
+        }
+    }
+}
+
+fn generate(config: &Configuration, generator: &Generator) -> Result<Vec<Shape>, String> {
+    let mut rng = match generator.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let positions = match &generator.type_field[..] {
+        "grid" => grid_positions(generator, &mut rng)?,
+        "scatter" => scatter_positions(generator, &mut rng)?,
+        _ => return Err(String::from("unsupported generator type")),
+    };
+    if let Some(choices) = &generator.materials {
+        check_material_choices(choices)?;
+    }
+    let template_position = vector_or(&generator.shape.transform.position, [0.0, 0.0, 0.0])
+        .map_err(|message| format!("shape position {}", message))?;
+    if let Some(exclusion) = generator
+        .exclude
+        .iter()
+        .flatten()
+        .find(|exclusion| exclusion.center.len() != 3)
+    {
+        return Err(format!(
+            "exclude center needs 3 values, not {}",
+            exclusion.center.len()
+        ));
+    }
+
+    Ok(positions
+        .into_iter()
+        .filter(|position| !is_excluded(generator, &template_position, position))
+        .map(|position| {
+            // copies are placed relative to the template shape
+            let mut shape = generator.shape.clone();
+            transform_shape(&mut shape, &position, 1.0);
+
+            if let Some(choices) = &generator.materials {
+                shape.material = choose_material(config, choices, &mut rng);
+            }
+
+            shape
+        })
+        .collect())
+}
+
+fn grid_positions(generator: &Generator, rng: &mut StdRng) -> Result<Vec<[Real; 3]>, String> {
+    let count = |axis: usize| generator.count.get(axis).cloned().unwrap_or(1);
+    let spacing = vector_or(generator.spacing.as_deref().unwrap_or(&[]), [1.0, 1.0, 1.0])
+        .map_err(|message| format!("spacing {}", message))?;
+    let jitter = vector_or(generator.jitter.as_deref().unwrap_or(&[]), [0.0, 0.0, 0.0])
+        .map_err(|message| format!("jitter {}", message))?;
+
+    let mut positions = Vec::new();
+    for x in 0..count(0) {
+        for y in 0..count(1) {
+            for z in 0..count(2) {
+                let cell = [x as Real, y as Real, z as Real];
+                let mut position = [0.0; 3];
+                for axis in 0..3 {
+                    position[axis] =
+                        (cell[axis] + jitter[axis] * rng.gen_range(0.0..1.0)) * spacing[axis];
+                }
+                positions.push(position);
+            }
+        }
+    }
+
+    Ok(positions)
+}
+
+fn scatter_positions(generator: &Generator, rng: &mut StdRng) -> Result<Vec<[Real; 3]>, String> {
+    let count = generator.count.first().cloned().unwrap_or(0);
+    let min = vector_or(generator.min.as_deref().unwrap_or(&[]), [0.0, 0.0, 0.0])
+        .map_err(|message| format!("min {}", message))?;
+    let max = vector_or(generator.max.as_deref().unwrap_or(&[]), [1.0, 1.0, 1.0])
+        .map_err(|message| format!("max {}", message))?;
+
+    Ok((0..count)
+        .map(|_| {
+            let mut position = [0.0; 3];
+            for axis in 0..3 {
+                position[axis] = min[axis] + (max[axis] - min[axis]) * rng.gen_range(0.0..1.0);
+            }
+            position
+        })
+        .collect())
+}
+
+fn is_excluded(generator: &Generator, template_position: &[Real; 3], position: &[Real; 3]) -> bool {
+    generator.exclude.iter().flatten().any(|exclusion| {
+        let distance_squared: Real = (0..3)
+            .map(|axis| {
+                let d = template_position[axis] + position[axis] - exclusion.center[axis];
+                d * d
+            })
+            .sum();
+
+        distance_squared < exclusion.radius * exclusion.radius
+    })
+}
+
+// gen_range panics on empty ranges, so they're caught before any material is chosen
+fn check_material_choices(choices: &[MaterialChoice]) -> Result<(), String> {
+    if choices
+        .iter()
+        .any(|choice| !choice.weight.is_finite() || choice.weight < 0.0)
+    {
+        return Err(String::from("material weights can't be negative"));
+    }
+    let total_weight: Real = choices.iter().map(|choice| choice.weight).sum();
+    if total_weight <= 0.0 {
+        return Err(String::from("material weights add up to 0"));
+    }
+
+    for choice in choices.iter() {
+        for (name, range) in [
+            ("diffuseRange", &choice.diffuse_range),
+            ("fuzzRange", &choice.fuzz_range),
+        ]
+        .iter()
+        {
+            match range.as_deref() {
+                None => {}
+                Some([low, high]) if low <= high => {}
+                Some(_) => return Err(format!("{} needs a low and a high value", name)),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn choose_material(
+    config: &Configuration,
+    choices: &[MaterialChoice],
+    rng: &mut StdRng,
+) -> MaterialReference {
+    let total_weight: Real = choices.iter().map(|choice| choice.weight).sum();
+    let mut pick = rng.gen_range(0.0..total_weight);
+
+    let choice = choices
+        .iter()
+        .find(|choice| {
+            pick -= choice.weight;
+            pick < 0.0
+        })
+        .unwrap_or_else(|| choices.last().unwrap());
+
+    if choice.diffuse_range.is_none() && choice.fuzz_range.is_none() {
+        return choice.material.clone();
+    }
+
+    let mut material = choice.material.resolve(&config.materials).clone();
+
+    if let Some(range) = &choice.diffuse_range {
+        material.diffuse = Some((0..3).map(|_| rng.gen_range(range[0]..=range[1])).collect());
+    }
+    if let Some(range) = &choice.fuzz_range {
+        material.fuzz = Some(rng.gen_range(range[0]..=range[1]));
+    }
+
+    MaterialReference::Inline(material)
+}
@@ -0,0 +1,143 @@
+// The file formats scenes can be written in. Every format is read into the same JSON value,
+// so migrations, includes and the Configuration structure work the same whichever is used,
+// and errors are reported as "line L, column C: message" regardless of the format.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneFormat {
+    Json,
+    Toml,
+    Yaml,
+    Ron,
+}
+
+impl SceneFormat {
+    /// Picks the format from the file extension, anything unknown is read as JSON.
+    pub fn from_path(path: &Path) -> SceneFormat {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("toml") => SceneFormat::Toml,
+            Some("yaml") | Some("yml") => SceneFormat::Yaml,
+            Some("ron") => SceneFormat::Ron,
+            _ => SceneFormat::Json,
+        }
+    }
+
+    /// Parses a document without interpreting it as a scene yet.
+    pub fn parse(self, text: &str) -> Result<Value, SceneError> {
+        self.deserialize(text)
+    }
+
+    /// Deserializes a document straight into `T`. Unlike going through [`SceneFormat::parse`]
+    /// this knows where in the text a field of the wrong type is.
+    pub fn deserialize<T: DeserializeOwned>(self, text: &str) -> Result<T, SceneError> {
+        match self {
+            SceneFormat::Json => serde_json::from_str(text).map_err(|error| {
+                let (line, column) = (error.line(), error.column());
+                let message = error.to_string();
+                let suffix = format!(" at line {} column {}", line, column);
+                SceneError::at(line, column, message.trim_end_matches(&suffix))
+            }),
+            SceneFormat::Toml => toml::from_str(text).map_err(|error| {
+                let (line, column) = match error.span() {
+                    Some(span) => line_and_column(text, span.start),
+                    None => (0, 0),
+                };
+                SceneError::at(line, column, error.message())
+            }),
+            SceneFormat::Yaml => serde_yaml::from_str(text).map_err(|error| {
+                let (line, column) = error
+                    .location()
+                    .map_or((0, 0), |location| (location.line(), location.column()));
+                let message = error.to_string();
+                let suffix = format!(" at line {} column {}", line, column);
+                SceneError::at(line, column, message.trim_end_matches(&suffix))
+            }),
+            SceneFormat::Ron => {
+                // like in scenes read through a value, options don't need to be wrapped in Some
+                let options = ron::Options::default()
+                    .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME);
+                options.from_str(text).map_err(|error| {
+                    let start = error.span.start;
+                    SceneError::at(start.line, start.col, &error.code.to_string())
+                })
+            }
+        }
+    }
+
+    /// Writes a document in this format.
+    pub fn write(self, value: &Value) -> io::Result<String> {
+        match self {
+            SceneFormat::Json => serde_json::to_string_pretty(value).map_err(io::Error::other),
+            SceneFormat::Toml => toml::to_string(value).map_err(io::Error::other),
+            SceneFormat::Yaml => serde_yaml::to_string(value).map_err(io::Error::other),
+            SceneFormat::Ron => {
+                let config = ron::ser::PrettyConfig::new().struct_names(false);
+                ron::ser::to_string_pretty(value, config).map_err(io::Error::other)
+            }
+        }
+    }
+}
+
+/// An error in a scene document. Lines and columns count from 1, they're 0 when the parser
+/// didn't say where the error is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl SceneError {
+    pub fn new(message: &str) -> SceneError {
+        SceneError::at(0, 0, message)
+    }
+
+    pub fn at(line: usize, column: usize, message: &str) -> SceneError {
+        SceneError {
+            line,
+            column,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(
+                f,
+                "line {}, column {}: {}",
+                self.line, self.column, self.message
+            )
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+impl From<SceneError> for io::Error {
+    fn from(error: SceneError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+fn line_and_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+
+    (line, column)
+}
@@ -0,0 +1,591 @@
+// Rendering scenes submitted over HTTP, for tools that want to render without going through
+// scene files on disk.
+//
+//   POST   /jobs                 a scene in the Configuration format, answers 202 with the id
+//   GET    /jobs/<id>            the job's state and progress
+//   GET    /jobs/<id>/image.png  the finished image, also image.exr for linear radiance
+//   DELETE /jobs/<id>            cancels a job that hasn't finished, forgets one that has
+//
+// Jobs wait in a bounded queue and a fixed number of them render at a time, a submission that
+// doesn't fit in the queue is turned away with 503. Scenes bigger than the job limits are
+// turned away with 400, and only a fixed number of finished jobs are kept, the oldest are
+// forgotten first. Errors are answered as {"error": "..."}.
+//
+// Files the scene refers to, like meshes or voxel grids, have to be relative paths inside the
+// service's asset directory. Without an asset directory scenes can't refer to files at all.
+
+use crate::configuration::{Configuration, Shape};
+use crate::defs::Real;
+use crate::display::Canvas;
+use crate::scene::{CancellationToken, RenderProgress, WorldRenderRequest};
+use crate::scenefile;
+use crate::worldbuilder::WorldBuilder;
+
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Cursor, Read};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const DEFAULT_QUEUE_LIMIT: usize = 16;
+const DEFAULT_FINISHED_LIMIT: usize = 64;
+const MAX_SCENE_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Queued,
+    Rendering,
+    Done,
+    Cancelled,
+    Failed,
+}
+
+/// What `GET /jobs/<id>` answers with.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub id: u64,
+    pub state: JobState,
+    /// Between 0 and 1.
+    pub progress: f64,
+    pub estimated_remaining_seconds: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// The largest job the service accepts.
+#[derive(Debug, Copy, Clone)]
+pub struct JobLimits {
+    /// In pixels, for the width and the height.
+    pub max_size: usize,
+    pub max_samples_per_pixel: i64,
+    pub max_time_budget: Duration,
+    /// Counting the shapes generators produce, and object parts once.
+    pub max_shapes: u64,
+}
+
+impl Default for JobLimits {
+    fn default() -> JobLimits {
+        JobLimits {
+            max_size: 4096,
+            max_samples_per_pixel: 4096,
+            max_time_budget: Duration::from_secs(10 * 60),
+            max_shapes: 100_000,
+        }
+    }
+}
+
+struct Job {
+    config: Option<Configuration>,
+    status: JobStatus,
+    cancellation: CancellationToken,
+    canvas: Option<Arc<Canvas>>,
+}
+
+struct Jobs {
+    next_id: u64,
+    queue: VecDeque<u64>,
+    jobs: HashMap<u64, Job>,
+    // ids of jobs that are done, cancelled or failed, oldest first
+    finished: VecDeque<u64>,
+    finished_limit: usize,
+    shutting_down: bool,
+}
+
+impl Jobs {
+    fn finish(&mut self, id: u64) {
+        self.finished.push_back(id);
+        while self.finished.len() > self.finished_limit {
+            let oldest = self.finished.pop_front().unwrap();
+            self.jobs.remove(&oldest);
+        }
+    }
+}
+
+type SharedJobs = Arc<(Mutex<Jobs>, Condvar)>;
+
+/// Answers the HTTP API and renders the jobs submitted to it.
+pub struct RenderService {
+    server: Server,
+    address: SocketAddr,
+    queue_limit: usize,
+    concurrency: usize,
+    finished_limit: usize,
+    limits: JobLimits,
+    asset_directory: Option<PathBuf>,
+    shutdown: CancellationToken,
+}
+
+impl RenderService {
+    /// Listens on `address`, which should be a loopback address since the API has no
+    /// authentication.
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<RenderService> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let server = Server::from_listener(listener, None)
+            .map_err(|error| io::Error::other(error.to_string()))?;
+
+        Ok(RenderService {
+            server,
+            address,
+            queue_limit: DEFAULT_QUEUE_LIMIT,
+            concurrency: thread::available_parallelism().map_or(1, |cores| cores.get()),
+            finished_limit: DEFAULT_FINISHED_LIMIT,
+            limits: JobLimits::default(),
+            asset_directory: None,
+            shutdown: CancellationToken::new(),
+        })
+    }
+
+    /// How many jobs may wait for a render slot, not counting the ones rendering.
+    pub fn with_queue_limit(mut self, queue_limit: usize) -> RenderService {
+        self.queue_limit = queue_limit;
+        self
+    }
+
+    /// How many jobs render at once, one per core by default. Each render runs on a single
+    /// thread so fewer leaves cores idle.
+    pub fn with_concurrency(mut self, concurrency: usize) -> RenderService {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// How many finished jobs are kept for their images, 64 by default.
+    pub fn with_finished_limit(mut self, finished_limit: usize) -> RenderService {
+        self.finished_limit = finished_limit;
+        self
+    }
+
+    pub fn with_job_limits(mut self, limits: JobLimits) -> RenderService {
+        self.limits = limits;
+        self
+    }
+
+    /// Lets scenes refer to files in `directory`, by paths relative to it.
+    pub fn with_asset_directory<P: Into<PathBuf>>(mut self, directory: P) -> RenderService {
+        self.asset_directory = Some(directory.into());
+        self
+    }
+
+    /// Stops the service when `shutdown` is cancelled, jobs still rendering are cancelled too.
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> RenderService {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// The address clients should connect to, useful when bound to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Answers requests until shut down.
+    pub fn run(self) -> io::Result<()> {
+        info!(
+            "Serving on http://{} with {} render slots",
+            self.address, self.concurrency
+        );
+
+        let jobs: SharedJobs = Arc::new((
+            Mutex::new(Jobs {
+                next_id: 1,
+                queue: VecDeque::new(),
+                jobs: HashMap::new(),
+                finished: VecDeque::new(),
+                finished_limit: self.finished_limit,
+                shutting_down: false,
+            }),
+            Condvar::new(),
+        ));
+
+        let renderers: Vec<_> = (0..self.concurrency)
+            .map(|_| {
+                let jobs = Arc::clone(&jobs);
+                thread::spawn(move || render_jobs(&jobs))
+            })
+            .collect();
+
+        // polled so a shutdown is noticed without a request coming in
+        while !self.shutdown.is_cancelled() {
+            if let Some(request) = self.server.recv_timeout(Duration::from_millis(100))? {
+                let response = answer(request, &jobs, &self);
+                if let Err(error) = response {
+                    warn!("Unable to answer a request: {}", error);
+                }
+            }
+        }
+
+        info!("Shutting down");
+        {
+            let mut state = jobs.0.lock().unwrap();
+            state.shutting_down = true;
+            for job in state.jobs.values() {
+                job.cancellation.cancel();
+            }
+            jobs.1.notify_all();
+        }
+        for renderer in renderers {
+            let _ = renderer.join();
+        }
+
+        Ok(())
+    }
+}
+
+fn answer(mut request: Request, jobs: &SharedJobs, service: &RenderService) -> io::Result<()> {
+    let url = request.url().to_string();
+    let path: Vec<&str> = url
+        .split('?')
+        .next()
+        .unwrap_or("")
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect();
+
+    let response = match (request.method(), path.as_slice()) {
+        (Method::Post, ["jobs"]) => {
+            let mut body = String::new();
+            match request
+                .as_reader()
+                .take(MAX_SCENE_BYTES)
+                .read_to_string(&mut body)
+            {
+                Ok(_) => submit(&body, jobs, service),
+                Err(error) => error_response(400, &error.to_string()),
+            }
+        }
+        (Method::Get, ["jobs", id]) => with_job(id, jobs, |job| json_response(200, &job.status)),
+        (Method::Get, ["jobs", id, image]) => with_job(id, jobs, |job| image_response(job, image)),
+        (Method::Delete, ["jobs", id]) => delete(id, jobs),
+        (_, ["jobs"]) | (_, ["jobs", _]) | (_, ["jobs", _, _]) => {
+            error_response(405, "Method not allowed")
+        }
+        _ => error_response(404, "Not found"),
+    };
+
+    request.respond(response)
+}
+
+fn submit(body: &str, jobs: &SharedJobs, service: &RenderService) -> Response<Cursor<Vec<u8>>> {
+    let mut config = match scenefile::parse(body) {
+        Ok(config) => config,
+        Err(error) => return error_response(400, &format!("Invalid scene: {}", error)),
+    };
+    // there's no directory to find included files in
+    if config.include.is_some() {
+        return error_response(
+            400,
+            "Invalid scene: includes can't be used with the service",
+        );
+    }
+    let height = config.width as Real / config.aspect_ratio;
+    if config.width <= 0 || height.is_nan() || height < 1.0 {
+        return error_response(400, "Invalid scene: the image would be empty");
+    }
+    if config.frames.is_some() {
+        return error_response(
+            400,
+            "Invalid scene: animations can't be rendered by the service",
+        );
+    }
+    if let Err(error) = check_limits(&config, height, &service.limits) {
+        return error_response(400, &format!("The job is too big: {}", error));
+    }
+    if let Err(error) = scenefile::expand(&mut config) {
+        return error_response(400, &format!("Invalid scene: {}", error));
+    }
+    if let Err(error) = resolve_files(&mut config, service.asset_directory.as_deref()) {
+        return error_response(400, &format!("Invalid scene: {}", error));
+    }
+
+    let mut state = jobs.0.lock().unwrap();
+    if state.queue.len() >= service.queue_limit {
+        return error_response(503, "The queue is full, try again later");
+    }
+
+    let id = state.next_id;
+    state.next_id += 1;
+    state.jobs.insert(
+        id,
+        Job {
+            config: Some(config),
+            status: JobStatus {
+                id,
+                state: JobState::Queued,
+                progress: 0.0,
+                estimated_remaining_seconds: None,
+                error: None,
+            },
+            cancellation: CancellationToken::new(),
+            canvas: None,
+        },
+    );
+    state.queue.push_back(id);
+    jobs.1.notify_one();
+    info!("Job {} queued, {} waiting", id, state.queue.len());
+
+    json_response(202, &serde_json::json!({ "id": id }))
+}
+
+fn check_limits(config: &Configuration, height: Real, limits: &JobLimits) -> Result<(), String> {
+    if config.width as usize > limits.max_size || height > limits.max_size as Real {
+        return Err(format!(
+            "images can be up to {} pixels wide and high",
+            limits.max_size
+        ));
+    }
+    if config.samples_per_pixel > limits.max_samples_per_pixel {
+        return Err(format!(
+            "samplesPerPixel can be up to {}",
+            limits.max_samples_per_pixel
+        ));
+    }
+    // checked before the generators are expanded, which is where a small scene gets big
+    if scenefile::shape_count(config) > limits.max_shapes {
+        return Err(format!(
+            "scenes can have up to {} shapes",
+            limits.max_shapes
+        ));
+    }
+    if let Some(seconds) = config.time_budget_seconds {
+        let max_seconds = limits.max_time_budget.as_secs_f64();
+        if !seconds.is_finite() || seconds <= 0.0 || seconds > max_seconds {
+            return Err(format!(
+                "timeBudgetSeconds has to be more than 0 and up to {}",
+                max_seconds
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// points the files the scene refers to into the asset directory, they have to be relative
+// paths that stay inside it
+fn resolve_files(config: &mut Configuration, assets: Option<&Path>) -> Result<(), String> {
+    let mut paths: Vec<&mut String> = Vec::new();
+    paths.extend(config.camera.aperture_mask.as_mut());
+    let mut shapes: Vec<&mut Shape> = config.shapes.iter_mut().collect();
+    if let Some(objects) = config.objects.as_mut() {
+        shapes.extend(objects.values_mut().flatten());
+    }
+    while let Some(shape) = shapes.pop() {
+        paths.extend(shape.path.as_mut());
+        shapes.extend(shape.boundary.as_deref_mut());
+    }
+
+    for path in paths {
+        let assets = assets.ok_or_else(|| {
+            format!(
+                "{} can't be loaded, the service has no asset directory",
+                path
+            )
+        })?;
+        let inside = Path::new(path.as_str())
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !inside {
+            return Err(format!(
+                "{} has to be a path relative to the asset directory",
+                path
+            ));
+        }
+
+        *path = assets.join(path.as_str()).to_string_lossy().into_owned();
+    }
+
+    Ok(())
+}
+
+fn with_job<F>(id: &str, jobs: &SharedJobs, f: F) -> Response<Cursor<Vec<u8>>>
+where
+    F: FnOnce(&Job) -> Response<Cursor<Vec<u8>>>,
+{
+    let state = jobs.0.lock().unwrap();
+    match id.parse().ok().and_then(|id: u64| state.jobs.get(&id)) {
+        Some(job) => f(job),
+        None => error_response(404, "No such job"),
+    }
+}
+
+fn image_response(job: &Job, image: &str) -> Response<Cursor<Vec<u8>>> {
+    let canvas = match &job.canvas {
+        Some(canvas) => canvas,
+        None => return error_response(409, "The job has no image yet"),
+    };
+
+    let mut bytes = Cursor::new(Vec::new());
+    let (written, content_type) = match image {
+        "image.png" => (
+            canvas
+                .write_png(&mut bytes)
+                .map_err(|error| error.to_string()),
+            "image/png",
+        ),
+        "image.exr" => (
+            canvas
+                .write_exr(&mut bytes)
+                .map_err(|error| error.to_string()),
+            "image/x-exr",
+        ),
+        _ => return error_response(404, "Images are image.png or image.exr"),
+    };
+
+    match written {
+        Ok(()) => {
+            Response::from_data(bytes.into_inner()).with_header(content_type_header(content_type))
+        }
+        Err(error) => error_response(500, &error),
+    }
+}
+
+fn delete(id: &str, jobs: &SharedJobs) -> Response<Cursor<Vec<u8>>> {
+    let mut state = jobs.0.lock().unwrap();
+    let id = match id.parse().ok().filter(|id| state.jobs.contains_key(id)) {
+        Some(id) => id,
+        None => return error_response(404, "No such job"),
+    };
+
+    let job = state.jobs.get_mut(&id).unwrap();
+    match job.status.state {
+        JobState::Queued => {
+            job.status.state = JobState::Cancelled;
+            job.config = None;
+            let status = job.status.clone();
+            state.queue.retain(|queued| *queued != id);
+            state.finish(id);
+            return json_response(202, &status);
+        }
+        JobState::Rendering => job.cancellation.cancel(),
+        JobState::Done | JobState::Cancelled | JobState::Failed => {
+            state.jobs.remove(&id);
+            state.finished.retain(|finished| *finished != id);
+            return Response::from_data(Vec::new()).with_status_code(204);
+        }
+    }
+
+    json_response(202, &state.jobs[&id].status)
+}
+
+// takes jobs off the queue and renders them until the service shuts down
+fn render_jobs(jobs: &SharedJobs) {
+    loop {
+        let (id, config, cancellation) = {
+            let mut state = jobs.0.lock().unwrap();
+            loop {
+                if state.shutting_down {
+                    return;
+                }
+                if !state.queue.is_empty() {
+                    break;
+                }
+                state = jobs.1.wait(state).unwrap();
+            }
+
+            let id = state.queue.pop_front().unwrap();
+            let job = state.jobs.get_mut(&id).unwrap();
+            job.status.state = JobState::Rendering;
+            (id, job.config.take().unwrap(), job.cancellation.clone())
+        };
+
+        let now = Instant::now();
+        info!("Rendering job {}", id);
+
+        // a scene that makes the builder or the renderer panic fails the job, not the service
+        let result =
+            panic::catch_unwind(AssertUnwindSafe(|| render(config, id, jobs, &cancellation)));
+
+        let mut state = jobs.0.lock().unwrap();
+        let job = match state.jobs.get_mut(&id) {
+            Some(job) => job,
+            None => continue,
+        };
+        match result {
+            Ok(canvas) => {
+                job.status.state = if cancellation.is_cancelled() {
+                    JobState::Cancelled
+                } else {
+                    JobState::Done
+                };
+                job.status.estimated_remaining_seconds = None;
+                job.canvas = Some(Arc::new(canvas));
+                info!("Job {} finished. Took {}ms", id, now.elapsed().as_millis());
+            }
+            Err(panic) => {
+                let message = panic
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| {
+                        panic
+                            .downcast_ref::<&str>()
+                            .map(|message| message.to_string())
+                    })
+                    .unwrap_or_else(|| String::from("The render failed"));
+                warn!("Job {} failed: {}", id, message);
+
+                job.status.state = JobState::Failed;
+                job.status.error = Some(message);
+            }
+        }
+        state.finish(id);
+    }
+}
+
+fn render(
+    config: Configuration,
+    id: u64,
+    jobs: &SharedJobs,
+    cancellation: &CancellationToken,
+) -> Canvas {
+    let width = config.width as usize;
+    let height = (width as Real / config.aspect_ratio) as usize;
+    let world = WorldBuilder::from_config(&config);
+
+    let progress_jobs = Arc::clone(jobs);
+    let request = WorldRenderRequest::new(
+        config.samples_per_pixel,
+        config.ray_max_depth,
+        config.ray_step,
+        width,
+        height,
+    )
+    .with_cancellation(cancellation.clone())
+    .with_progress(move |progress: &RenderProgress| {
+        let mut state = progress_jobs.0.lock().unwrap();
+        if let Some(job) = state.jobs.get_mut(&id) {
+            job.status.progress = progress.fraction();
+            job.status.estimated_remaining_seconds = progress
+                .estimated_remaining()
+                .map(|remaining| remaining.as_secs_f64());
+        }
+    });
+    let request = match config.seed {
+        Some(seed) => request.with_seed(seed),
+        None => request,
+    };
+    let request = match config.time_budget_seconds {
+        Some(seconds) => request.with_time_budget(Duration::from_secs_f64(seconds)),
+        None => request,
+    };
+
+    world.render(request)
+}
+
+fn json_response<T: Serialize>(status: u16, body: &T) -> Response<Cursor<Vec<u8>>> {
+    Response::from_data(serde_json::to_vec(body).unwrap())
+        .with_status_code(status)
+        .with_header(content_type_header("application/json"))
+}
+
+fn error_response(status: u16, message: &str) -> Response<Cursor<Vec<u8>>> {
+    json_response(status, &serde_json::json!({ "error": message }))
+}
+
+fn content_type_header(content_type: &str) -> Header {
+    Header::from_bytes("Content-Type", content_type).unwrap()
+}
@@ -1,66 +1,297 @@
-use crate::defs::Real;
-use crate::math::{Point3, Ray};
+use crate::defs::{consts, Real};
+use crate::material::{DiffuseLight, Scatterer};
+use crate::math::{offset_ray_origin, Color3, Point3, Ray, Vector3};
+#[cfg(feature = "packet")]
+use crate::packet::{hit_sphere4, RayPacket4};
+use crate::random;
 use crate::records::IntersectionRecord;
 
-use crate::material::Material;
+use rand::Rng;
+use std::sync::Arc;
 
-pub enum Shape {
-    Sphere {
-        center: Point3,
-        radius: Real,
-        material: Material,
-    },
+/// Something a ray can hit. Implement this to add a shape of your own.
+pub trait Intersectable: Send + Sync {
+    /// Returns the closest intersection along the ray with `t` in [`t_min`, `t_max`].
+    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord<'_>>;
 }
 
-pub trait Intersectable {
-    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord>;
+pub struct Sphere {
+    pub center: Point3,
+    pub radius: Real,
+    pub material: Arc<dyn Scatterer>,
 }
 
-impl Intersectable for Shape {
-    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord> {
-        match self {
-            Shape::Sphere {
-                center,
-                radius,
-                material,
-            } => {
-                let oc = ray.origin - *center;
-
-                let a: Real = ray.direction.magnitude_squared();
-                let half_b: Real = oc.dot(&ray.direction);
-                let c: Real = oc.magnitude_squared() - radius * radius;
-
-                let discriminant: Real = half_b * half_b - (a * c);
-
-                if discriminant < 0.0 {
-                    return None;
-                }
-
-                let disc_sqrt: Real = discriminant.sqrt();
-                let mut root: Real = (-half_b - disc_sqrt) / a;
-                if root < t_min || t_max < root {
-                    root = (-half_b + disc_sqrt) / a;
-                    if root < t_min || t_max < root {
-                        return None;
-                    }
-                }
-
-                let intersection_point = ray.at(root);
-                let mut intersection_normal = (intersection_point - *center) / *radius;
-                let front_face = ray.direction.dot(&intersection_normal) < 0.0;
-                if !front_face {
-                    intersection_normal = -intersection_normal;
-                }
-                let intersection = IntersectionRecord::new(
-                    intersection_point,
-                    intersection_normal,
-                    root,
-                    front_face,
-                    material,
-                );
-
-                Some(intersection)
+/// Shapes that are hit as one, e.g. everything loaded from one file.
+pub struct ShapeGroup {
+    pub shapes: Vec<Arc<dyn Intersectable>>,
+}
+
+/// A shape moved and uniformly scaled without copying it, so every instance of an object
+/// shares the object's geometry.
+pub struct Instance {
+    pub shape: Arc<dyn Intersectable>,
+    pub offset: Vector3,
+    pub scale: Real,
+}
+
+/// A volume of constant density filling the inside of a closed boundary shape.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Intersectable>,
+    negative_inverse_density: Real,
+    phase_function: Arc<dyn Scatterer>,
+}
+
+impl ConstantMedium {
+    pub fn new(
+        boundary: Arc<dyn Intersectable>,
+        density: Real,
+        phase_function: Arc<dyn Scatterer>,
+    ) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            negative_inverse_density: -1.0 / density,
+            phase_function,
+        }
+    }
+}
+
+impl Sphere {
+    /// A small sphere standing in for a point light, since rays can only hit surfaces. A
+    /// sphere of radius r and radiance L has the intensity L * pi * r^2 in every direction.
+    pub fn point_light(center: Point3, intensity: Color3, radius: Real) -> Sphere {
+        Sphere {
+            center,
+            radius,
+            material: Arc::new(DiffuseLight {
+                emission: intensity / (consts::PI * radius * radius),
+                two_sided: true,
+            }),
+        }
+    }
+
+    /// Intersects four rays at once, giving the same results as [`Intersectable::hit`] would
+    /// for each of them.
+    #[cfg(feature = "packet")]
+    pub fn hit_packet(
+        &self,
+        packet: &RayPacket4,
+        t_min: Real,
+        t_max: Real,
+    ) -> [Option<IntersectionRecord<'_>>; 4] {
+        let t = hit_sphere4(packet, &self.center, self.radius, t_min, t_max);
+        let mut records = [None, None, None, None];
+
+        for (lane, record) in records.iter_mut().enumerate() {
+            if t.0[lane].is_finite() {
+                *record = Some(sphere_record(
+                    &self.center,
+                    self.radius,
+                    self.material.as_ref(),
+                    &packet.ray(lane),
+                    t.0[lane],
+                ));
             }
         }
+
+        records
+    }
+}
+
+impl Intersectable for Sphere {
+    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord<'_>> {
+        hit_sphere(
+            &self.center,
+            self.radius,
+            self.material.as_ref(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+}
+
+impl Intersectable for ShapeGroup {
+    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord<'_>> {
+        let mut closest: Option<IntersectionRecord> = None;
+        let mut closest_t = t_max;
+
+        for shape in self.shapes.iter() {
+            if let Some(intersection) = shape.hit(ray, t_min, closest_t) {
+                closest_t = intersection.t;
+                closest = Some(intersection);
+            }
+        }
+
+        closest
+    }
+}
+
+impl Intersectable for Instance {
+    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord<'_>> {
+        // scaling the direction along with the origin keeps t the same in both spaces
+        let local_ray = Ray::new(
+            (ray.origin - self.offset) / self.scale,
+            ray.direction / self.scale,
+        );
+        let mut record = self.shape.hit(&local_ray, t_min, t_max)?;
+
+        record.point = record.point * self.scale + self.offset;
+        // a negative scale mirrors the shape, which turns its normals around
+        record.normal = record.normal * self.scale.signum();
+        let largest = record.point[0]
+            .abs()
+            .max(record.point[1].abs())
+            .max(record.point[2].abs());
+        record.error_bound = record.error_bound * self.scale.abs() + largest * Real::EPSILON;
+        Some(record)
+    }
+}
+
+impl Intersectable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord<'_>> {
+        hit_constant_medium(
+            self.boundary.as_ref(),
+            self.negative_inverse_density,
+            self.phase_function.as_ref(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+}
+
+const SPHERE_ERROR_ULPS: Real = 16.0;
+
+fn hit_sphere<'a>(
+    center: &Point3,
+    radius: Real,
+    material: &'a dyn Scatterer,
+    ray: &Ray,
+    t_min: Real,
+    t_max: Real,
+) -> Option<IntersectionRecord<'a>> {
+    let oc = ray.origin - *center;
+
+    let a: Real = ray.direction.magnitude_squared();
+    let half_b: Real = oc.dot(&ray.direction);
+    let c: Real = oc.magnitude_squared() - radius * radius;
+
+    // "Precision Improvements for Ray/Sphere Intersection", Haines et al. the discriminant
+    // comes from the distance between the center and the ray so it doesn't lose precision
+    // far from the sphere, and the roots avoid subtracting nearly equal numbers
+    let closest = oc - (ray.direction * (half_b / a));
+    let discriminant: Real = a * (radius * radius - closest.magnitude_squared());
+
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let q: Real = -(half_b + discriminant.sqrt().copysign(half_b));
+    if q == 0.0 {
+        return None;
+    }
+
+    let (near, far) = ordered(c / q, q / a);
+
+    let mut root: Real = near;
+    if root < t_min || t_max < root {
+        root = far;
+        if root < t_min || t_max < root {
+            return None;
+        }
+    }
+
+    Some(sphere_record(center, radius, material, ray, root))
+}
+
+fn ordered(a: Real, b: Real) -> (Real, Real) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
     }
 }
+
+fn sphere_record<'a>(
+    center: &Point3,
+    radius: Real,
+    material: &'a dyn Scatterer,
+    ray: &Ray,
+    root: Real,
+) -> IntersectionRecord<'a> {
+    // project the point back onto the sphere so the error in the root doesn't carry over,
+    // what's left is the rounding of the center and radius
+    let to_point = ray.at(root) - *center;
+    let intersection_point = *center + to_point * (radius / to_point.magnitude());
+    let error_bound = SPHERE_ERROR_ULPS
+        * Real::EPSILON
+        * (center[0].abs().max(center[1].abs()).max(center[2].abs()) + radius);
+
+    let mut intersection_normal = (intersection_point - *center) / radius;
+    let front_face = ray.direction.dot(&intersection_normal) < 0.0;
+    if !front_face {
+        intersection_normal = -intersection_normal;
+    }
+
+    IntersectionRecord::new(
+        intersection_point,
+        intersection_normal,
+        root,
+        front_face,
+        material,
+    )
+    .with_error_bound(error_bound)
+}
+
+fn hit_constant_medium<'a>(
+    boundary: &dyn Intersectable,
+    negative_inverse_density: Real,
+    phase_function: &'a dyn Scatterer,
+    ray: &Ray,
+    t_min: Real,
+    t_max: Real,
+) -> Option<IntersectionRecord<'a>> {
+    // find where the ray enters and leaves the boundary, regardless of t_min/t_max,
+    // so rays starting inside the volume are handled too
+    let entry = boundary.hit(ray, -Real::INFINITY, Real::INFINITY)?;
+    // the exit is searched for from just past the entry, offset like a scattered ray so the
+    // boundary isn't hit at the entry again
+    let inward = if ray.direction.dot(&entry.normal) < 0.0 {
+        -entry.normal
+    } else {
+        entry.normal
+    };
+    let start = offset_ray_origin(&(entry.point + inward * entry.error_bound), &inward);
+    let exit = boundary.hit(&Ray::new(start, ray.direction), 0.0, Real::INFINITY)?;
+    let exit_t = (exit.point - ray.origin).dot(&ray.direction) / ray.direction.magnitude_squared();
+
+    let mut t_entry = Real::max(entry.t, t_min);
+    let t_exit = Real::min(exit_t, t_max);
+
+    if t_entry >= t_exit {
+        return None;
+    }
+
+    t_entry = Real::max(t_entry, 0.0);
+
+    let ray_length = ray.direction.magnitude();
+    let distance_inside_boundary = (t_exit - t_entry) * ray_length;
+
+    let mut rng = random::rng();
+    let hit_distance = negative_inverse_density * rng.gen_range(Real::EPSILON..1.0).ln();
+
+    if hit_distance > distance_inside_boundary {
+        return None;
+    }
+
+    let t = t_entry + hit_distance / ray_length;
+
+    // normal and front_face are arbitrary, phase functions don't use them
+    Some(IntersectionRecord::new(
+        ray.at(t),
+        Vector3::new(1.0, 0.0, 0.0),
+        t,
+        true,
+        phase_function,
+    ))
+}
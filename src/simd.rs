@@ -0,0 +1,380 @@
+// Four lane vectors of Real used by Vector3 and the ray packet code. Operations go through
+// SSE when Real is f32, AVX when Real is f64 and the target has it enabled (e.g. with
+// RUSTFLAGS="-C target-cpu=native"), and plain loops everywhere else.
+
+use crate::defs::Real;
+
+use std::ops::{Add, BitAnd, Div, Mul, Sub};
+
+#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+pub const BACKEND: &str = "sse";
+#[cfg(all(target_arch = "x86_64", feature = "f64", target_feature = "avx"))]
+pub const BACKEND: &str = "avx";
+#[cfg(not(any(
+    all(target_arch = "x86_64", not(feature = "f64")),
+    all(target_arch = "x86_64", feature = "f64", target_feature = "avx")
+)))]
+pub const BACKEND: &str = "scalar";
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C, align(16))]
+pub struct Real4(pub [Real; 4]);
+
+impl Real4 {
+    pub fn new(a: Real, b: Real, c: Real, d: Real) -> Real4 {
+        Real4([a, b, c, d])
+    }
+
+    pub fn splat(value: Real) -> Real4 {
+        Real4([value; 4])
+    }
+
+    pub fn sqrt(self) -> Real4 {
+        backend::sqrt(self)
+    }
+
+    pub fn min(self, other: Real4) -> Real4 {
+        backend::min(self, other)
+    }
+
+    pub fn max(self, other: Real4) -> Real4 {
+        backend::max(self, other)
+    }
+
+    pub fn lanes_less_than(self, other: Real4) -> [bool; 4] {
+        [
+            self.0[0] < other.0[0],
+            self.0[1] < other.0[1],
+            self.0[2] < other.0[2],
+            self.0[3] < other.0[3],
+        ]
+    }
+
+    // the sum of the first three lanes, vectors keep the fourth lane for padding
+    pub fn sum3(self) -> Real {
+        self.0[0] + self.0[1] + self.0[2]
+    }
+
+    /// Each lane's magnitude with the sign of the same lane in `sign`.
+    pub fn copysign(self, sign: Real4) -> Real4 {
+        backend::copysign(self, sign)
+    }
+
+    // comparisons with NaN are false, like for single values
+    pub fn less_than(self, other: Real4) -> Mask4 {
+        Mask4(backend::less_than(self, other))
+    }
+
+    pub fn less_equal(self, other: Real4) -> Mask4 {
+        Mask4(backend::less_equal(self, other))
+    }
+
+    pub fn not_equal(self, other: Real4) -> Mask4 {
+        Mask4(backend::not_equal(self, other))
+    }
+}
+
+/// The result of comparing two [`Real4`] lane by lane, every bit of a lane is set where the
+/// comparison held.
+#[derive(Copy, Clone, Debug)]
+pub struct Mask4(Real4);
+
+impl Mask4 {
+    /// Takes the lanes of `if_true` where the mask is set and those of `if_false` elsewhere.
+    pub fn select(self, if_true: Real4, if_false: Real4) -> Real4 {
+        backend::select(self.0, if_true, if_false)
+    }
+
+    pub fn lanes(self) -> [bool; 4] {
+        let set = |lane: Real| lane.to_bits() != 0;
+        [
+            set(self.0 .0[0]),
+            set(self.0 .0[1]),
+            set(self.0 .0[2]),
+            set(self.0 .0[3]),
+        ]
+    }
+}
+
+impl BitAnd for Mask4 {
+    type Output = Mask4;
+
+    fn bitand(self, other: Mask4) -> Mask4 {
+        Mask4(backend::and(self.0, other.0))
+    }
+}
+
+impl Add for Real4 {
+    type Output = Real4;
+
+    fn add(self, other: Real4) -> Real4 {
+        backend::add(self, other)
+    }
+}
+
+impl Sub for Real4 {
+    type Output = Real4;
+
+    fn sub(self, other: Real4) -> Real4 {
+        backend::sub(self, other)
+    }
+}
+
+impl Mul for Real4 {
+    type Output = Real4;
+
+    fn mul(self, other: Real4) -> Real4 {
+        backend::mul(self, other)
+    }
+}
+
+impl Div for Real4 {
+    type Output = Real4;
+
+    fn div(self, other: Real4) -> Real4 {
+        backend::div(self, other)
+    }
+}
+
+// always available so benchmarks can compare against it
+pub mod scalar {
+    use super::Real4;
+    use crate::defs::Real;
+
+    fn zip(a: Real4, b: Real4, op: impl Fn(Real, Real) -> Real) -> Real4 {
+        Real4([
+            op(a.0[0], b.0[0]),
+            op(a.0[1], b.0[1]),
+            op(a.0[2], b.0[2]),
+            op(a.0[3], b.0[3]),
+        ])
+    }
+
+    pub fn add(a: Real4, b: Real4) -> Real4 {
+        zip(a, b, |a, b| a + b)
+    }
+
+    pub fn sub(a: Real4, b: Real4) -> Real4 {
+        zip(a, b, |a, b| a - b)
+    }
+
+    pub fn mul(a: Real4, b: Real4) -> Real4 {
+        zip(a, b, |a, b| a * b)
+    }
+
+    pub fn div(a: Real4, b: Real4) -> Real4 {
+        zip(a, b, |a, b| a / b)
+    }
+
+    pub fn min(a: Real4, b: Real4) -> Real4 {
+        zip(a, b, Real::min)
+    }
+
+    pub fn max(a: Real4, b: Real4) -> Real4 {
+        zip(a, b, Real::max)
+    }
+
+    pub fn sqrt(a: Real4) -> Real4 {
+        Real4([a.0[0].sqrt(), a.0[1].sqrt(), a.0[2].sqrt(), a.0[3].sqrt()])
+    }
+
+    pub fn copysign(a: Real4, sign: Real4) -> Real4 {
+        zip(a, sign, Real::copysign)
+    }
+
+    // masks have every bit of a lane set or none of them, like the simd comparisons give
+    fn mask(a: Real4, b: Real4, compare: impl Fn(Real, Real) -> bool) -> Real4 {
+        zip(a, b, |a, b| {
+            if compare(a, b) {
+                Real::from_bits(!0)
+            } else {
+                0.0
+            }
+        })
+    }
+
+    pub fn less_than(a: Real4, b: Real4) -> Real4 {
+        mask(a, b, |a, b| a < b)
+    }
+
+    pub fn less_equal(a: Real4, b: Real4) -> Real4 {
+        mask(a, b, |a, b| a <= b)
+    }
+
+    pub fn not_equal(a: Real4, b: Real4) -> Real4 {
+        mask(a, b, |a, b| a != b)
+    }
+
+    pub fn and(a: Real4, b: Real4) -> Real4 {
+        zip(a, b, |a, b| Real::from_bits(a.to_bits() & b.to_bits()))
+    }
+
+    pub fn select(mask: Real4, if_true: Real4, if_false: Real4) -> Real4 {
+        let chosen = |lane: usize| {
+            if mask.0[lane].to_bits() != 0 {
+                if_true.0[lane]
+            } else {
+                if_false.0[lane]
+            }
+        };
+        Real4([chosen(0), chosen(1), chosen(2), chosen(3)])
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+mod backend {
+    use super::Real4;
+    use std::arch::x86_64::*;
+
+    macro_rules! binary {
+        ($name:ident, $intrinsic:ident) => {
+            #[inline(always)]
+            pub fn $name(a: Real4, b: Real4) -> Real4 {
+                let mut result = Real4::default();
+                // SAFETY: sse is part of the x86_64 baseline, so the intrinsic is available on
+                // every target this module is compiled for. Real4 is 16 byte aligned and four
+                // f32 long, which is what the aligned loads and the store need.
+                unsafe {
+                    let value = $intrinsic(_mm_load_ps(a.0.as_ptr()), _mm_load_ps(b.0.as_ptr()));
+                    _mm_store_ps(result.0.as_mut_ptr(), value);
+                }
+                result
+            }
+        };
+    }
+
+    binary!(add, _mm_add_ps);
+    binary!(sub, _mm_sub_ps);
+    binary!(mul, _mm_mul_ps);
+    binary!(div, _mm_div_ps);
+    binary!(min, _mm_min_ps);
+    binary!(max, _mm_max_ps);
+    binary!(less_than, _mm_cmplt_ps);
+    binary!(less_equal, _mm_cmple_ps);
+    // cmpneq is true for NaN, the ordered comparison below isn't
+    binary!(unordered_not_equal, _mm_cmpneq_ps);
+    binary!(ordered, _mm_cmpord_ps);
+    binary!(and, _mm_and_ps);
+    binary!(and_not, _mm_andnot_ps);
+    binary!(or, _mm_or_ps);
+
+    #[inline(always)]
+    pub fn not_equal(a: Real4, b: Real4) -> Real4 {
+        and(unordered_not_equal(a, b), ordered(a, b))
+    }
+
+    #[inline(always)]
+    pub fn select(mask: Real4, if_true: Real4, if_false: Real4) -> Real4 {
+        or(and(mask, if_true), and_not(mask, if_false))
+    }
+
+    #[inline(always)]
+    pub fn copysign(a: Real4, sign: Real4) -> Real4 {
+        let sign_bit = Real4::splat(-0.0);
+        or(and_not(sign_bit, a), and(sign_bit, sign))
+    }
+
+    #[inline(always)]
+    pub fn sqrt(a: Real4) -> Real4 {
+        let mut result = Real4::default();
+        // SAFETY: as for the binary operations, sse is always there on x86_64 and Real4 has
+        // the alignment and size the load and store need.
+        unsafe {
+            _mm_store_ps(
+                result.0.as_mut_ptr(),
+                _mm_sqrt_ps(_mm_load_ps(a.0.as_ptr())),
+            );
+        }
+        result
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "f64", target_feature = "avx"))]
+mod backend {
+    use super::Real4;
+    use std::arch::x86_64::*;
+
+    macro_rules! binary {
+        ($name:ident, $intrinsic:ident) => {
+            #[inline(always)]
+            pub fn $name(a: Real4, b: Real4) -> Real4 {
+                let mut result = Real4::default();
+                // SAFETY: this module is only compiled when avx is enabled for the whole
+                // target, so the intrinsic is available. Real4 holds four f64, the unaligned
+                // loads and the store read and write exactly those 32 bytes.
+                unsafe {
+                    let value =
+                        $intrinsic(_mm256_loadu_pd(a.0.as_ptr()), _mm256_loadu_pd(b.0.as_ptr()));
+                    _mm256_storeu_pd(result.0.as_mut_ptr(), value);
+                }
+                result
+            }
+        };
+    }
+
+    // comparisons take the predicate as a const argument
+    macro_rules! compare {
+        ($name:ident, $predicate:ident) => {
+            #[inline(always)]
+            pub fn $name(a: Real4, b: Real4) -> Real4 {
+                let mut result = Real4::default();
+                // SAFETY: as for the binary operations, avx is enabled for the whole target and
+                // Real4 is the 32 bytes the loads and the store touch.
+                unsafe {
+                    let value = _mm256_cmp_pd::<$predicate>(
+                        _mm256_loadu_pd(a.0.as_ptr()),
+                        _mm256_loadu_pd(b.0.as_ptr()),
+                    );
+                    _mm256_storeu_pd(result.0.as_mut_ptr(), value);
+                }
+                result
+            }
+        };
+    }
+
+    binary!(add, _mm256_add_pd);
+    binary!(sub, _mm256_sub_pd);
+    binary!(mul, _mm256_mul_pd);
+    binary!(div, _mm256_div_pd);
+    binary!(min, _mm256_min_pd);
+    binary!(max, _mm256_max_pd);
+    binary!(and, _mm256_and_pd);
+    binary!(and_not, _mm256_andnot_pd);
+    binary!(or, _mm256_or_pd);
+    compare!(less_than, _CMP_LT_OQ);
+    compare!(less_equal, _CMP_LE_OQ);
+    compare!(not_equal, _CMP_NEQ_OQ);
+
+    #[inline(always)]
+    pub fn select(mask: Real4, if_true: Real4, if_false: Real4) -> Real4 {
+        or(and(mask, if_true), and_not(mask, if_false))
+    }
+
+    #[inline(always)]
+    pub fn copysign(a: Real4, sign: Real4) -> Real4 {
+        let sign_bit = Real4::splat(-0.0);
+        or(and_not(sign_bit, a), and(sign_bit, sign))
+    }
+
+    #[inline(always)]
+    pub fn sqrt(a: Real4) -> Real4 {
+        let mut result = Real4::default();
+        // SAFETY: as for the binary operations, avx is enabled for the whole target and Real4
+        // is the 32 bytes the load and the store touch.
+        unsafe {
+            _mm256_storeu_pd(
+                result.0.as_mut_ptr(),
+                _mm256_sqrt_pd(_mm256_loadu_pd(a.0.as_ptr())),
+            );
+        }
+        result
+    }
+}
+
+#[cfg(not(any(
+    all(target_arch = "x86_64", not(feature = "f64")),
+    all(target_arch = "x86_64", feature = "f64", target_feature = "avx")
+)))]
+mod backend {
+    pub use super::scalar::*;
+}
@@ -0,0 +1,142 @@
+// Counters are kept per thread so recording them stays cheap, a render resets them when it
+// starts and collects them into a RenderStats when it's done.
+
+use serde::Serialize;
+use std::cell::Cell;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Copy, Clone)]
+pub enum Counter {
+    CameraRays,
+    ScatterRays,
+    IntersectionTests,
+    BvhNodesVisited,
+}
+
+const COUNTER_COUNT: usize = 4;
+
+thread_local! {
+    static COUNTERS: Cell<[u64; COUNTER_COUNT]> = const { Cell::new([0; COUNTER_COUNT]) };
+}
+
+/// Adds `amount` to one of the current thread's counters.
+pub fn record(counter: Counter, amount: u64) {
+    COUNTERS.with(|counters| {
+        let mut values = counters.get();
+        values[counter as usize] += amount;
+        counters.set(values);
+    });
+}
+
+/// Zeroes the current thread's counters and returns what they were.
+pub fn take_counters() -> Counters {
+    let values = COUNTERS.with(|counters| counters.replace([0; COUNTER_COUNT]));
+
+    Counters {
+        camera_rays: values[Counter::CameraRays as usize],
+        scatter_rays: values[Counter::ScatterRays as usize],
+        intersection_tests: values[Counter::IntersectionTests as usize],
+        bvh_nodes_visited: values[Counter::BvhNodesVisited as usize],
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Counters {
+    pub camera_rays: u64,
+    pub scatter_rays: u64,
+    /// Shapes and mesh triangles tested against a ray.
+    pub intersection_tests: u64,
+    /// Nodes of mesh bvhs whose bounds a ray was tested against.
+    pub bvh_nodes_visited: u64,
+}
+
+impl Counters {
+    pub fn total_rays(&self) -> u64 {
+        self.camera_rays + self.scatter_rays
+    }
+
+    pub fn merge(&mut self, other: &Counters) {
+        self.camera_rays += other.camera_rays;
+        self.scatter_rays += other.scatter_rays;
+        self.intersection_tests += other.intersection_tests;
+        self.bvh_nodes_visited += other.bvh_nodes_visited;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Phase {
+    pub name: String,
+    pub milliseconds: f64,
+}
+
+/// What a render did and how long it took, see [`crate::World::render_with_stats`].
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderStats {
+    #[serde(flatten)]
+    pub counters: Counters,
+    // camera rays plus the scattered rays that followed them, divided by the camera rays
+    pub average_path_length: f64,
+    pub rays_per_second: f64,
+    // each pass adds one sample to every pixel
+    pub completed_passes: u64,
+    pub cancelled: bool,
+    pub phases: Vec<Phase>,
+}
+
+impl RenderStats {
+    pub fn new(counters: Counters, render_time: Duration) -> RenderStats {
+        let paths = counters.camera_rays.max(1) as f64;
+        let seconds = render_time.as_secs_f64();
+
+        let mut stats = RenderStats {
+            counters,
+            average_path_length: (counters.camera_rays + counters.scatter_rays) as f64 / paths,
+            rays_per_second: if seconds > 0.0 {
+                counters.total_rays() as f64 / seconds
+            } else {
+                0.0
+            },
+            completed_passes: 0,
+            cancelled: false,
+            phases: Vec::new(),
+        };
+        stats.add_phase("render", render_time);
+        stats
+    }
+
+    pub fn add_phase(&mut self, name: &str, duration: Duration) {
+        self.phases.push(Phase {
+            name: name.to_string(),
+            milliseconds: duration.as_secs_f64() * 1000.0,
+        });
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counters = &self.counters;
+        writeln!(f, "Render statistics")?;
+        writeln!(f, "  camera rays:         {}", counters.camera_rays)?;
+        writeln!(f, "  scatter rays:        {}", counters.scatter_rays)?;
+        writeln!(f, "  intersection tests:  {}", counters.intersection_tests)?;
+        writeln!(f, "  bvh nodes visited:   {}", counters.bvh_nodes_visited)?;
+        writeln!(f, "  average path length: {:.2}", self.average_path_length)?;
+        writeln!(f, "  rays per second:     {:.0}", self.rays_per_second)?;
+        writeln!(f, "  completed passes:    {}", self.completed_passes)?;
+        if self.cancelled {
+            writeln!(f, "  cancelled")?;
+        }
+        for phase in self.phases.iter() {
+            writeln!(f, "  {} time: {:.1}ms", phase.name, phase.milliseconds)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+// Reads meshes from STL files, ASCII or binary. STL stores every triangle with its own three
+// corners, so corners at the same position are merged into one vertex to let the mesh be
+// shaded smooth. The facet normals are ignored, they are often missing or wrong.
+
+use crate::defs::Real;
+use crate::math::Point3;
+use crate::mesh::Mesh;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const HEADER_SIZE: usize = 80;
+const TRIANGLE_SIZE: usize = 50;
+
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Mesh> {
+    parse(&fs::read(path)?)
+}
+
+pub fn parse(bytes: &[u8]) -> io::Result<Mesh> {
+    let corners = if is_binary(bytes) {
+        read_binary(bytes)?
+    } else {
+        read_ascii(bytes)?
+    };
+
+    Ok(weld(&corners))
+}
+
+// binary files may start with "solid" too, so the size decides
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < HEADER_SIZE + 4 {
+        return false;
+    }
+    let count = u32::from_le_bytes([
+        bytes[HEADER_SIZE],
+        bytes[HEADER_SIZE + 1],
+        bytes[HEADER_SIZE + 2],
+        bytes[HEADER_SIZE + 3],
+    ]) as usize;
+
+    bytes.len() == HEADER_SIZE + 4 + count * TRIANGLE_SIZE
+}
+
+fn read_binary(bytes: &[u8]) -> io::Result<Vec<[f32; 3]>> {
+    let mut corners = Vec::new();
+    for triangle in bytes[HEADER_SIZE + 4..].chunks_exact(TRIANGLE_SIZE) {
+        // a normal, three corners and two bytes of attributes
+        for corner in triangle[12..48].chunks_exact(12) {
+            let coordinate = |i: usize| {
+                f32::from_le_bytes([
+                    corner[i * 4],
+                    corner[i * 4 + 1],
+                    corner[i * 4 + 2],
+                    corner[i * 4 + 3],
+                ])
+            };
+            corners.push([coordinate(0), coordinate(1), coordinate(2)]);
+        }
+    }
+
+    Ok(corners)
+}
+
+fn read_ascii(bytes: &[u8]) -> io::Result<Vec<[f32; 3]>> {
+    let text = std::str::from_utf8(bytes).map_err(invalid)?;
+    let mut words = text.split_ascii_whitespace();
+    if words.next() != Some("solid") {
+        return Err(invalid("not an STL file"));
+    }
+
+    let mut corners = Vec::new();
+    let mut facet_corners = 0;
+    while let Some(word) = words.next() {
+        match word {
+            "vertex" => {
+                let mut coordinate = || -> io::Result<f32> {
+                    words
+                        .next()
+                        .ok_or_else(|| invalid("file ends early"))?
+                        .parse()
+                        .map_err(invalid)
+                };
+                corners.push([coordinate()?, coordinate()?, coordinate()?]);
+                facet_corners += 1;
+            }
+            "endloop" => {
+                if facet_corners != 3 {
+                    return Err(invalid("only triangular facets are supported"));
+                }
+                facet_corners = 0;
+            }
+            // the rest is structure, names and the facet normals
+            _ => {}
+        }
+    }
+
+    Ok(corners)
+}
+
+#[allow(clippy::unnecessary_cast)] // only needed when Real is f32
+fn weld(corners: &[[f32; 3]]) -> Mesh {
+    let mut mesh = Mesh::default();
+    let mut vertices: HashMap<[u32; 3], usize> = HashMap::new();
+
+    let mut vertex = |corner: &[f32; 3]| {
+        // adding zero turns -0 into 0 so they share a key
+        let key = [
+            (corner[0] + 0.0).to_bits(),
+            (corner[1] + 0.0).to_bits(),
+            (corner[2] + 0.0).to_bits(),
+        ];
+        *vertices.entry(key).or_insert_with(|| {
+            mesh.positions.push(Point3::new(
+                corner[0] as Real,
+                corner[1] as Real,
+                corner[2] as Real,
+            ));
+            mesh.positions.len() - 1
+        })
+    };
+    let triangles = corners
+        .chunks_exact(3)
+        .map(|triangle| {
+            [
+                vertex(&triangle[0]),
+                vertex(&triangle[1]),
+                vertex(&triangle[2]),
+            ]
+        })
+        .collect();
+    mesh.triangles = triangles;
+
+    mesh
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
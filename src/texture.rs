@@ -0,0 +1,92 @@
+// Image textures for materials, looked up by the texture coordinates of an intersection.
+
+use crate::defs::Real;
+use crate::math::Color3;
+
+/// What happens to texture coordinates outside [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+/// An image of linear colours, sampled with bilinear filtering. The texture coordinate
+/// (0, 0) is the top left corner of the image.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<Color3>,
+    wrap_u: Wrap,
+    wrap_v: Wrap,
+}
+
+impl ImageTexture {
+    /// `texels` go row by row from the top, panics if there aren't `width * height` of them.
+    pub fn new(width: usize, height: usize, texels: Vec<Color3>) -> ImageTexture {
+        assert!(
+            width > 0 && height > 0 && texels.len() == width * height,
+            "Texture needs width * height texels"
+        );
+
+        ImageTexture {
+            width,
+            height,
+            texels,
+            wrap_u: Wrap::Repeat,
+            wrap_v: Wrap::Repeat,
+        }
+    }
+
+    /// Both directions repeat by default.
+    pub fn with_wrap(mut self, wrap_u: Wrap, wrap_v: Wrap) -> Self {
+        self.wrap_u = wrap_u;
+        self.wrap_v = wrap_v;
+        self
+    }
+
+    pub fn sample(&self, uv: [Real; 2]) -> Color3 {
+        // texel centres are at half coordinates
+        let x = uv[0] * self.width as Real - 0.5;
+        let y = uv[1] * self.height as Real - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |x: Real, y: Real| {
+            let column = wrap(x as i64, self.width, self.wrap_u);
+            let row = wrap(y as i64, self.height, self.wrap_v);
+            self.texels[row * self.width + column]
+        };
+
+        let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1.0, y0) * fx;
+        let bottom = texel(x0, y0 + 1.0) * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+fn wrap(index: i64, size: usize, wrap: Wrap) -> usize {
+    let size = size as i64;
+    let index = match wrap {
+        Wrap::Repeat => index.rem_euclid(size),
+        Wrap::MirroredRepeat => {
+            let period = index.rem_euclid(2 * size);
+            if period < size {
+                period
+            } else {
+                2 * size - 1 - period
+            }
+        }
+        Wrap::ClampToEdge => index.clamp(0, size - 1),
+    };
+
+    index as usize
+}
+
+/// Converts an sRGB encoded channel in [0, 1] to linear.
+pub fn srgb_to_linear(value: Real) -> Real {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
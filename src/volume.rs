@@ -0,0 +1,431 @@
+// Voxel grids for heterogeneous media.
+//
+// Grids can be loaded from two formats:
+//
+// JSON (.json) - all channels in one file, values stored x fastest, then y, then z:
+//   {
+//     "resolution": [nx, ny, nz],
+//     "density": [nx * ny * nz values],
+//     "temperature": [nx * ny * nz values in kelvin, optional],
+//     "emission": [nx * ny * nz * 3 rgb values, optional]
+//   }
+//
+// Raw (any other extension) - density only, nx * ny * nz little endian f32 values in the
+// same order. The resolution isn't stored in the file so it has to be given in the scene.
+//
+// Rays are scattered at collisions sampled with delta tracking. Ratio tracking estimates how
+// much light gets through a grid without scattering, for lights sampled through a medium.
+
+use crate::aabb::Aabb;
+use crate::defs::Real;
+use crate::material::Scatterer;
+use crate::math::{Color3, Point3, Ray, Vector3};
+use crate::random;
+use crate::records::IntersectionRecord;
+use crate::shapes::Intersectable;
+
+use rand::Rng;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct GridFile {
+    resolution: [usize; 3],
+    density: Vec<Real>,
+    temperature: Option<Vec<Real>>,
+    emission: Option<Vec<Real>>,
+}
+
+pub struct VoxelGrid {
+    resolution: [usize; 3],
+    density: Vec<Real>,
+    temperature: Option<Vec<Real>>,
+    emission: Option<Vec<Color3>>,
+}
+
+impl VoxelGrid {
+    pub fn new(
+        resolution: [usize; 3],
+        density: Vec<Real>,
+        temperature: Option<Vec<Real>>,
+        emission: Option<Vec<Color3>>,
+    ) -> VoxelGrid {
+        assert!(
+            resolution.iter().all(|&size| size > 0),
+            "Voxel grids need at least one voxel along every axis"
+        );
+        let voxel_count = resolution[0] * resolution[1] * resolution[2];
+
+        assert_eq!(density.len(), voxel_count, "Density channel size mismatch");
+        if let Some(temperature) = &temperature {
+            assert_eq!(
+                temperature.len(),
+                voxel_count,
+                "Temperature channel size mismatch"
+            );
+        }
+        if let Some(emission) = &emission {
+            assert_eq!(
+                emission.len(),
+                voxel_count,
+                "Emission channel size mismatch"
+            );
+        }
+
+        VoxelGrid {
+            resolution,
+            density,
+            temperature,
+            emission,
+        }
+    }
+
+    /// Fails with the path in the message if the file can't be read or its channels don't
+    /// have a value for every voxel.
+    pub fn load(path: &str, resolution: Option<[usize; 3]>) -> io::Result<VoxelGrid> {
+        let is_json = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension == "json");
+
+        let grid = if is_json {
+            Self::load_json(path)
+        } else {
+            match resolution {
+                Some(resolution) => Self::load_raw(path, resolution),
+                None => Err(invalid_data(String::from(
+                    "raw voxel grids require a resolution",
+                ))),
+            }
+        };
+        grid.map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path, error)))
+    }
+
+    fn load_json(path: &str) -> io::Result<VoxelGrid> {
+        let reader = BufReader::new(File::open(path)?);
+        let grid: GridFile = serde_json::from_reader(reader)?;
+
+        let voxel_count = voxel_count(grid.resolution)?;
+        check_channel("density", grid.density.len(), voxel_count)?;
+        if let Some(temperature) = &grid.temperature {
+            check_channel("temperature", temperature.len(), voxel_count)?;
+        }
+        if let Some(emission) = &grid.emission {
+            check_channel("emission", emission.len(), 3 * voxel_count)?;
+        }
+
+        let emission = grid.emission.map(|values| {
+            values
+                .chunks_exact(3)
+                .map(|rgb| Color3::new(rgb[0], rgb[1], rgb[2]))
+                .collect()
+        });
+
+        Ok(Self::new(
+            grid.resolution,
+            grid.density,
+            grid.temperature,
+            emission,
+        ))
+    }
+
+    fn load_raw(path: &str, resolution: [usize; 3]) -> io::Result<VoxelGrid> {
+        let bytes = fs::read(path)?;
+        let voxel_count = voxel_count(resolution)?;
+        if bytes.len() != 4 * voxel_count {
+            return Err(invalid_data(format!(
+                "{} bytes, a {}x{}x{} grid of f32 values needs {}",
+                bytes.len(),
+                resolution[0],
+                resolution[1],
+                resolution[2],
+                4 * voxel_count
+            )));
+        }
+
+        let density = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as Real)
+            .collect();
+
+        Ok(Self::new(resolution, density, None, None))
+    }
+
+    pub fn max_density(&self) -> Real {
+        self.density.iter().cloned().fold(0.0, Real::max)
+    }
+
+    pub fn has_emission(&self) -> bool {
+        self.temperature.is_some() || self.emission.is_some()
+    }
+
+    // local coordinates are in [0, 1] across the whole grid
+    pub fn density_at(&self, local: &Point3) -> Real {
+        self.interpolate(local, |index| self.density[index])
+    }
+
+    pub fn temperature_at(&self, local: &Point3) -> Real {
+        match &self.temperature {
+            Some(temperature) => self.interpolate(local, |index| temperature[index]),
+            None => 0.0,
+        }
+    }
+
+    pub fn emission_at(&self, local: &Point3) -> Color3 {
+        match &self.emission {
+            Some(emission) => Color3::new(
+                self.interpolate(local, |index| emission[index][0]),
+                self.interpolate(local, |index| emission[index][1]),
+                self.interpolate(local, |index| emission[index][2]),
+            ),
+            None => Color3::default(),
+        }
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (z * self.resolution[1] + y) * self.resolution[0] + x
+    }
+
+    fn interpolate<F: Fn(usize) -> Real>(&self, local: &Point3, value: F) -> Real {
+        let mut lower = [0usize; 3];
+        let mut upper = [0usize; 3];
+        let mut fraction: [Real; 3] = [0.0; 3];
+
+        // voxel values are stored at cell centers
+        for axis in 0..3 {
+            let size = self.resolution[axis];
+            let position = (local[axis] * size as Real - 0.5).max(0.0);
+            let cell = (position.floor() as usize).min(size - 1);
+
+            lower[axis] = cell;
+            upper[axis] = (cell + 1).min(size - 1);
+            fraction[axis] = (position - cell as Real).min(1.0);
+        }
+
+        let lerp = |a: Real, b: Real, t: Real| a + (b - a) * t;
+
+        let c00 = lerp(
+            value(self.index(lower[0], lower[1], lower[2])),
+            value(self.index(upper[0], lower[1], lower[2])),
+            fraction[0],
+        );
+        let c10 = lerp(
+            value(self.index(lower[0], upper[1], lower[2])),
+            value(self.index(upper[0], upper[1], lower[2])),
+            fraction[0],
+        );
+        let c01 = lerp(
+            value(self.index(lower[0], lower[1], upper[2])),
+            value(self.index(upper[0], lower[1], upper[2])),
+            fraction[0],
+        );
+        let c11 = lerp(
+            value(self.index(lower[0], upper[1], upper[2])),
+            value(self.index(upper[0], upper[1], upper[2])),
+            fraction[0],
+        );
+
+        lerp(
+            lerp(c00, c10, fraction[1]),
+            lerp(c01, c11, fraction[1]),
+            fraction[2],
+        )
+    }
+}
+
+pub struct GridMedium {
+    grid: VoxelGrid,
+    bounds: Aabb,
+    density_scale: Real,
+    majorant: Real,
+    emission_scale: Real,
+    temperature_scale: Real,
+    phase_function: Arc<dyn Scatterer>,
+}
+
+impl GridMedium {
+    pub fn new(
+        grid: VoxelGrid,
+        min: Point3,
+        max: Point3,
+        density_scale: Real,
+        emission_scale: Real,
+        temperature_scale: Real,
+        phase_function: Arc<dyn Scatterer>,
+    ) -> GridMedium {
+        let majorant = grid.max_density() * density_scale;
+
+        GridMedium {
+            grid,
+            bounds: Aabb::new(min, max),
+            density_scale,
+            majorant,
+            emission_scale,
+            temperature_scale,
+            phase_function,
+        }
+    }
+
+    fn to_local(&self, point: &Point3) -> Point3 {
+        let offset = point - self.bounds.min;
+        let size = self.bounds.max - self.bounds.min;
+
+        Point3::new(
+            offset[0] / size[0],
+            offset[1] / size[1],
+            offset[2] / size[2],
+        )
+    }
+
+    fn density_at(&self, point: &Point3) -> Real {
+        self.grid.density_at(&self.to_local(point)) * self.density_scale
+    }
+
+    fn emission_at(&self, point: &Point3) -> Color3 {
+        if !self.grid.has_emission() {
+            return Color3::default();
+        }
+
+        let local = self.to_local(point);
+
+        self.grid.emission_at(&local) * self.emission_scale
+            + blackbody_color(self.grid.temperature_at(&local)) * self.temperature_scale
+    }
+
+    /// The fraction of light that gets from `ray.at(t_min)` to `ray.at(t_max)` without being
+    /// scattered, estimated with ratio tracking. Unbiased but noisy, average several estimates.
+    pub fn transmittance(&self, ray: &Ray, t_min: Real, t_max: Real) -> Real {
+        let (t_entry, t_exit) = match self.bounds.hit_interval(ray, t_min, t_max) {
+            Some(interval) => interval,
+            None => return 1.0,
+        };
+        if self.majorant <= 0.0 {
+            return 1.0;
+        }
+
+        // the same tentative collisions as delta tracking, but instead of stopping at one each
+        // of them weighs the estimate down by the chance it would have been a real collision
+        let mut rng = random::rng();
+        let inverse_majorant = 1.0 / (self.majorant * ray.direction.magnitude());
+        let mut t = Real::max(t_entry, 0.0);
+        let mut transmittance = 1.0;
+
+        loop {
+            t -= rng.gen_range(Real::EPSILON..1.0).ln() * inverse_majorant;
+
+            if t >= t_exit {
+                return transmittance;
+            }
+
+            transmittance *= 1.0 - self.density_at(&ray.at(t)) / self.majorant;
+        }
+    }
+
+    // samples a collision distance by tracking against the homogeneous majorant and
+    // accepting each tentative collision with probability density / majorant
+    fn delta_tracking(&self, ray: &Ray, t_entry: Real, t_exit: Real) -> Option<Real> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+
+        let mut rng = random::rng();
+        let inverse_majorant = 1.0 / (self.majorant * ray.direction.magnitude());
+        let mut t = t_entry;
+
+        loop {
+            t -= rng.gen_range(Real::EPSILON..1.0).ln() * inverse_majorant;
+
+            if t >= t_exit {
+                return None;
+            }
+
+            if rng.gen_range(0.0..1.0) * self.majorant < self.density_at(&ray.at(t)) {
+                return Some(t);
+            }
+        }
+    }
+}
+
+impl Intersectable for GridMedium {
+    fn hit(&self, ray: &Ray, t_min: Real, t_max: Real) -> Option<IntersectionRecord<'_>> {
+        let (t_entry, t_exit) = self.bounds.hit_interval(ray, t_min, t_max)?;
+        let t_entry = Real::max(t_entry, 0.0);
+        let t = self.delta_tracking(ray, t_entry, t_exit)?;
+        let point = ray.at(t);
+
+        // normal and front_face are arbitrary, phase functions don't use them
+        Some(
+            IntersectionRecord::new(
+                point,
+                Vector3::new(1.0, 0.0, 0.0),
+                t,
+                true,
+                self.phase_function.as_ref(),
+            )
+            .with_emission(self.emission_at(&point)),
+        )
+    }
+}
+
+fn voxel_count(resolution: [usize; 3]) -> io::Result<usize> {
+    let count = resolution
+        .iter()
+        .try_fold(1usize, |count, &size| count.checked_mul(size));
+    match count {
+        Some(count) if count > 0 => Ok(count),
+        _ => Err(invalid_data(format!(
+            "a resolution of {}x{}x{} isn't a grid",
+            resolution[0], resolution[1], resolution[2]
+        ))),
+    }
+}
+
+fn check_channel(name: &str, length: usize, expected: usize) -> io::Result<()> {
+    if length == expected {
+        Ok(())
+    } else {
+        Err(invalid_data(format!(
+            "the {} channel has {} values instead of {}",
+            name, length, expected
+        )))
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// approximation of the normalized color of a black body at the given temperature in kelvin
+pub fn blackbody_color(kelvin: Real) -> Color3 {
+    if kelvin <= 0.0 {
+        return Color3::default();
+    }
+
+    let temperature = kelvin.clamp(1000.0, 40000.0) / 100.0;
+
+    let red = if temperature <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (temperature - 60.0).powf(-0.133_204_76)
+    };
+
+    let green = if temperature <= 66.0 {
+        99.470_8 * temperature.ln() - 161.119_57
+    } else {
+        288.122_16 * (temperature - 60.0).powf(-0.075_514_85)
+    };
+
+    let blue = if temperature >= 66.0 {
+        255.0
+    } else if temperature <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (temperature - 10.0).ln() - 305.044_8
+    };
+
+    let clamp = |c: Real| c.clamp(0.0, 255.0) / 255.0;
+
+    Color3::new(clamp(red), clamp(green), clamp(blue))
+}
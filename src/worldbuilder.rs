@@ -1,50 +1,289 @@
-use crate::camera::Camera;
-use crate::configuration::Configuration;
+use crate::camera::{ApertureMask, ApertureShape, Camera, CameraModel};
+use crate::configuration::{self, Configuration, MaterialReference};
 use crate::defs::Real;
-use crate::material::Material;
-use crate::math::{Color3, Point3, Vector3};
+use crate::gltfimport;
+use crate::material::{
+    Dielectric, DiffuseLight, HenyeyGreenstein, Isotropic, Lambertian, Metal, Scatterer,
+};
+use crate::math::{Color3, Matrix4, Point3, Vector3};
+use crate::mesh::TriangleMesh;
+use crate::ply;
 use crate::scene::World;
-use crate::shapes::Shape;
+use crate::shapes::{ConstantMedium, Instance, Intersectable, ShapeGroup, Sphere};
+use crate::stl;
+use crate::volume::{GridMedium, VoxelGrid};
 
-pub struct WorldBuilder;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Creates a shape from its scene description, `type` chooses which factory is used.
+pub type ShapeFactory =
+    Box<dyn Fn(&BuildContext, &configuration::Shape) -> Arc<dyn Intersectable> + Send + Sync>;
+
+/// Creates a material from its scene description, `type` chooses which factory is used.
+pub type MaterialFactory =
+    Box<dyn Fn(&configuration::Material) -> Arc<dyn Scatterer> + Send + Sync>;
+
+/// Builds a [`World`] from a scene [`Configuration`].
+///
+/// The built in shapes and materials are registered by [`WorldBuilder::new`], custom ones can
+/// be added with [`WorldBuilder::register_shape`] and [`WorldBuilder::register_material`] and
+/// are then available in scene files by the name they were registered with. Values they need
+/// that crayfish doesn't know about go in the `parameters` object of the shape or material
+/// description, other unknown fields are rejected.
+pub struct WorldBuilder {
+    shapes: HashMap<String, ShapeFactory>,
+    materials: HashMap<String, MaterialFactory>,
+}
+
+/// Passed to shape factories so they can create the materials and nested shapes they need.
+pub struct BuildContext<'a> {
+    builder: &'a WorldBuilder,
+    config: &'a Configuration,
+    named_materials: HashMap<String, Arc<dyn Scatterer>>,
+    // the geometry of each object, by name and the material its instances give it
+    objects: RefCell<HashMap<String, Arc<dyn Intersectable>>>,
+}
+
+impl<'a> BuildContext<'a> {
+    pub fn config(&self) -> &Configuration {
+        self.config
+    }
+
+    pub fn create_shape(&self, shape: &configuration::Shape) -> Arc<dyn Intersectable> {
+        let factory = self
+            .builder
+            .shapes
+            .get(&shape.type_field)
+            .unwrap_or_else(|| panic!("Unsupported shape type {}", shape.type_field));
+
+        factory(self, shape)
+    }
+
+    // named materials are created once and shared by every shape that uses them
+    pub fn create_material(&self, material: &MaterialReference) -> Arc<dyn Scatterer> {
+        match material {
+            MaterialReference::Named(name) => self
+                .named_materials
+                .get(name)
+                .unwrap_or_else(|| panic!("Unknown material {}", name))
+                .clone(),
+            MaterialReference::Inline(material) => self.builder.create_material(material),
+        }
+    }
+
+    /// The shapes of an object in the configuration's `objects`, created the first time an
+    /// instance asks for them. Parts without a material of their own take `material`.
+    pub fn create_object(
+        &self,
+        name: &str,
+        material: &MaterialReference,
+    ) -> Arc<dyn Intersectable> {
+        let key = format!("{}:{}", name, serde_json::to_string(material).unwrap());
+        if let Some(object) = self.objects.borrow().get(&key) {
+            return object.clone();
+        }
+
+        let parts = self
+            .config
+            .objects
+            .as_ref()
+            .and_then(|objects| objects.get(name))
+            .unwrap_or_else(|| panic!("Unknown object {}", name));
+        let shapes = parts
+            .iter()
+            .map(|part| {
+                if part.material == MaterialReference::default() {
+                    let mut part = part.clone();
+                    part.material = material.clone();
+                    self.create_shape(&part)
+                } else {
+                    self.create_shape(part)
+                }
+            })
+            .collect();
+
+        let object: Arc<dyn Intersectable> = Arc::new(ShapeGroup { shapes });
+        self.objects.borrow_mut().insert(key, object.clone());
+        object
+    }
+}
+
+impl Default for WorldBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl WorldBuilder {
+    pub fn new() -> WorldBuilder {
+        let mut builder = WorldBuilder {
+            shapes: HashMap::new(),
+            materials: HashMap::new(),
+        };
+
+        builder.register_shape("sphere", create_sphere);
+        builder.register_shape("constantMedium", create_constant_medium);
+        builder.register_shape("gridMedium", create_grid_medium);
+        builder.register_shape("gltf", create_gltf);
+        builder.register_shape("mesh", create_mesh);
+        builder.register_shape("instance", create_instance);
+
+        builder.register_material("lambertian", create_lambertian_material);
+        builder.register_material("metal", create_metal_material);
+        builder.register_material("dielectric", create_dielectric_material);
+        builder.register_material("isotropic", create_isotropic_material);
+        builder.register_material("henyeyGreenstein", create_henyey_greenstein_material);
+        builder.register_material("diffuseLight", create_diffuse_light_material);
+
+        builder
+    }
+
+    /// Panics if the configuration refers to unknown shape, material or camera types, or is
+    /// missing values they require.
     pub fn from_config(config: &Configuration) -> World {
+        Self::new().build(config)
+    }
+
+    /// Registering a name that's already in use replaces the existing factory.
+    pub fn register_shape<F>(&mut self, type_name: &str, factory: F)
+    where
+        F: Fn(&BuildContext, &configuration::Shape) -> Arc<dyn Intersectable>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.shapes.insert(type_name.to_string(), Box::new(factory));
+    }
+
+    /// Registering a name that's already in use replaces the existing factory.
+    pub fn register_material<F>(&mut self, type_name: &str, factory: F)
+    where
+        F: Fn(&configuration::Material) -> Arc<dyn Scatterer> + Send + Sync + 'static,
+    {
+        self.materials
+            .insert(type_name.to_string(), Box::new(factory));
+    }
+
+    pub fn build(&self, config: &Configuration) -> World {
         let camera = create_camera(config);
         let mut world = World::new(camera);
 
+        let context = self.context(config);
         for shape in config.shapes.iter() {
-            match &shape.type_field[..] {
-                "sphere" => world.add_shape(create_sphere(shape)),
-                _ => panic!("Unsupported shape type"),
-            }
+            world.add_shared_shape(context.create_shape(shape));
         }
 
         world
     }
+
+    fn context<'a>(&'a self, config: &'a Configuration) -> BuildContext<'a> {
+        let named_materials = config
+            .materials
+            .iter()
+            .flatten()
+            .map(|(name, material)| (name.clone(), self.create_material(material)))
+            .collect();
+
+        BuildContext {
+            builder: self,
+            config,
+            named_materials,
+            objects: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn create_material(&self, material: &configuration::Material) -> Arc<dyn Scatterer> {
+        let factory = self
+            .materials
+            .get(&material.type_field)
+            .unwrap_or_else(|| panic!("Unsupported material type {}", material.type_field));
+
+        factory(material)
+    }
 }
 
-fn create_camera(config: &crate::configuration::Configuration) -> Camera {
+fn create_camera(config: &Configuration) -> Camera {
     let camera = &config.camera;
 
     let origin = Point3::new(camera.position[0], camera.position[1], camera.position[2]);
     let look_at = Point3::new(camera.look_at[0], camera.look_at[1], camera.look_at[2]);
     let up = Vector3::new(camera.up[0], camera.up[1], camera.up[2]);
 
-    let focus_distance = (origin - look_at).magnitude();
+    // focus on the plane through the focus target, otherwise focus on look_at
+    let focus_distance = match (&camera.focus_distance, &camera.focus_target) {
+        (Some(focus_distance), _) => *focus_distance,
+        (None, Some(target)) => {
+            let target = Point3::new(target[0], target[1], target[2]);
+            (target - origin).dot(&(look_at - origin).as_normal())
+        }
+        (None, None) => (origin - look_at).magnitude(),
+    };
+
+    let mut aperture = camera.aperture;
+
+    let model = match camera.model.as_deref().unwrap_or("perspective") {
+        "perspective" => CameraModel::Perspective {
+            fov_deg: camera.fov_deg.expect("Perspective camera requires fovDeg"),
+        },
+        "thinLens" => {
+            let focal_length = camera
+                .focal_length
+                .expect("Thin lens camera requires focalLength");
+            let f_stop = camera.f_stop.expect("Thin lens camera requires fStop");
+
+            // lens dimensions are in millimeters, scene units are meters unless told otherwise
+            let meters_per_unit = camera.meters_per_unit.unwrap_or(1.0);
+            aperture = focal_length / f_stop / 1000.0 / meters_per_unit;
+
+            CameraModel::thin_lens(
+                focal_length,
+                camera.sensor_width.unwrap_or(36.0),
+                config.aspect_ratio,
+            )
+        }
+        "orthographic" => CameraModel::Orthographic {
+            view_height: camera
+                .view_height
+                .expect("Orthographic camera requires viewHeight"),
+        },
+        "fisheye" => CameraModel::Fisheye {
+            fov_deg: camera.fov_deg.unwrap_or(180.0),
+        },
+        "equirectangular" => CameraModel::Equirectangular,
+        "cylindrical" => CameraModel::Cylindrical {
+            fov_deg: camera.fov_deg.unwrap_or(90.0),
+        },
+        _ => panic!("Unsupported camera model"),
+    };
+
+    let aperture_shape = match (&camera.aperture_mask, camera.aperture_blades) {
+        (Some(path), _) => ApertureShape::Mask(ApertureMask::load(path)),
+        (None, Some(blades)) => {
+            assert!(blades >= 3, "Polygonal apertures require at least 3 blades");
+            ApertureShape::Polygon {
+                blades,
+                rotation_deg: camera.aperture_rotation_deg.unwrap_or(0.0),
+            }
+        }
+        (None, None) => ApertureShape::Circle,
+    };
 
-    Camera::new(
+    Camera::with_model(
         origin,
         look_at,
         up,
         config.aspect_ratio,
-        camera.fov_deg,
+        model,
         focus_distance,
-        camera.aperture,
+        aperture,
     )
+    .with_aperture_shape(aperture_shape)
 }
 
-fn create_sphere(shape: &crate::configuration::Shape) -> Shape {
+fn create_sphere(context: &BuildContext, shape: &configuration::Shape) -> Arc<dyn Intersectable> {
     let radius: Real = shape.transform.size[0];
     let position = Point3::new(
         shape.transform.position[0],
@@ -52,44 +291,194 @@ fn create_sphere(shape: &crate::configuration::Shape) -> Shape {
         shape.transform.position[2],
     );
 
-    match &shape.material.type_field[..] {
-        "lambertian" => Shape::Sphere {
-            center: position,
-            radius,
-            material: create_lambertian_material(&shape.material),
-        },
-        "metal" => Shape::Sphere {
-            center: position,
-            radius,
-            material: create_metal_material(&shape.material),
-        },
-        "dielectric" => Shape::Sphere {
-            center: position,
-            radius,
-            material: create_dielectric_material(&shape.material),
-        },
-        _ => panic!("Unsupported material type"),
+    Arc::new(Sphere {
+        center: position,
+        radius,
+        material: context.create_material(&shape.material),
+    })
+}
+
+// an object from the objects map, moved by the position and uniformly scaled by the first size
+fn create_instance(context: &BuildContext, shape: &configuration::Shape) -> Arc<dyn Intersectable> {
+    let name = shape.object.as_ref().expect("Instance requires an object");
+    let position = &shape.transform.position;
+    let offset = match position.len() {
+        0 => Vector3::default(),
+        _ => Vector3::new(position[0], position[1], position[2]),
+    };
+
+    Arc::new(Instance {
+        shape: context.create_object(name, &shape.material),
+        offset,
+        scale: shape.transform.size.first().cloned().unwrap_or(1.0),
+    })
+}
+
+fn create_constant_medium(
+    context: &BuildContext,
+    shape: &configuration::Shape,
+) -> Arc<dyn Intersectable> {
+    let boundary = shape
+        .boundary
+        .as_ref()
+        .expect("Constant medium requires a boundary shape");
+    let density = shape.density.expect("Constant medium requires a density");
+
+    Arc::new(ConstantMedium::new(
+        context.create_shape(boundary),
+        density,
+        context.create_material(&shape.material),
+    ))
+}
+
+fn create_grid_medium(
+    context: &BuildContext,
+    shape: &configuration::Shape,
+) -> Arc<dyn Intersectable> {
+    let path = shape.path.as_ref().expect("Grid medium requires a path");
+    let grid = VoxelGrid::load(path, shape.resolution)
+        .unwrap_or_else(|error| panic!("Unable to load voxel grid {}", error));
+
+    let center = Point3::new(
+        shape.transform.position[0],
+        shape.transform.position[1],
+        shape.transform.position[2],
+    );
+    let half_size = Vector3::new(
+        shape.transform.size[0],
+        shape.transform.size[1],
+        shape.transform.size[2],
+    ) * 0.5;
+
+    Arc::new(GridMedium::new(
+        grid,
+        center - half_size,
+        center + half_size,
+        shape.density_scale.unwrap_or(1.0),
+        shape.emission_scale.unwrap_or(1.0),
+        shape.temperature_scale.unwrap_or(1.0),
+        context.create_material(&shape.material),
+    ))
+}
+
+// the asset's own materials and lights are used, the transform places it in the scene with a
+// uniform size or one per axis. Its camera isn't, the scene has its own, rendering the .gltf
+// file by itself shows it through the asset's camera
+fn create_gltf(_context: &BuildContext, shape: &configuration::Shape) -> Arc<dyn Intersectable> {
+    let path = shape.path.as_ref().expect("glTF requires a path");
+    let asset = gltfimport::load(path, &placement(&shape.transform))
+        .unwrap_or_else(|error| panic!("Unable to load {}: {}", path, error));
+    Arc::new(asset.into_group())
+}
+
+// PLY or STL by the file's extension. Normals are "smooth" or "flat", by default the file's
+// own are used if it has any. PLY files without faces are point clouds, every point becomes
+// a small octahedron
+fn create_mesh(context: &BuildContext, shape: &configuration::Shape) -> Arc<dyn Intersectable> {
+    let path = shape.path.as_ref().expect("Mesh requires a path");
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let mut mesh = match extension.as_deref() {
+        Some("ply") => ply::load(path),
+        Some("stl") => stl::load(path),
+        _ => panic!("Mesh {} isn't a .ply or .stl file", path),
+    }
+    .unwrap_or_else(|error| panic!("Unable to load {}: {}", path, error));
+
+    if mesh.triangles.is_empty() {
+        let radius = shape.point_radius.unwrap_or_else(|| {
+            mesh.bounds()
+                .map_or(0.0, |bounds| (bounds.max - bounds.min).magnitude() * 0.002)
+        });
+        mesh = mesh.points_as_octahedra(radius);
+    }
+    match shape.normals.as_deref() {
+        Some("smooth") => mesh.compute_smooth_normals(),
+        Some("flat") => mesh.normals.clear(),
+        None => {}
+        Some(other) => panic!("Unknown normals {}, expected smooth or flat", other),
+    }
+
+    let transform = placement(&shape.transform);
+    let mut mesh = mesh.transformed(&transform);
+    // mirroring turns counter clockwise triangles clockwise
+    if transform.swaps_handedness() {
+        mesh.flip_winding();
     }
+
+    Arc::new(TriangleMesh::new(
+        mesh,
+        context.create_material(&shape.material),
+    ))
+}
+
+// position translates, one size scales uniformly and three scale each axis
+fn placement(transform: &configuration::Transform) -> Matrix4 {
+    let position = &transform.position;
+    let size = &transform.size;
+
+    let translation = match position.len() {
+        0 => Matrix4::identity(),
+        _ => Matrix4::translate(position[0], position[1], position[2]),
+    };
+    let scale = match size.len() {
+        0 => Matrix4::identity(),
+        1 | 2 => Matrix4::scale(size[0], size[0], size[0]),
+        _ => Matrix4::scale(size[0], size[1], size[2]),
+    };
+
+    translation * scale
 }
 
-fn create_dielectric_material(material: &crate::configuration::Material) -> Material {
-    Material::Dielectric {
+fn create_dielectric_material(material: &configuration::Material) -> Arc<dyn Scatterer> {
+    Arc::new(Dielectric {
         refraction_index: material.refraction_index.unwrap(),
-    }
+    })
 }
 
-fn create_metal_material(material: &crate::configuration::Material) -> Material {
+fn create_metal_material(material: &configuration::Material) -> Arc<dyn Scatterer> {
     let diffuse = material.diffuse.as_ref().unwrap();
-    Material::Metal {
+    Arc::new(Metal {
         diffuse: Color3::new(diffuse[0], diffuse[1], diffuse[2]),
         fuzz: material.fuzz.unwrap_or(0.0),
-    }
+    })
 }
 
-fn create_lambertian_material(material: &crate::configuration::Material) -> Material {
+fn create_lambertian_material(material: &configuration::Material) -> Arc<dyn Scatterer> {
     let diffuse = material.diffuse.as_ref().unwrap();
 
-    Material::Lambertian {
+    Arc::new(Lambertian {
         diffuse: Vector3::new(diffuse[0], diffuse[1], diffuse[2]),
-    }
+    })
+}
+
+fn create_isotropic_material(material: &configuration::Material) -> Arc<dyn Scatterer> {
+    let albedo = material.albedo.as_ref().unwrap();
+
+    Arc::new(Isotropic {
+        albedo: Color3::new(albedo[0], albedo[1], albedo[2]),
+    })
+}
+
+fn create_henyey_greenstein_material(material: &configuration::Material) -> Arc<dyn Scatterer> {
+    let albedo = material.albedo.as_ref().unwrap();
+
+    Arc::new(HenyeyGreenstein {
+        albedo: Color3::new(albedo[0], albedo[1], albedo[2]),
+        anisotropy: material.anisotropy.unwrap_or(0.0),
+    })
+}
+
+fn create_diffuse_light_material(material: &configuration::Material) -> Arc<dyn Scatterer> {
+    let emission = material
+        .emission
+        .as_ref()
+        .expect("Diffuse light requires an emission");
+
+    Arc::new(DiffuseLight {
+        emission: Color3::new(emission[0], emission[1], emission[2]),
+        two_sided: material.two_sided.unwrap_or(false),
+    })
 }
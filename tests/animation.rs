@@ -0,0 +1,26 @@
+use crayfish::animation::frame_output_path;
+
+#[test]
+fn frame_numbers_replace_printf_patterns() {
+    assert_eq!(
+        frame_output_path("out/frame_%04d.png", 7),
+        "out/frame_0007.png"
+    );
+    assert_eq!(
+        frame_output_path("out/frame_%d.png", 12),
+        "out/frame_12.png"
+    );
+    assert_eq!(frame_output_path("out/frame.png", 3), "out/frame_0003.png");
+}
+
+#[test]
+fn percent_signs_that_are_not_patterns_are_kept() {
+    assert_eq!(
+        frame_output_path("out/100%_dir/frame.png", 5),
+        "out/100%_dir/frame_0005.png"
+    );
+    assert_eq!(
+        frame_output_path("out/50%/frame_%03d.png", 5),
+        "out/50%/frame_005.png"
+    );
+}
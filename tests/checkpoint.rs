@@ -0,0 +1,160 @@
+use crayfish::checkpoint::Checkpoint;
+use crayfish::configuration::Configuration;
+use crayfish::scenefile;
+use crayfish::{WorldBuilder, WorldRenderRequest};
+use std::io::ErrorKind;
+
+const SEED: u64 = 40;
+
+fn scene(radius: f64) -> Configuration {
+    serde_json::from_value(serde_json::json!({
+        "width": 16,
+        "aspectRatio": 2.0,
+        "outputPath": "unused.png",
+        "rayStep": 1,
+        "samplesPerPixel": 2,
+        "rayMaxDepth": 4,
+        "camera": { "fovDeg": 40.0, "position": [0, 0, 4], "lookAt": [0, 0, 0], "up": [0, 1, 0] },
+        "shapes": [{
+            "type": "sphere",
+            "material": { "type": "lambertian", "diffuse": [0.8, 0.3, 0.3] },
+            "transform": { "position": [0, 0, 0], "size": [radius, 1, 1] }
+        }]
+    }))
+    .unwrap()
+}
+
+fn request(config: &Configuration, seed: u64) -> WorldRenderRequest {
+    WorldRenderRequest::new(config.samples_per_pixel, config.ray_max_depth, 1, 16, 8)
+        .with_seed(seed)
+        .with_scene_hash(scenefile::scene_hash(config))
+}
+
+fn render(config: &Configuration, seed: u64) -> Checkpoint {
+    let world = WorldBuilder::from_config(config);
+    world.render_to_checkpoint(request(config, seed)).unwrap().0
+}
+
+#[test]
+fn the_header_survives_a_round_trip() {
+    let config = scene(1.0);
+    let checkpoint = render(&config, SEED);
+
+    let mut bytes = Vec::new();
+    checkpoint.write_to(&mut bytes).unwrap();
+    let read = Checkpoint::read_from(&mut bytes.as_slice()).unwrap();
+
+    assert_eq!(read.seed, SEED);
+    assert_eq!(read.samples_per_pixel, 2);
+    assert_eq!(read.ray_step, 1);
+    assert_eq!(read.scene_hash, scenefile::scene_hash(&config));
+    assert_eq!(read.pass, 2);
+}
+
+#[test]
+fn only_the_scene_changes_its_hash() {
+    let config = scene(1.0);
+    let mut more_samples = config.clone();
+    more_samples.samples_per_pixel = 64;
+    more_samples.output_path = "elsewhere.png".to_string();
+
+    assert_eq!(
+        scenefile::scene_hash(&config),
+        scenefile::scene_hash(&more_samples)
+    );
+    assert_ne!(
+        scenefile::scene_hash(&config),
+        scenefile::scene_hash(&scene(0.5))
+    );
+}
+
+#[test]
+fn checkpoints_of_another_scene_are_not_resumed() {
+    let checkpoint = render(&scene(1.0), SEED);
+
+    let other = scene(0.5);
+    let world = WorldBuilder::from_config(&other);
+    let error = world
+        .render_to_checkpoint(request(&other, SEED).with_resume(checkpoint))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("another scene"), "{}", error);
+}
+
+#[test]
+fn checkpoints_of_another_size_are_not_resumed() {
+    let config = scene(1.0);
+    let checkpoint = render(&config, SEED);
+
+    let world = WorldBuilder::from_config(&config);
+    let request = WorldRenderRequest::new(2, 4, 1, 8, 4)
+        .with_scene_hash(scenefile::scene_hash(&config))
+        .with_resume(checkpoint);
+    let error = world.render_to_checkpoint(request).err().unwrap();
+    assert!(error.to_string().contains("16x8"), "{}", error);
+}
+
+#[test]
+fn merging_needs_the_same_scene_and_different_seeds() {
+    let config = scene(1.0);
+    let mut merged = render(&config, SEED);
+
+    merged.merge(&render(&config, SEED + 1)).unwrap();
+    assert_eq!(merged.samples_per_pixel, 4);
+    assert_eq!(merged.pass, 4);
+
+    let same_seed = merged.merge(&render(&config, SEED)).err().unwrap();
+    assert!(same_seed.to_string().contains("seed"), "{}", same_seed);
+
+    let other_scene = merged.merge(&render(&scene(0.5), SEED + 2)).err().unwrap();
+    assert!(
+        other_scene.to_string().contains("another scene"),
+        "{}",
+        other_scene
+    );
+}
+
+#[test]
+fn seeds_are_never_merged_twice() {
+    let config = scene(1.0);
+    let mut merged = render(&config, SEED);
+    merged.merge(&render(&config, SEED + 1)).unwrap();
+
+    // a seed merged in earlier, not the one the merged checkpoint started with
+    let repeated = merged.merge(&render(&config, SEED + 1)).err().unwrap();
+    assert!(repeated.to_string().contains("seed 41"), "{}", repeated);
+
+    // the seeds survive being saved, and are checked when two merges are merged
+    let mut bytes = Vec::new();
+    merged.write_to(&mut bytes).unwrap();
+    let read = Checkpoint::read_from(&mut bytes.as_slice()).unwrap();
+    assert_eq!(read.merged_seeds, vec![SEED + 1]);
+
+    let mut other = render(&config, SEED + 2);
+    other.merge(&render(&config, SEED + 1)).unwrap();
+    let overlapping = other.merge(&read).err().unwrap();
+    assert!(overlapping.to_string().contains("seed"), "{}", overlapping);
+
+    other = render(&config, SEED + 2);
+    other.merge(&render(&config, SEED + 3)).unwrap();
+    other.merge(&read).unwrap();
+    assert_eq!(other.samples_per_pixel, 8);
+}
+
+#[test]
+fn huge_sizes_in_the_header_are_not_allocated() {
+    let mut bytes = Vec::new();
+    render(&scene(1.0), SEED).write_to(&mut bytes).unwrap();
+    // claim a million by a million pixels, the samples of 16x8 follow
+    bytes[12..20].copy_from_slice(&1_000_000u64.to_le_bytes());
+    bytes[20..28].copy_from_slice(&1_000_000u64.to_le_bytes());
+
+    let error = Checkpoint::read_from(&mut bytes.as_slice()).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(
+        error.to_string().contains("only has samples for 128"),
+        "{}",
+        error
+    );
+}
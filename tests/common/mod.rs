@@ -0,0 +1,56 @@
+// Fixtures shared by the tests that load files from disk and check the colours they render
+// to. Not every test uses all of it.
+#![allow(dead_code)]
+
+use crayfish::{Configuration, World, WorldRenderRequest};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const SEED: u64 = 1234;
+
+/// An empty directory for a test's files, named after the test so tests don't share one.
+pub fn fixture_directory(name: &str) -> PathBuf {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// A 32 by 16 scene of the given shapes, looking down -z from 4 units away so the shapes
+/// between x -3 and 3 are in view.
+pub fn scene(shapes: serde_json::Value) -> Configuration {
+    serde_json::from_value(serde_json::json!({
+        "width": 32,
+        "aspectRatio": 2.0,
+        "outputPath": "unused.png",
+        "rayStep": 1,
+        "samplesPerPixel": 4,
+        "rayMaxDepth": 4,
+        "camera": { "fovDeg": 60.0, "position": [0, 0, 4], "lookAt": [0, 0, 0], "up": [0, 1, 0] },
+        "shapes": shapes
+    }))
+    .unwrap()
+}
+
+/// Renders with 16 samples per pixel and returns the pixels as rgb bytes.
+pub fn render(world: &World, width: usize, height: usize) -> Vec<u8> {
+    let request = WorldRenderRequest::new(16, 4, 1, width, height).with_seed(SEED);
+    world.render(request).to_u8_vec()
+}
+
+pub fn pixel(pixels: &[u8], width: usize, x: usize, y: usize) -> [i32; 3] {
+    let start = (y * width + x) * 3;
+    let channel = |offset: usize| pixels[start + offset] as i32;
+    [channel(0), channel(1), channel(2)]
+}
+
+/// Checks that a pixel has more of one colour channel than of another, by at least `margin`.
+pub fn assert_more(pixel: [i32; 3], channel: usize, than: usize, margin: i32) {
+    assert!(
+        pixel[channel] > pixel[than] + margin,
+        "channel {} of {:?} isn't above channel {} by {}",
+        channel,
+        pixel,
+        than,
+        margin
+    );
+}
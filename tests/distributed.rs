@@ -0,0 +1,174 @@
+use crayfish::configuration::Configuration;
+use crayfish::distributed::{run_worker, Coordinator};
+use crayfish::{WorldBuilder, WorldRenderRequest};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const SEED: u64 = 1234;
+
+fn scene() -> Configuration {
+    serde_json::from_str(
+        r#"{
+            "width": 24,
+            "aspectRatio": 1.5,
+            "outputPath": "unused.png",
+            "rayStep": 1,
+            "samplesPerPixel": 8,
+            "rayMaxDepth": 8,
+            "seed": 1234,
+            "camera": {
+                "fovDeg": 40.0,
+                "position": [0.0, 1.0, 4.0],
+                "lookAt": [0.0, 0.0, 0.0],
+                "up": [0.0, 1.0, 0.0]
+            },
+            "shapes": [
+                {
+                    "type": "sphere",
+                    "material": { "type": "lambertian", "diffuse": [0.8, 0.3, 0.3] },
+                    "transform": { "position": [0.0, 0.0, 0.0], "size": [1.0, 1.0, 1.0] }
+                },
+                {
+                    "type": "sphere",
+                    "material": { "type": "metal", "diffuse": [0.8, 0.8, 0.8], "fuzz": 0.1 },
+                    "transform": { "position": [0.0, -101.0, 0.0], "size": [100.0, 1.0, 1.0] }
+                }
+            ]
+        }"#,
+    )
+    .unwrap()
+}
+
+fn render_locally(config: &Configuration) -> Vec<u8> {
+    let world = WorldBuilder::from_config(config);
+    let request =
+        WorldRenderRequest::new(config.samples_per_pixel, config.ray_max_depth, 1, 24, 16)
+            .with_seed(SEED);
+
+    world.render(request).to_u8_vec()
+}
+
+fn assert_nearly_equal(distributed: &[u8], local: &[u8]) {
+    assert_eq!(distributed.len(), local.len());
+
+    // jobs come back in any order so the sums can round differently
+    for (a, b) in distributed.iter().zip(local.iter()) {
+        assert!(
+            (*a as i32 - *b as i32).abs() <= 1,
+            "{} differs from {}",
+            a,
+            b
+        );
+    }
+}
+
+#[test]
+fn several_workers_render_the_same_image_as_one_machine() {
+    let config = scene();
+    let coordinator = Coordinator::bind("127.0.0.1:0", config.clone())
+        .unwrap()
+        .with_passes_per_job(2);
+    let address = coordinator.local_addr().unwrap();
+
+    let workers: Vec<_> = (0..3)
+        .map(|_| thread::spawn(move || run_worker(address).unwrap()))
+        .collect();
+
+    let canvas = coordinator.run().unwrap();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    assert_nearly_equal(&canvas.to_u8_vec(), &render_locally(&config));
+}
+
+#[test]
+fn jobs_of_a_disconnected_worker_are_rendered_by_another() {
+    let config = scene();
+    let coordinator = Coordinator::bind("127.0.0.1:0", config.clone())
+        .unwrap()
+        .with_passes_per_job(1);
+    let address = coordinator.local_addr().unwrap();
+
+    // connects and hangs up without rendering anything
+    drop(TcpStream::connect(address).unwrap());
+
+    let worker = thread::spawn(move || run_worker(address).unwrap());
+
+    let canvas = coordinator.run().unwrap();
+    worker.join().unwrap();
+
+    assert_nearly_equal(&canvas.to_u8_vec(), &render_locally(&config));
+}
+
+// reads one message the way a worker would, without looking at it
+fn skip_message(stream: &mut TcpStream) {
+    let mut length = [0; 4];
+    stream.read_exact(&mut length).unwrap();
+    let mut message = vec![0; u32::from_le_bytes(length) as usize];
+    stream.read_exact(&mut message).unwrap();
+}
+
+#[test]
+fn jobs_of_workers_that_go_quiet_or_send_the_wrong_image_are_rendered_by_another() {
+    let config = scene();
+    let coordinator = Coordinator::bind("127.0.0.1:0", config.clone())
+        .unwrap()
+        .with_passes_per_job(1)
+        .with_worker_timeout(Duration::from_millis(200));
+    let address = coordinator.local_addr().unwrap();
+    let coordinator = thread::spawn(move || coordinator.run().unwrap());
+
+    // both take a job, one never answers and the other claims an image far too big to allocate
+    let (job_taken, jobs_taken) = mpsc::channel();
+    let liars: Vec<_> = [true, false]
+        .iter()
+        .map(|&quiet| {
+            let job_taken = job_taken.clone();
+            thread::spawn(move || {
+                let mut stream = TcpStream::connect(address).unwrap();
+                skip_message(&mut stream);
+                skip_message(&mut stream);
+                job_taken.send(()).unwrap();
+
+                if quiet {
+                    thread::sleep(Duration::from_secs(2));
+                } else {
+                    let mut header = b"CRAYCKPT".to_vec();
+                    header.extend_from_slice(&2u32.to_le_bytes());
+                    header.extend_from_slice(&u64::MAX.to_le_bytes());
+                    header.extend_from_slice(&u64::MAX.to_le_bytes());
+                    let _ = stream.write_all(&header);
+                }
+            })
+        })
+        .collect();
+    jobs_taken.recv().unwrap();
+    jobs_taken.recv().unwrap();
+
+    let worker = thread::spawn(move || run_worker(address).unwrap());
+    let canvas = coordinator.join().unwrap();
+    worker.join().unwrap();
+    for liar in liars {
+        liar.join().unwrap();
+    }
+
+    assert_nearly_equal(&canvas.to_u8_vec(), &render_locally(&config));
+}
+
+#[test]
+fn oversized_messages_are_rejected() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let worker = thread::spawn(move || run_worker(address));
+
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.write_all(&u32::MAX.to_le_bytes()).unwrap();
+
+    let error = worker.join().unwrap().err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("larger than"), "{}", error);
+}
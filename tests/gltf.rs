@@ -0,0 +1,165 @@
+mod common;
+
+use common::{assert_more, pixel};
+use crayfish::gltfimport;
+use crayfish::math::Matrix4;
+use crayfish::WorldBuilder;
+use std::fs;
+use std::path::PathBuf;
+
+// a 2 by 2 quad in a node moved 3 units in front of a camera at the origin. It only emits,
+// red on its left half and blue on its right from a 2 by 1 texture
+const ASSET: &str = r#"{
+    "asset": { "version": "2.0" },
+    "extensionsUsed": ["KHR_lights_punctual"],
+    "extensions": {
+        "KHR_lights_punctual": {
+            "lights": [
+                { "type": "point", "color": [1, 1, 1], "intensity": 2 },
+                { "type": "directional" }
+            ]
+        }
+    },
+    "scene": 0,
+    "scenes": [{ "nodes": [0, 1, 3, 4] }],
+    "nodes": [
+        { "translation": [0, 0, -3], "children": [2] },
+        { "camera": 0 },
+        { "mesh": 0 },
+        { "translation": [0, 5, 0], "extensions": { "KHR_lights_punctual": { "light": 0 } } },
+        { "extensions": { "KHR_lights_punctual": { "light": 1 } } }
+    ],
+    "cameras": [
+        { "type": "perspective", "perspective": { "yfov": 1.0472, "aspectRatio": 1.0, "znear": 0.1 } }
+    ],
+    "meshes": [
+        { "primitives": [{ "attributes": { "POSITION": 0, "TEXCOORD_0": 1 }, "indices": 2, "material": 0 }] }
+    ],
+    "materials": [
+        {
+            "pbrMetallicRoughness": { "baseColorFactor": [0, 0, 0, 1], "metallicFactor": 0 },
+            "emissiveFactor": [1, 1, 1],
+            "emissiveTexture": { "index": 0 }
+        }
+    ],
+    "textures": [{ "source": 0, "sampler": 0 }],
+    "samplers": [{ "wrapS": 33071, "wrapT": 33071 }],
+    "images": [{ "uri": "halves.png" }],
+    "buffers": [{ "uri": "quad.bin", "byteLength": 92 }],
+    "bufferViews": [
+        { "buffer": 0, "byteOffset": 0, "byteLength": 48 },
+        { "buffer": 0, "byteOffset": 48, "byteLength": 32 },
+        { "buffer": 0, "byteOffset": 80, "byteLength": 12 }
+    ],
+    "accessors": [
+        { "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3", "min": [-1, -1, 0], "max": [1, 1, 0] },
+        { "bufferView": 1, "componentType": 5126, "count": 4, "type": "VEC2" },
+        { "bufferView": 2, "componentType": 5123, "count": 6, "type": "SCALAR" }
+    ]
+}"#;
+
+fn quad_buffer() -> Vec<u8> {
+    let positions: [f32; 12] = [
+        -1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0, 1.0, 0.0,
+    ];
+    let uvs: [f32; 8] = [0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0];
+    let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
+
+    let mut buffer = Vec::new();
+    for value in positions.iter().chain(uvs.iter()) {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    for index in indices.iter() {
+        buffer.extend_from_slice(&index.to_le_bytes());
+    }
+    buffer
+}
+
+fn write_asset(name: &str) -> PathBuf {
+    let directory = common::fixture_directory(name);
+    image::save_buffer(
+        directory.join("halves.png"),
+        &[255, 0, 0, 0, 0, 255],
+        2,
+        1,
+        image::ColorType::Rgb8,
+    )
+    .unwrap();
+    fs::write(directory.join("quad.bin"), quad_buffer()).unwrap();
+    fs::write(directory.join("quad.gltf"), ASSET).unwrap();
+
+    directory
+}
+
+#[test]
+fn node_transforms_camera_and_textures_are_imported() {
+    let directory = write_asset("gltf_quad");
+    let asset = gltfimport::load(directory.join("quad.gltf"), &Matrix4::identity()).unwrap();
+
+    // the quad and the point light
+    assert_eq!(asset.shapes.len(), 2);
+    assert_eq!(asset.aspect_ratio(), Some(1.0));
+    assert_eq!(asset.warnings.len(), 1, "{:?}", asset.warnings);
+    assert!(asset.warnings[0].contains("Directional"));
+
+    let world = asset.into_world(2.0);
+    let pixels = common::render(&world, 16, 16);
+
+    // the clear coat reflects a little of the sky
+    assert_more(pixel(&pixels, 16, 5, 8), 0, 2, 100);
+    assert_more(pixel(&pixels, 16, 10, 8), 2, 0, 100);
+}
+
+// the same asset with its buffer in a binary chunk
+fn glb() -> Vec<u8> {
+    let mut json: serde_json::Value = serde_json::from_str(ASSET).unwrap();
+    json["buffers"] = serde_json::json!([{ "byteLength": 92 }]);
+    let mut json = serde_json::to_vec(&json).unwrap();
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    let buffer = quad_buffer();
+
+    let length = 12 + 8 + json.len() + 8 + buffer.len();
+    let mut glb = Vec::new();
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&(length as u32).to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+    glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"BIN\0");
+    glb.extend_from_slice(&buffer);
+    glb
+}
+
+#[test]
+fn binary_assets_combine_with_shapes_from_the_configuration() {
+    let directory = write_asset("gltf_glb");
+    let glb_path = directory.join("quad.glb");
+    fs::write(&glb_path, glb()).unwrap();
+
+    // the quad moved right and scaled up, next to a sphere on the left
+    let config = common::scene(serde_json::json!([
+        {
+            "type": "sphere",
+            "material": { "type": "lambertian", "diffuse": [0.1, 0.9, 0.1] },
+            "transform": { "position": [-3, 0, 0], "size": [1] }
+        },
+        {
+            "type": "gltf",
+            "path": glb_path.to_str().unwrap(),
+            "transform": { "position": [2, 0, 3], "size": [1.5] }
+        }
+    ]));
+
+    let world = WorldBuilder::from_config(&config);
+    let pixels = common::render(&world, 32, 16);
+
+    let sphere = pixel(&pixels, 32, 6, 8);
+    assert_more(sphere, 1, 0, 0);
+    assert_more(sphere, 1, 2, 0);
+    assert_more(pixel(&pixels, 32, 18, 8), 0, 2, 100);
+    assert_more(pixel(&pixels, 32, 23, 8), 2, 0, 100);
+}
@@ -0,0 +1,59 @@
+use crayfish::imagediff::{self, Convergence, Image};
+use crayfish::{Canvas, Color3, Real};
+use std::path::Path;
+
+fn gradient() -> Canvas {
+    let mut canvas = Canvas::new(16, 8);
+    for y in 0..8 {
+        for x in 0..16 {
+            let color = Color3::new(x as Real, y as Real, 4.0) / 16.0;
+            canvas.set_pixel(x, y, &color, 1);
+        }
+    }
+
+    canvas
+}
+
+#[test]
+fn identical_images_have_no_difference() {
+    let canvas = gradient();
+    let image = Image::from_rgb8(canvas.width, canvas.height, &canvas.to_u8_vec());
+
+    let difference = imagediff::compare(&image, &image);
+    assert_eq!(difference.mse, 0.0);
+    assert!(difference.psnr.is_infinite());
+    assert!((difference.ssim - 1.0).abs() < 1e-9);
+    assert_eq!(difference.flip, 0.0);
+}
+
+#[test]
+fn png_and_exr_of_the_same_render_compare_as_equal() {
+    let canvas = gradient();
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let png = directory.join("imagediff_gradient.png");
+    let exr = directory.join("imagediff_gradient.exr");
+    canvas.save(png.to_str().unwrap()).unwrap();
+    canvas.save(exr.to_str().unwrap()).unwrap();
+
+    let (png, exr) = (Image::load(&png).unwrap(), Image::load(&exr).unwrap());
+    assert_eq!((exr.width, exr.height), (16, 8));
+
+    // only the rounding to 8 bits differs
+    let difference = imagediff::compare(&png, &exr);
+    assert!(difference.psnr > 50.0, "PSNR {}", difference.psnr);
+    assert!(difference.ssim > 0.999, "SSIM {}", difference.ssim);
+}
+
+#[test]
+fn convergence_accounts_for_the_noise_of_the_reference() {
+    let canvas = gradient();
+    let image = Image::from_rgb8(canvas.width, canvas.height, &canvas.to_u8_vec());
+    let mut difference = imagediff::compare(&image, &image);
+
+    // a variance of 0.01 per sample shows up as 0.01 / 16 + 0.01 / 48 between the renders
+    difference.mse = 0.01 / 16.0 + 0.01 / 48.0;
+    let convergence = Convergence::estimate(&difference, 16, 48);
+
+    assert!((convergence.mse - 0.01 / 16.0).abs() < 1e-12);
+    assert!((convergence.samples_for_psnr(30.0) - 10.0).abs() < 1e-6);
+}
@@ -0,0 +1,114 @@
+use crayfish::configuration::Configuration;
+use crayfish::material::Lambertian;
+use crayfish::scenefile;
+use crayfish::shapes::{Instance, Sphere};
+use crayfish::{Color3, Intersectable, Point3, Ray, Real, Vector3};
+use crayfish::{WorldBuilder, WorldRenderRequest};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+fn instances_move_and_scale_the_shape_they_share() {
+    let instance = Instance {
+        shape: Arc::new(Sphere {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Arc::new(Lambertian {
+                diffuse: Color3::new(0.5, 0.5, 0.5),
+            }),
+        }),
+        offset: Vector3::new(3.0, 0.0, 0.0),
+        scale: 2.0,
+    };
+
+    let towards = Ray::new(Point3::new(3.0, 0.0, -10.0), Vector3::new(0.0, 0.0, 1.0));
+    let hit = instance.hit(&towards, 0.0, Real::INFINITY).unwrap();
+    assert!((hit.t - 8.0).abs() < 1e-4, "{}", hit.t);
+    assert!((hit.point[2] + 2.0).abs() < 1e-4, "{}", hit.point[2]);
+    assert_eq!(hit.normal[2], -1.0);
+
+    // where the sphere is before it's moved
+    let past = Ray::new(Point3::new(0.0, 0.0, -10.0), Vector3::new(0.0, 0.0, 1.0));
+    assert!(instance.hit(&past, 0.0, Real::INFINITY).is_none());
+}
+
+// an object whose only part has no transform and no material of its own, instanced on
+// either side of the origin with a red light as the material
+fn instanced_scene() -> Configuration {
+    let config = serde_json::json!({
+        "width": 16,
+        "aspectRatio": 2.0,
+        "outputPath": "unused.png",
+        "rayStep": 1,
+        "samplesPerPixel": 1,
+        "rayMaxDepth": 2,
+        "camera": {
+            "model": "orthographic", "viewHeight": 4.0,
+            "position": [0, 0, 5], "lookAt": [0, 0, 0], "up": [0, 1, 0]
+        },
+        "objects": { "ball": [{ "type": "counted" }] },
+        "shapes": [
+            {
+                "type": "instance", "object": "ball",
+                "material": { "type": "diffuseLight", "emission": [4, 0, 0] },
+                "transform": { "position": [-2, 0, 0], "size": [1] }
+            },
+            {
+                "type": "instance", "object": "ball",
+                "material": { "type": "diffuseLight", "emission": [4, 0, 0] },
+                "transform": { "position": [2, 0, 0], "size": [0.5] }
+            }
+        ]
+    });
+    let mut config = scenefile::parse(&config.to_string()).unwrap();
+    scenefile::expand(&mut config).unwrap();
+    config
+}
+
+#[test]
+fn instances_of_parts_without_a_transform_are_placed_and_built_once() {
+    let config = instanced_scene();
+    assert_eq!(config.shapes.len(), 2);
+    assert!(config
+        .shapes
+        .iter()
+        .all(|shape| shape.type_field == "instance"));
+
+    // a unit sphere at the origin, wherever the part would be placed
+    let built = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&built);
+    let mut builder = WorldBuilder::new();
+    builder.register_shape("counted", move |context, shape| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Arc::new(Sphere {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: context.create_material(&shape.material),
+        })
+    });
+
+    let world = builder.build(&config);
+    assert_eq!(built.load(Ordering::SeqCst), 1);
+
+    let request = WorldRenderRequest::new(1, 2, 1, 16, 8).with_seed(31);
+    let pixels = world.render(request).to_u8_vec();
+    let red = |x: usize, y: usize| {
+        let start = (y * 16 + x) * 3;
+        pixels[start] as i32 - pixels[start + 2] as i32
+    };
+
+    // the view is 8 units wide, two pixels to a unit
+    assert!(red(3, 4) > 100, "{}", red(3, 4));
+    assert!(red(11, 4) > 100, "{}", red(11, 4));
+    assert!(red(8, 4) < 0, "{}", red(8, 4));
+
+    // the second instance is half the size
+    let lit = |columns: std::ops::Range<usize>| {
+        let columns = &columns;
+        (0..8)
+            .flat_map(|y| columns.clone().map(move |x| (x, y)))
+            .filter(|&(x, y)| red(x, y) > 100)
+            .count()
+    };
+    assert!(lit(0..8) > 2 * lit(8..16), "{} {}", lit(0..8), lit(8..16));
+}
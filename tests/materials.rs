@@ -0,0 +1,463 @@
+// Statistical checks of the materials' scattering. Histograms of sampled directions are
+// compared against each material's analytic distribution with a chi-square test, with the
+// random numbers seeded so a failure can be reproduced.
+
+// converting reals with f64::from does nothing with the f64 feature
+#![allow(clippy::useless_conversion)]
+
+use crayfish::defs::consts::PI;
+use crayfish::material::{Dielectric, Lambertian, Metal, Scatterer};
+use crayfish::random;
+use crayfish::records::IntersectionRecord;
+use crayfish::{Color3, Point3, Ray, Real, Vector3};
+
+const SEED: u64 = 44;
+const SAMPLES: usize = 200_000;
+// the z score of a one sided p value of 0.001
+const Z_CRITICAL: f64 = 3.09;
+
+fn normal() -> Vector3 {
+    Vector3::new(0.0, 0.0, 1.0)
+}
+
+// a ray arriving at the origin at `theta` radians from the normal, in the x z plane
+fn incoming(theta: Real) -> Ray {
+    let direction = Vector3::new(theta.sin(), 0.0, -theta.cos());
+    Ray::new(Point3::new(0.0, 0.0, 0.0) - direction, direction)
+}
+
+fn scatter(material: &dyn Scatterer, ray: &Ray, front_face: bool) -> Option<(Vector3, Color3)> {
+    // the normal faces the side the ray arrives from, `front_face` says whether that's outside
+    let intersection = IntersectionRecord::new(
+        Point3::new(0.0, 0.0, 0.0),
+        normal(),
+        1.0,
+        front_face,
+        material,
+    );
+
+    material.scatter(ray, &intersection).map(|interaction| {
+        (
+            interaction.scattered_ray.direction.as_normal(),
+            interaction.attenuation,
+        )
+    })
+}
+
+fn samples(
+    material: &dyn Scatterer,
+    ray: &Ray,
+    front_face: bool,
+) -> Vec<Option<(Vector3, Color3)>> {
+    random::seed(SEED);
+    (0..SAMPLES)
+        .map(|_| scatter(material, ray, front_face))
+        .collect()
+}
+
+// Wilson and Hilferty's approximation of the chi-square distribution
+fn chi_square_critical(degrees_of_freedom: usize) -> f64 {
+    let k = degrees_of_freedom as f64;
+    let spread = 2.0 / (9.0 * k);
+    k * (1.0 - spread + Z_CRITICAL * spread.sqrt()).powi(3)
+}
+
+fn assert_chi_square(name: &str, observed: &[usize], expected_probabilities: &[f64]) {
+    let total: usize = observed.iter().sum();
+    let probability_sum: f64 = expected_probabilities.iter().sum();
+    assert!(
+        (probability_sum - 1.0).abs() < 1e-4,
+        "The probabilities of {} add up to {}",
+        name,
+        probability_sum
+    );
+
+    // bins expecting too few samples make the statistic unreliable, they're pooled together
+    let mut statistic = 0.0;
+    let mut bins = 0;
+    let mut pooled = (0.0, 0.0);
+    for (count, probability) in observed.iter().zip(expected_probabilities) {
+        let expected = probability * total as f64;
+        if expected < 5.0 {
+            pooled.0 += *count as f64;
+            pooled.1 += expected;
+            continue;
+        }
+
+        statistic += (*count as f64 - expected).powi(2) / expected;
+        bins += 1;
+    }
+    if pooled.1 > 0.0 {
+        statistic += (pooled.0 - pooled.1).powi(2) / pooled.1;
+        bins += 1;
+    }
+
+    let critical = chi_square_critical(bins - 1);
+    assert!(
+        statistic < critical,
+        "{} doesn't follow its distribution, chi-square {:.1} over {} bins (at most {:.1})",
+        name,
+        statistic,
+        bins,
+        critical
+    );
+}
+
+// the polar angle is measured from `axis`, the azimuth around it starting at `reference`
+fn spherical(direction: &Vector3, axis: &Vector3, reference: &Vector3) -> (f64, f64) {
+    let cos_theta = f64::from(direction.dot(axis)).clamp(-1.0, 1.0);
+    let bitangent = axis.cross(reference);
+    let phi = f64::from(direction.dot(&bitangent)).atan2(f64::from(direction.dot(reference)));
+
+    (cos_theta, phi.rem_euclid(2.0 * std::f64::consts::PI))
+}
+
+const THETA_BINS: usize = 10;
+const PHI_BINS: usize = 8;
+
+// bins directions evenly in the cosine of the polar angle over `cos_range` and in azimuth
+fn histogram<I: Iterator<Item = (f64, f64)>>(directions: I, cos_range: (f64, f64)) -> Vec<usize> {
+    let mut counts = vec![0; THETA_BINS * PHI_BINS];
+    let (low, high) = cos_range;
+
+    for (cos_theta, phi) in directions {
+        let theta_bin = (((cos_theta - low) / (high - low)) * THETA_BINS as f64) as usize;
+        let phi_bin = (phi / (2.0 * std::f64::consts::PI) * PHI_BINS as f64) as usize;
+        counts[theta_bin.min(THETA_BINS - 1) * PHI_BINS + phi_bin.min(PHI_BINS - 1)] += 1;
+    }
+
+    counts
+}
+
+// the probability of every histogram bin for a distribution that's symmetric around its axis,
+// `cdf` is the probability of a polar angle cosine above the one given
+fn bin_probabilities<F: Fn(f64) -> f64>(cdf: F, cos_range: (f64, f64)) -> Vec<f64> {
+    let (low, high) = cos_range;
+    let mut probabilities = Vec::with_capacity(THETA_BINS * PHI_BINS);
+
+    for theta_bin in 0..THETA_BINS {
+        let from = low + (high - low) * theta_bin as f64 / THETA_BINS as f64;
+        let to = low + (high - low) * (theta_bin + 1) as f64 / THETA_BINS as f64;
+        let probability = cdf(from) - cdf(to);
+        probabilities.extend((0..PHI_BINS).map(|_| probability / PHI_BINS as f64));
+    }
+
+    probabilities
+}
+
+fn white_lambertian() -> Lambertian {
+    Lambertian {
+        diffuse: Color3::new(1.0, 1.0, 1.0),
+    }
+}
+
+#[test]
+fn lambertian_samples_follow_the_cosine_distribution() {
+    let material = white_lambertian();
+    let tangent = Vector3::new(1.0, 0.0, 0.0);
+
+    for theta in [0.0, 1.0, 1.5] {
+        let directions = samples(&material, &incoming(theta), true)
+            .into_iter()
+            .map(|sample| spherical(&sample.unwrap().0, &normal(), &tangent));
+
+        // p(w) = cos(theta) / pi, so P(cos(theta) > c) = 1 - c^2
+        assert_chi_square(
+            &format!("Lambertian at {} radians", theta),
+            &histogram(directions, (0.0, 1.0)),
+            &bin_probabilities(|c| 1.0 - c * c, (0.0, 1.0)),
+        );
+    }
+}
+
+// P(cos(angle) > c) for the direction of a point uniform in a ball of radius `fuzz` around the
+// unit vector along the axis, integrated numerically over the part of the ball inside the cone
+fn fuzzed_direction_cdf(fuzz: f64, cos_angle: f64) -> f64 {
+    const STEPS: usize = 2000;
+
+    let max_angle = fuzz.asin();
+    let angle = cos_angle.clamp(-1.0, 1.0).acos().min(max_angle);
+    let ball_volume = 4.0 / 3.0 * std::f64::consts::PI * fuzz.powi(3);
+
+    // the volume of the ball between the polar angles psi and psi + d psi
+    let shell = |psi: f64| {
+        let half_chord = (fuzz * fuzz - psi.sin().powi(2)).max(0.0).sqrt();
+        let (near, far) = (psi.cos() - half_chord, psi.cos() + half_chord);
+        2.0 * std::f64::consts::PI * psi.sin() * (far.powi(3) - near.powi(3)) / 3.0
+    };
+
+    // simpson's rule
+    let step = angle / STEPS as f64;
+    let mut sum = shell(0.0) + shell(angle);
+    for i in 1..STEPS {
+        let weight = if i % 2 == 1 { 4.0 } else { 2.0 };
+        sum += weight * shell(i as f64 * step);
+    }
+
+    sum * step / 3.0 / ball_volume
+}
+
+#[test]
+fn metal_samples_follow_the_fuzz_distribution() {
+    let fuzz: Real = 0.5;
+    let material = Metal {
+        diffuse: Color3::new(1.0, 1.0, 1.0),
+        fuzz,
+    };
+    let tangent = Vector3::new(1.0, 0.0, 0.0);
+
+    // at normal incidence the mirror direction is the normal and no fuzzed ray is absorbed
+    let directions = samples(&material, &incoming(0.0), true)
+        .into_iter()
+        .map(|sample| spherical(&sample.unwrap().0, &normal(), &tangent));
+
+    let fuzz = f64::from(fuzz);
+    let cos_range = ((1.0 - fuzz * fuzz).sqrt(), 1.0);
+    assert_chi_square(
+        "Metal with a fuzz of 0.5",
+        &histogram(directions, cos_range),
+        &bin_probabilities(|c| fuzzed_direction_cdf(fuzz, c), cos_range),
+    );
+}
+
+#[test]
+fn polished_metal_reflects_like_a_mirror() {
+    let material = Metal {
+        diffuse: Color3::new(0.9, 0.8, 0.7),
+        fuzz: 0.0,
+    };
+
+    for theta in [0.0, 0.7, 1.5] {
+        let ray = incoming(theta);
+        let (direction, attenuation) = scatter(&material, &ray, true).unwrap();
+        let mirrored = Vector3::new(theta.sin(), 0.0, theta.cos());
+
+        assert!((direction - mirrored).magnitude() < 1e-5);
+        assert!((attenuation - Color3::new(0.9, 0.8, 0.7)).magnitude() < 1e-6);
+
+        // reciprocity: light coming back along the reflected ray leaves along the incoming one
+        let back = Ray::new(Point3::new(0.0, 0.0, 0.0) + mirrored, -mirrored);
+        let (returned, _) = scatter(&material, &back, true).unwrap();
+        assert!((returned + ray.direction.as_normal()).magnitude() < 1e-5);
+    }
+}
+
+// schlick's approximation of the fresnel reflectance, always taken with the cosine on the
+// side with the lower refraction index so it's the same for light going either way
+fn schlick(cos_outside: f64, refraction_index: f64) -> f64 {
+    let r0 = ((1.0 - refraction_index) / (1.0 + refraction_index)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos_outside).powi(5)
+}
+
+fn reflected_fraction(material: &Dielectric, theta: Real, front_face: bool) -> f64 {
+    let reflected = samples(material, &incoming(theta), front_face)
+        .into_iter()
+        .filter(|sample| {
+            let (direction, _) = sample.unwrap();
+            // reflections stay on the side the ray came from
+            direction[2] > 0.0
+        })
+        .count();
+
+    reflected as f64 / SAMPLES as f64
+}
+
+fn assert_probability(name: &str, observed: f64, expected: f64) {
+    let standard_error = (expected * (1.0 - expected) / SAMPLES as f64).sqrt();
+    assert!(
+        (observed - expected).abs() <= Z_CRITICAL * standard_error,
+        "{} happens {:.4} of the time instead of {:.4}",
+        name,
+        observed,
+        expected
+    );
+}
+
+#[test]
+fn glass_reflects_as_often_as_fresnel_predicts() {
+    let refraction_index: Real = 1.5;
+    let material = Dielectric { refraction_index };
+    let refraction_index = f64::from(refraction_index);
+
+    for theta in [0.0, 0.5, 1.0, 1.3, 1.5] {
+        let cos_theta = f64::from(theta).cos();
+        assert_probability(
+            &format!("Entering glass at {} radians, reflection", theta),
+            reflected_fraction(&material, theta, true),
+            schlick(cos_theta, refraction_index),
+        );
+    }
+}
+
+#[test]
+fn glass_reflects_the_same_in_both_directions() {
+    let refraction_index: Real = 1.5;
+    let material = Dielectric { refraction_index };
+
+    // light entering at theta refracts to theta_t, light leaving at theta_t should be reflected
+    // exactly as often as light entering at theta
+    for theta in [0.3, 0.8, 1.2, 1.5 as Real] {
+        let theta_transmitted = (theta.sin() / refraction_index).asin();
+
+        let entering = reflected_fraction(&material, theta, true);
+        let leaving = reflected_fraction(&material, theta_transmitted, false);
+
+        let standard_error = (entering * (1.0 - entering) * 2.0 / SAMPLES as f64).sqrt();
+        assert!(
+            (entering - leaving).abs() <= Z_CRITICAL * standard_error + 1e-4,
+            "At {} radians {:.4} is reflected going in but {:.4} coming out",
+            theta,
+            entering,
+            leaving
+        );
+    }
+}
+
+#[test]
+fn glass_reflects_everything_beyond_the_critical_angle() {
+    let material = Dielectric {
+        refraction_index: 1.5,
+    };
+    let critical_angle = (1.0 / 1.5 as Real).asin();
+
+    for theta in [critical_angle + 0.01, 1.2, 1.5] {
+        assert_eq!(reflected_fraction(&material, theta, false), 1.0);
+    }
+}
+
+#[test]
+fn glass_refracts_by_snells_law() {
+    let refraction_index: Real = 1.5;
+    let material = Dielectric { refraction_index };
+
+    for theta in [0.0, 0.4, 1.1, 1.55] {
+        random::seed(SEED);
+        let refracted = (0..1000)
+            .filter_map(|_| scatter(&material, &incoming(theta), true))
+            .map(|(direction, _)| direction)
+            .find(|direction| direction[2] < 0.0)
+            .expect("Nothing was refracted");
+
+        let sin_transmitted = Vector3::new(refracted[0], refracted[1], 0.0).magnitude();
+        assert!((sin_transmitted - theta.sin() / refraction_index).abs() < 1e-5);
+        assert!(refracted[0] >= 0.0 && refracted[1].abs() < 1e-6);
+    }
+}
+
+// in a white furnace every surface is lit equally from every direction, a material that
+// neither absorbs nor creates energy has to send on exactly what arrives
+fn furnace_throughput(material: &dyn Scatterer, theta: Real, transmits: bool) -> f64 {
+    let total: f64 = samples(material, &incoming(theta), true)
+        .into_iter()
+        .map(|sample| match sample {
+            // an opaque surface can't send light into itself
+            Some((direction, attenuation)) if transmits || direction[2] > 0.0 => {
+                f64::from(attenuation[0] + attenuation[1] + attenuation[2]) / 3.0
+            }
+            _ => 0.0,
+        })
+        .sum();
+
+    total / SAMPLES as f64
+}
+
+#[test]
+fn white_materials_conserve_energy_in_a_furnace() {
+    let dielectric = Dielectric {
+        refraction_index: 1.5,
+    };
+    let metal = Metal {
+        diffuse: Color3::new(1.0, 1.0, 1.0),
+        fuzz: 0.0,
+    };
+
+    for theta in [0.0, 0.8, 1.5, PI / 2.0 - 0.001] {
+        let lambertian = furnace_throughput(&white_lambertian(), theta, false);
+        assert!(
+            (lambertian - 1.0).abs() < 1e-6,
+            "Lambertian sends on {}",
+            lambertian
+        );
+
+        let metal = furnace_throughput(&metal, theta, false);
+        assert!(
+            (metal - 1.0).abs() < 1e-6,
+            "Polished metal sends on {}",
+            metal
+        );
+
+        let glass = furnace_throughput(&dielectric, theta, true);
+        assert!((glass - 1.0).abs() < 1e-6, "Glass sends on {}", glass);
+    }
+}
+
+#[test]
+fn fuzzy_metal_never_scatters_into_the_surface() {
+    let material = Metal {
+        diffuse: Color3::new(1.0, 1.0, 1.0),
+        fuzz: 0.8,
+    };
+
+    // near grazing angles much of the fuzz points into the surface and has to be absorbed
+    for theta in [0.0, 1.0, 1.5] {
+        let leaks = samples(&material, &incoming(theta), true)
+            .into_iter()
+            .flatten()
+            .filter(|(direction, _)| direction[2] <= 0.0)
+            .count();
+        assert_eq!(
+            leaks, 0,
+            "{} rays went into the metal at {} radians",
+            leaks, theta
+        );
+
+        let throughput = furnace_throughput(&material, theta, false);
+        assert!(throughput <= 1.0 && throughput > 0.0);
+    }
+}
+
+#[test]
+fn reflect_mirrors_around_the_normal() {
+    let normal = normal();
+
+    let head_on = Vector3::new(0.0, 0.0, -1.0).reflect(&normal);
+    assert!((head_on - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-6);
+
+    // a grazing direction doesn't touch the surface and carries on unchanged
+    let grazing = Vector3::new(1.0, 0.0, 0.0).reflect(&normal);
+    assert!((grazing - Vector3::new(1.0, 0.0, 0.0)).magnitude() < 1e-6);
+
+    let oblique = Vector3::new(0.6, 0.0, -0.8);
+    let reflected = oblique.reflect(&normal);
+    assert!((reflected - Vector3::new(0.6, 0.0, 0.8)).magnitude() < 1e-6);
+    assert!((reflected.magnitude() - 1.0).abs() < 1e-6);
+    assert!((reflected.reflect(&normal) - oblique).magnitude() < 1e-6);
+}
+
+#[test]
+fn refract_follows_snells_law_up_to_grazing_angles() {
+    let normal = normal();
+
+    // an index ratio of 1 or normal incidence leave the direction alone
+    let oblique = Vector3::new(0.6, 0.0, -0.8);
+    assert!((oblique.refract(&normal, 1.0) - oblique).magnitude() < 1e-6);
+    let head_on = Vector3::new(0.0, 0.0, -1.0);
+    assert!((head_on.refract(&normal, 1.0 / 1.5) - head_on).magnitude() < 1e-6);
+
+    for theta in [0.2, 0.9, 1.4, PI / 2.0 - 1e-4] {
+        let direction = incoming(theta).direction;
+        let refracted = direction.refract(&normal, 1.0 / 1.5);
+
+        assert!((refracted.magnitude() - 1.0).abs() < 1e-4);
+        assert!(refracted[2] < 0.0);
+        assert!((refracted[0] - theta.sin() / 1.5).abs() < 1e-5);
+    }
+
+    // just inside the critical angle the refracted ray skims along the surface
+    let critical_angle = (1.0 / 1.5 as Real).asin();
+    let skimming = incoming(critical_angle - 1e-4)
+        .direction
+        .refract(&normal, 1.5);
+    assert!((skimming.magnitude() - 1.0).abs() < 1e-3);
+    assert!(skimming[0] > 0.99);
+}
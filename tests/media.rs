@@ -0,0 +1,146 @@
+// Checks of participating media: how often rays get through them is compared with the
+// transmittance Beer-Lambert's law gives, with the random numbers seeded, and voxel grids
+// are sampled at their edges and loaded from broken files.
+
+use crayfish::material::Isotropic;
+use crayfish::random;
+use crayfish::shapes::{ConstantMedium, Sphere};
+use crayfish::volume::{GridMedium, VoxelGrid};
+use crayfish::{Color3, Intersectable, Point3, Ray, Real, Vector3};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+const SEED: u64 = 27;
+const SAMPLES: usize = 20_000;
+
+fn isotropic() -> Arc<Isotropic> {
+    Arc::new(Isotropic {
+        albedo: Color3::new(0.5, 0.5, 0.5),
+    })
+}
+
+// the fraction of rays that get through without scattering
+fn transmitted(medium: &dyn Intersectable, ray: &Ray) -> f64 {
+    random::seed(SEED);
+    let passed = (0..SAMPLES)
+        .filter(|_| medium.hit(ray, 0.0, Real::INFINITY).is_none())
+        .count();
+    passed as f64 / SAMPLES as f64
+}
+
+#[test]
+fn constant_media_transmit_as_beer_lambert_predicts() {
+    let boundary = Arc::new(Sphere {
+        center: Point3::new(0.0, 0.0, 0.0),
+        radius: 1.0,
+        material: isotropic(),
+    });
+    let medium = ConstantMedium::new(boundary, 0.5, isotropic());
+    let direction = Vector3::new(0.0, 0.0, 1.0);
+
+    // through the whole diameter from outside, and from the centre out
+    let outside = transmitted(&medium, &Ray::new(Point3::new(0.0, 0.0, -5.0), direction));
+    let inside = transmitted(&medium, &Ray::new(Point3::new(0.0, 0.0, 0.0), direction));
+    assert!((outside - (-1.0f64).exp()).abs() < 0.02, "{}", outside);
+    assert!((inside - (-0.5f64).exp()).abs() < 0.02, "{}", inside);
+}
+
+#[test]
+fn dense_media_scatter_right_after_the_boundary() {
+    let boundary = Arc::new(Sphere {
+        center: Point3::new(0.0, 0.0, 0.0),
+        radius: 1.0,
+        material: isotropic(),
+    });
+    let medium = ConstantMedium::new(boundary, 1000.0, isotropic());
+
+    random::seed(SEED);
+    let ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 2.0));
+    let hit = medium.hit(&ray, 0.0, Real::INFINITY).unwrap();
+    assert!(hit.t > 2.0 && hit.t < 2.01, "{}", hit.t);
+}
+
+#[test]
+fn single_voxel_grids_are_constant() {
+    let grid = VoxelGrid::new(
+        [1, 1, 1],
+        vec![0.25],
+        Some(vec![1500.0]),
+        Some(vec![Color3::new(1.0, 2.0, 3.0)]),
+    );
+
+    for local in [
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(0.5, 0.5, 0.5),
+        Point3::new(1.0, 1.0, 1.0),
+    ]
+    .iter()
+    {
+        assert_eq!(grid.density_at(local), 0.25);
+        assert_eq!(grid.temperature_at(local), 1500.0);
+        assert_eq!(grid.emission_at(local)[2], 3.0);
+    }
+}
+
+#[test]
+#[should_panic(expected = "at least one voxel")]
+fn empty_grids_are_rejected() {
+    VoxelGrid::new([4, 0, 4], Vec::new(), None, None);
+}
+
+#[test]
+fn ratio_tracking_matches_beer_lambert() {
+    // a uniform grid as thick as the unit sphere above, from -1 to 1 along the ray
+    let grid = VoxelGrid::new([2, 2, 2], vec![0.5; 8], None, None);
+    let medium = GridMedium::new(
+        grid,
+        Point3::new(-1.0, -1.0, -1.0),
+        Point3::new(1.0, 1.0, 1.0),
+        1.0,
+        1.0,
+        1.0,
+        isotropic(),
+    );
+    let ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0));
+
+    random::seed(SEED);
+    let estimate = (0..SAMPLES)
+        .map(|_| medium.transmittance(&ray, 0.0, Real::INFINITY) as f64)
+        .sum::<f64>()
+        / SAMPLES as f64;
+    assert!((estimate - (-1.0f64).exp()).abs() < 0.02, "{}", estimate);
+
+    // rays that miss the grid get through untouched
+    let past = Ray::new(Point3::new(3.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0));
+    assert_eq!(medium.transmittance(&past, 0.0, Real::INFINITY), 1.0);
+}
+
+#[test]
+fn broken_grid_files_are_reported_with_their_path() {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join("broken_grids");
+    fs::create_dir_all(&directory).unwrap();
+
+    // one byte short of a 2x2x2 grid
+    let raw = directory.join("short.raw");
+    fs::write(&raw, vec![0; 31]).unwrap();
+    let raw = raw.to_str().unwrap();
+    let error = VoxelGrid::load(raw, Some([2, 2, 2])).err().unwrap();
+    assert!(error.to_string().contains(raw), "{}", error);
+    assert!(error.to_string().contains("31 bytes"), "{}", error);
+
+    let json = directory.join("grid.json");
+    fs::write(
+        &json,
+        r#"{ "resolution": [1, 1, 2], "density": [1, 1], "emission": [1, 1, 1, 1] }"#,
+    )
+    .unwrap();
+    let error = VoxelGrid::load(json.to_str().unwrap(), None).err().unwrap();
+    assert!(error.to_string().contains("emission"), "{}", error);
+
+    let missing = directory.join("missing.json");
+    let error = VoxelGrid::load(missing.to_str().unwrap(), None)
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("missing.json"), "{}", error);
+}
@@ -0,0 +1,255 @@
+mod common;
+
+use common::{assert_more, pixel, SEED};
+use crayfish::mesh::Mesh;
+use crayfish::{ply, stl};
+use crayfish::{Configuration, WorldBuilder, WorldRenderRequest};
+use std::fs;
+use std::path::PathBuf;
+
+// a unit square split into two triangles, with every optional vertex property
+const COLORED_PLY: &str = "ply
+format ascii 1.0
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property float s
+property float t
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0  0 0 1  0 0  255 0 0
+1 0 0  0 0 1  1 0  0 255 0
+1 1 0  0 0 1  1 1  0 0 255
+0 1 0  0 0 1  0 1  0 0 0
+4 0 1 2 3
+";
+
+#[test]
+fn ply_vertex_properties_are_read() {
+    let mesh = ply::parse(COLORED_PLY.as_bytes()).unwrap();
+
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    assert_eq!(mesh.normals.len(), 4);
+    assert_eq!(mesh.normals[2][2], 1.0);
+    assert_eq!(mesh.uvs[2], [1.0, 1.0]);
+
+    // integer colours are sRGB, full channels stay full
+    assert_eq!(mesh.colors.len(), 4);
+    assert_eq!(mesh.colors[0][0], 1.0);
+    assert_eq!(mesh.colors[1][1], 1.0);
+    assert_eq!(mesh.colors[3][2], 0.0);
+}
+
+#[test]
+fn ply_faces_need_whole_non_negative_indices() {
+    for face in ["4 0 1 -2 3", "4 0 1 2.5 3"] {
+        let ply = COLORED_PLY.replace("4 0 1 2 3", face);
+        let error = ply::parse(ply.as_bytes()).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(
+            error.to_string().contains("isn't a vertex index"),
+            "{}",
+            error
+        );
+    }
+}
+
+#[test]
+fn binary_ply_point_clouds_have_no_faces() {
+    let mut ply = b"ply\nformat binary_little_endian 1.0\nelement vertex 2\n\
+        property float x\nproperty float y\nproperty float z\n\
+        property float red\nproperty float green\nproperty float blue\nend_header\n"
+        .to_vec();
+    for value in [
+        1.0f32, 2.0, 3.0, 0.5, 0.25, 0.0, -1.0, -2.0, -3.0, 0.0, 0.0, 1.0,
+    ]
+    .iter()
+    {
+        ply.extend_from_slice(&value.to_le_bytes());
+    }
+    let mesh = ply::parse(&ply).unwrap();
+
+    assert_eq!(mesh.positions.len(), 2);
+    assert!(mesh.triangles.is_empty());
+    assert_eq!(mesh.positions[1][1], -2.0);
+    // float colours are already linear
+    assert_eq!(mesh.colors[0][1], 0.25);
+
+    let octahedra = mesh.points_as_octahedra(0.5);
+    assert_eq!(octahedra.positions.len(), 12);
+    assert_eq!(octahedra.triangles.len(), 16);
+    assert_eq!(octahedra.colors.len(), 12);
+    let bounds = octahedra.bounds().unwrap();
+    assert_eq!(bounds.max[0], 1.5);
+    assert_eq!(bounds.min[2], -3.5);
+}
+
+// a tetrahedron with its corners at the origin and on the three axes
+const TETRAHEDRON: [[[f32; 3]; 3]; 4] = [
+    [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+    [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+    [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]],
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+];
+
+fn ascii_stl() -> String {
+    let mut stl = String::from("solid tetrahedron\n");
+    for triangle in TETRAHEDRON.iter() {
+        stl.push_str("  facet normal 0 0 0\n    outer loop\n");
+        for corner in triangle.iter() {
+            stl.push_str(&format!(
+                "      vertex {} {} {}\n",
+                corner[0], corner[1], corner[2]
+            ));
+        }
+        stl.push_str("    endloop\n  endfacet\n");
+    }
+    stl.push_str("endsolid tetrahedron\n");
+    stl
+}
+
+fn binary_stl() -> Vec<u8> {
+    // binary headers may start with "solid" as well
+    let mut stl = b"solid but binary".to_vec();
+    stl.resize(80, 0);
+    stl.extend_from_slice(&(TETRAHEDRON.len() as u32).to_le_bytes());
+    for triangle in TETRAHEDRON.iter() {
+        stl.extend_from_slice(&[0; 12]);
+        for corner in triangle.iter() {
+            for coordinate in corner.iter() {
+                // a negative zero is still the same corner
+                let coordinate = if *coordinate == 0.0 {
+                    -0.0
+                } else {
+                    *coordinate
+                };
+                stl.extend_from_slice(&coordinate.to_le_bytes());
+            }
+        }
+        stl.extend_from_slice(&[0; 2]);
+    }
+    stl
+}
+
+fn assert_welded_tetrahedron(mesh: &Mesh) {
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.triangles.len(), 4);
+    assert!(mesh.normals.is_empty());
+}
+
+#[test]
+fn stl_corners_are_welded_into_shared_vertices() {
+    assert_welded_tetrahedron(&stl::parse(ascii_stl().as_bytes()).unwrap());
+    assert_welded_tetrahedron(&stl::parse(&binary_stl()).unwrap());
+
+    let error = stl::parse(b"solid broken\nfacet normal 0 0 0\nouter loop\nvertex 0 0\n")
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn smooth_normals_average_the_faces_around_a_vertex() {
+    let mut mesh = stl::parse(ascii_stl().as_bytes()).unwrap();
+    mesh.compute_smooth_normals();
+
+    // the corner at the origin is shared by the three faces on the axis planes
+    let origin = mesh
+        .positions
+        .iter()
+        .position(|position| position.magnitude() == 0.0)
+        .unwrap();
+    let normal = mesh.normals[origin];
+    let expected = -1.0 / (3.0f64.sqrt()) as crayfish::defs::Real;
+    for coordinate in [normal[0], normal[1], normal[2]].iter() {
+        assert!((coordinate - expected).abs() < 1e-5, "{}", coordinate);
+    }
+}
+
+fn write_meshes(name: &str) -> PathBuf {
+    let directory = common::fixture_directory(name);
+    fs::write(directory.join("tetrahedron.stl"), binary_stl()).unwrap();
+    fs::write(directory.join("square.ply"), COLORED_PLY).unwrap();
+    directory
+}
+
+#[test]
+fn meshes_are_referenced_from_scene_files() {
+    let directory = write_meshes("mesh_scene");
+    let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+
+    // the coloured square scaled up on the right, the tetrahedron on the left
+    let config = common::scene(serde_json::json!([
+        {
+            "type": "mesh",
+            "path": path("tetrahedron.stl"),
+            "normals": "smooth",
+            "material": { "type": "lambertian", "diffuse": [0.1, 0.9, 0.1] },
+            "transform": { "position": [-3, -1, 0], "size": [2] }
+        },
+        {
+            "type": "mesh",
+            "path": path("square.ply"),
+            "material": { "type": "lambertian", "diffuse": [1, 1, 1] },
+            "transform": { "position": [0.5, -0.5, 0], "size": [2.5, 2, 1] }
+        }
+    ]));
+
+    let world = WorldBuilder::from_config(&config);
+    let pixels = common::render(&world, 32, 16);
+
+    let tetrahedron = pixel(&pixels, 32, 7, 9);
+    assert_more(tetrahedron, 1, 0, 50);
+    assert_more(tetrahedron, 1, 2, 50);
+    // the bottom left corner of the square is red, the bottom right green
+    assert_more(pixel(&pixels, 32, 19, 9), 0, 1, 50);
+    assert_more(pixel(&pixels, 32, 25, 9), 1, 0, 50);
+}
+
+#[test]
+fn bvh_nodes_and_triangle_tests_are_counted() {
+    let directory = write_meshes("mesh_stats");
+    let path = directory.join("tetrahedron.stl");
+    let config: Configuration = serde_json::from_value(serde_json::json!({
+        "width": 8,
+        "aspectRatio": 1.0,
+        "outputPath": "unused.png",
+        "rayStep": 1,
+        "samplesPerPixel": 1,
+        "rayMaxDepth": 2,
+        "camera": { "fovDeg": 60.0, "position": [0, 0, 4], "lookAt": [0, 0, 0], "up": [0, 1, 0] },
+        "shapes": [{
+            "type": "mesh",
+            "path": path.to_str().unwrap(),
+            "material": { "type": "lambertian", "diffuse": [0.5, 0.5, 0.5] },
+            "transform": { "position": [0, 0, 0], "size": [2] }
+        }]
+    }))
+    .unwrap();
+
+    let world = WorldBuilder::from_config(&config);
+    let request = WorldRenderRequest::new(1, 2, 1, 8, 8).with_seed(SEED);
+    let (_, stats) = world.render_with_stats(request);
+
+    let counters = stats.counters;
+    assert!(
+        counters.bvh_nodes_visited >= counters.camera_rays,
+        "{:?}",
+        counters
+    );
+    // the triangles of the mesh on top of the mesh itself
+    assert!(
+        counters.intersection_tests > counters.total_rays(),
+        "{:?}",
+        counters
+    );
+}
@@ -0,0 +1,109 @@
+// The ray packet code against the single ray code it has to agree with, lane by lane on
+// random rays. Run with `--features packet`, and `--features packet,f64` for double precision.
+#![cfg(feature = "packet")]
+
+use crayfish::aabb::Aabb;
+use crayfish::material::Lambertian;
+use crayfish::packet::{hit_aabb4, RayPacket4};
+use crayfish::shapes::Sphere;
+use crayfish::{Color3, Intersectable, Point3, Ray, Real, Vector3};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+
+const SEED: u64 = 35;
+const PACKETS: usize = 20_000;
+
+fn point(rng: &mut StdRng, extent: Real) -> Point3 {
+    Point3::new(
+        rng.gen_range(-extent..extent),
+        rng.gen_range(-extent..extent),
+        rng.gen_range(-extent..extent),
+    )
+}
+
+// rays from around the shapes aimed roughly at them, so plenty of them hit
+fn random_rays(rng: &mut StdRng) -> [Ray; 4] {
+    let mut ray = || {
+        let origin = point(rng, 4.0);
+        let target = point(rng, 1.5);
+        Ray::new(origin, target - origin)
+    };
+    [ray(), ray(), ray(), ray()]
+}
+
+fn t_range(rng: &mut StdRng) -> (Real, Real) {
+    if rng.gen_bool(0.5) {
+        (0.0, Real::INFINITY)
+    } else {
+        let t_min = rng.gen_range(0.0..1.0);
+        (t_min, t_min + rng.gen_range(0.0..2.0))
+    }
+}
+
+#[test]
+fn sphere_packets_match_single_rays() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut hits = 0;
+
+    for _ in 0..PACKETS {
+        let sphere = Sphere {
+            center: point(&mut rng, 1.0),
+            radius: rng.gen_range(0.1..2.0),
+            material: Arc::new(Lambertian {
+                diffuse: Color3::new(0.5, 0.5, 0.5),
+            }),
+        };
+        let rays = random_rays(&mut rng);
+        let (t_min, t_max) = t_range(&mut rng);
+
+        let records = sphere.hit_packet(&RayPacket4::new(&rays), t_min, t_max);
+        for (ray, packet_record) in rays.iter().zip(records.iter()) {
+            let single_record = sphere.hit(ray, t_min, t_max);
+            match (packet_record, &single_record) {
+                (None, None) => {}
+                (Some(packet_record), Some(single_record)) => {
+                    assert_eq!(packet_record.t, single_record.t);
+                    assert_eq!(packet_record.front_face, single_record.front_face);
+                    hits += 1;
+                }
+                _ => panic!(
+                    "packet hit {} but the single ray hit {} in [{}, {}]",
+                    packet_record.is_some(),
+                    single_record.is_some(),
+                    t_min,
+                    t_max
+                ),
+            }
+        }
+    }
+
+    // both outcomes have to be covered for the comparison to mean anything
+    assert!(hits > PACKETS && hits < 3 * PACKETS, "{}", hits);
+}
+
+#[test]
+fn box_packets_match_single_rays() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut hits = 0;
+
+    for _ in 0..PACKETS {
+        let corner = point(&mut rng, 1.0);
+        let size = Vector3::new(
+            rng.gen_range(0.1..2.0),
+            rng.gen_range(0.1..2.0),
+            rng.gen_range(0.1..2.0),
+        );
+        let aabb = Aabb::new(corner, corner + size);
+        let rays = random_rays(&mut rng);
+        let (t_min, t_max) = t_range(&mut rng);
+
+        let packet_hits = hit_aabb4(&RayPacket4::new(&rays), &aabb, t_min, t_max);
+        for (ray, packet_hit) in rays.iter().zip(packet_hits.iter()) {
+            assert_eq!(*packet_hit, aabb.hit(ray, t_min, t_max));
+            hits += *packet_hit as usize;
+        }
+    }
+
+    assert!(hits > PACKETS / 4 && hits < 3 * PACKETS, "{}", hits);
+}
@@ -0,0 +1,223 @@
+use crayfish::pbrt::{self, PbrtScene};
+use crayfish::WorldRenderRequest;
+use std::fs;
+use std::path::Path;
+
+const SEED: u64 = 1234;
+
+// average red, green and blue of the left and right halves of the image
+fn render_halves(scene: &PbrtScene) -> [[f64; 3]; 2] {
+    let request = WorldRenderRequest::new(8, scene.ray_max_depth, 1, 24, 12).with_seed(SEED);
+    let pixels = scene.world.render(request).to_u8_vec();
+
+    let mut halves = [[0.0; 3]; 2];
+    for (i, pixel) in pixels.chunks_exact(3).enumerate() {
+        let half = if i % 24 < 12 { 0 } else { 1 };
+        for channel in 0..3 {
+            halves[half][channel] += pixel[channel] as f64 / (12.0 * 12.0);
+        }
+    }
+
+    halves
+}
+
+fn brightness(half: &[f64; 3]) -> f64 {
+    half.iter().sum()
+}
+
+const SETTINGS_SCENE: &str = r#"
+# settings crayfish understands and a few features it doesn't
+Film "rgb" "integer xresolution" [ 320 ] "integer yresolution" 240 "string filename" "out.exr"
+Sampler "halton" "integer pixelsamples" 64
+Integrator "bdpt" "integer maxdepth" [ 3 ]
+LookAt 0 0 -5  0 0 0  0 1 0
+Camera "perspective" "float fov" 45 "float screenwindow" [ -1 1 -1 1 ]
+WorldBegin
+LightSource "infinite" "rgb L" [ 0.4 0.5 0.6 ]
+LightSource "spot" "rgb I" [ 1 1 1 ]
+AttributeBegin
+  Material "hair"
+  Shape "sphere" "float radius" 1 "float zmax" 0.5
+AttributeEnd
+Shape "disk"
+Shape "disk"
+"#;
+
+#[test]
+fn settings_are_read_and_unsupported_features_become_warnings() {
+    let scene = pbrt::parse(SETTINGS_SCENE, Path::new("")).unwrap();
+
+    assert_eq!((scene.width, scene.height), (320, 240));
+    assert_eq!(scene.samples_per_pixel, 64);
+    assert_eq!(scene.ray_max_depth, 4);
+    assert_eq!(scene.output_path, "out.png");
+
+    let expected = [
+        "bdpt integrator",
+        "screenwindow",
+        "spot lights",
+        "Material hair",
+        "\"zmax\"",
+        "Shape disk",
+    ];
+    for expected in expected.iter() {
+        assert!(
+            scene
+                .warnings
+                .iter()
+                .any(|warning| warning.contains(expected)),
+            "no warning about {} in {:?}",
+            expected,
+            scene.warnings
+        );
+    }
+    let disk_warnings = scene
+        .warnings
+        .iter()
+        .filter(|warning| warning.contains("disk"))
+        .count();
+    assert_eq!(disk_warnings, 1, "warnings are only given once");
+}
+
+#[test]
+fn syntax_errors_point_at_the_directive() {
+    let text = "LookAt 0 0 -5  0 0 0  0 1 0\nWorldBegin\n  Translate 1 2\n";
+    let error = pbrt::parse(text, Path::new("")).err().unwrap();
+
+    assert!(
+        error.to_string().starts_with("line 3, column 3"),
+        "{}",
+        error
+    );
+}
+
+fn two_spheres(camera_transform: &str) -> String {
+    format!(
+        r#"
+        {}
+        LookAt 0 0 -5  0 0 0  0 1 0
+        Camera "perspective" "float fov" 40
+        WorldBegin
+        LightSource "infinite" "rgb L" [ 0.2 0.2 0.2 ]
+        AttributeBegin
+          Material "matte" "rgb Kd" [ 0.9 0.1 0.1 ]
+          Translate -1.2 0 0
+          Shape "sphere" "float radius" 0.8
+        AttributeEnd
+        AttributeBegin
+          Material "matte" "rgb Kd" [ 0.1 0.9 0.1 ]
+          Translate 1.2 0 0
+          Shape "sphere" "float radius" 0.8
+        AttributeEnd
+        "#,
+        camera_transform
+    )
+}
+
+#[test]
+fn images_come_out_the_same_way_round_as_pbrt() {
+    // looking down +z in PBRT's left handed space, -x is on the left
+    let scene = pbrt::parse(&two_spheres(""), Path::new("")).unwrap();
+    let [left, right] = render_halves(&scene);
+    assert!(
+        left[0] > left[1] && right[1] > right[0],
+        "{:?}",
+        [left, right]
+    );
+
+    // exporters often mirror the camera to make the scene right handed
+    let scene = pbrt::parse(&two_spheres("Scale -1 1 1"), Path::new("")).unwrap();
+    let [left, right] = render_halves(&scene);
+    assert!(
+        left[1] > left[0] && right[0] > right[1],
+        "{:?}",
+        [left, right]
+    );
+}
+
+fn area_light(camera_z: f64, orientation: &str) -> String {
+    format!(
+        r#"
+        LookAt 0 0 {}  0 0 0  0 1 0
+        Camera "perspective" "float fov" 60
+        WorldBegin
+        AttributeBegin
+          {}
+          AreaLightSource "diffuse" "rgb L" [ 4 4 4 ]
+          Shape "trianglemesh" "point3 P" [ -1 -1 0  1 -1 0  1 1 0  -1 1 0 ]
+              "integer indices" [ 0 1 2  0 2 3 ]
+        AttributeEnd
+        "#,
+        camera_z, orientation
+    )
+}
+
+#[test]
+fn area_lights_emit_on_the_side_pbrt_does() {
+    let lit = |camera_z: f64, orientation: &str| {
+        let scene = pbrt::parse(&area_light(camera_z, orientation), Path::new("")).unwrap();
+        let [left, right] = render_halves(&scene);
+        brightness(&left) + brightness(&right) > 100.0
+    };
+
+    // the winding makes the normal point along +z
+    assert!(lit(5.0, ""));
+    assert!(!lit(-5.0, ""));
+    assert!(lit(-5.0, "ReverseOrientation"));
+    assert!(lit(-5.0, "Scale 1 1 -1"));
+}
+
+#[test]
+fn included_files_and_ply_meshes_are_found_next_to_the_scene() {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join("pbrt_include");
+    fs::create_dir_all(directory.join("geometry")).unwrap();
+
+    // a binary quad in front of the camera
+    let mut ply = b"ply\nformat binary_little_endian 1.0\nelement vertex 4\n\
+        property float x\nproperty float y\nproperty float z\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n"
+        .to_vec();
+    for vertex in [
+        [-1.0f32, -1.0, 0.0],
+        [1.0, -1.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [-1.0, 1.0, 0.0],
+    ]
+    .iter()
+    {
+        for coordinate in vertex.iter() {
+            ply.extend_from_slice(&coordinate.to_le_bytes());
+        }
+    }
+    ply.push(4);
+    for index in [0i32, 1, 2, 3].iter() {
+        ply.extend_from_slice(&index.to_le_bytes());
+    }
+    fs::write(directory.join("geometry").join("quad.ply"), ply).unwrap();
+    fs::write(
+        directory.join("geometry").join("lights.pbrt"),
+        r#"AreaLightSource "diffuse" "rgb L" [ 4 4 4 ]
+        Shape "plymesh" "string filename" "quad.ply""#,
+    )
+    .unwrap();
+
+    let scene_path = directory.join("scene.pbrt");
+    fs::write(
+        &scene_path,
+        r#"LookAt 0 0 5  0 0 0  0 1 0
+        Camera "perspective" "float fov" 60
+        WorldBegin
+        Include "geometry/lights.pbrt""#,
+    )
+    .unwrap();
+
+    let scene = pbrt::load(&scene_path).unwrap();
+    assert!(scene.warnings.is_empty(), "{:?}", scene.warnings);
+    assert_eq!(
+        scene.output_path,
+        directory.join("scene.png").to_str().unwrap()
+    );
+
+    let [left, right] = render_halves(&scene);
+    assert!(brightness(&left) + brightness(&right) > 100.0);
+}
@@ -0,0 +1,154 @@
+// Renders small scenes with a fixed seed and compares them against the reference images in
+// references/, so a change to a material or shape that alters how scenes look fails a test.
+//
+// When a change to the look is intended, regenerate the references with
+//   CRAYFISH_UPDATE_REFERENCES=1 cargo test --test regression
+// and check the new images in alongside the change. A failing comparison writes the render and
+// a heat map of the difference next to the test binary's temporary files.
+//
+// Both images are blurred before they're measured, which averages away the sampling noise of
+// a render along with detail too fine to see.
+
+use crayfish::configuration::Configuration;
+use crayfish::imagediff::{self, Image};
+use crayfish::{scenefile, Real, WorldBuilder, WorldRenderRequest};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const UPDATE_VARIABLE: &str = "CRAYFISH_UPDATE_REFERENCES";
+
+// a render that took entirely different samples, as one with another seed or with f64 reals
+// does, stays within these, changing a material's colour or a refraction index doesn't
+const MAX_RMSE: f64 = 0.002;
+const MIN_PSNR: f64 = 54.0;
+const MAX_FLIP: f64 = 0.01;
+const DENOISE_SIGMA: f64 = 1.5;
+
+fn regression_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/regression")
+}
+
+fn load_scene(name: &str) -> Configuration {
+    let path = regression_dir()
+        .join("scenes")
+        .join(format!("{}.json", name));
+    scenefile::load(path.to_str().unwrap())
+}
+
+fn render(config: &Configuration) -> Image {
+    let width = config.width as usize;
+    let height = (width as Real / config.aspect_ratio) as usize;
+    let request = WorldRenderRequest::new(
+        config.samples_per_pixel,
+        config.ray_max_depth,
+        config.ray_step,
+        width,
+        height,
+    )
+    .with_seed(config.seed.expect("Regression scenes need a seed"));
+
+    let canvas = WorldBuilder::from_config(config).render(request);
+    Image::from_rgb8(width, height, &canvas.to_u8_vec())
+}
+
+fn save(image: &Image, bytes: &[u8], path: &Path) {
+    image::save_buffer(
+        path,
+        bytes,
+        image.width as u32,
+        image.height as u32,
+        image::ColorType::Rgb8,
+    )
+    .unwrap();
+}
+
+fn assert_matches_reference(name: &str, config: &Configuration) {
+    let actual = render(config);
+    let bytes = actual.to_rgb8();
+    let reference_path = regression_dir()
+        .join("references")
+        .join(format!("{}.png", name));
+
+    if env::var_os(UPDATE_VARIABLE).is_some() {
+        save(&actual, &bytes, &reference_path);
+        return;
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|error| {
+            panic!(
+                "Unable to open {}: {}, set {} to create it",
+                reference_path.display(),
+                error,
+                UPDATE_VARIABLE
+            )
+        })
+        .to_rgb8();
+    let expected = Image::from_rgb8(
+        reference.width() as usize,
+        reference.height() as usize,
+        reference.as_raw(),
+    );
+
+    let difference = imagediff::compare(
+        &actual.blurred(DENOISE_SIGMA),
+        &expected.blurred(DENOISE_SIGMA),
+    );
+    let rmse = difference.rmse();
+    if rmse <= MAX_RMSE && difference.psnr >= MIN_PSNR && difference.flip <= MAX_FLIP {
+        return;
+    }
+
+    let output_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("regression");
+    fs::create_dir_all(&output_dir).unwrap();
+    let actual_path = output_dir.join(format!("{}.png", name));
+    let diff_path = output_dir.join(format!("{}.diff.png", name));
+    save(&actual, &bytes, &actual_path);
+    imagediff::heatmap(actual.width, actual.height, &difference.flip_errors)
+        .save(&diff_path)
+        .unwrap();
+
+    panic!(
+        "{} no longer matches its reference: RMSE {:.4} (at most {}), PSNR {:.2}dB (at least {}), \
+         FLIP {:.4} (at most {}). The render is at {} and the difference at {}",
+        name,
+        rmse,
+        MAX_RMSE,
+        difference.psnr,
+        MIN_PSNR,
+        difference.flip,
+        MAX_FLIP,
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+#[test]
+fn default_scene_spheres() {
+    // the scene crayfish renders by default, made small enough to render quickly
+    let mut config = scenefile::load(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("scene_config.json")
+            .to_str()
+            .unwrap(),
+    );
+    config.width = 64;
+    config.samples_per_pixel = 1024;
+    config.ray_max_depth = 16;
+    config.seed = Some(43);
+
+    assert_matches_reference("spheres", &config);
+}
+
+// without planes or lights the walls are large spheres and the top is left open so the sky
+// lights the box
+#[test]
+fn cornell_box() {
+    assert_matches_reference("cornell_box", &load_scene("cornell_box"));
+}
+
+#[test]
+fn glass_sphere() {
+    assert_matches_reference("glass_sphere", &load_scene("glass_sphere"));
+}
@@ -0,0 +1,268 @@
+use crayfish::configuration::{self, CURRENT_VERSION};
+use crayfish::scenefile;
+use crayfish::sceneformat::SceneFormat;
+use crayfish::Configuration;
+use std::fs;
+use std::path::Path;
+
+const UNVERSIONED_SCENE: &str = r#"{
+    "width": 32,
+    "aspectRatio": 2.0,
+    "outputPath": "image.png",
+    "rayStep": 1,
+    "samplesPerPixel": 4,
+    "rayMaxDepth": 8,
+    "camera": { "fovDeg": 40.0, "position": [0, 0, 0], "lookAt": [0, 0, -1], "up": [0, 1, 0] },
+    "shapes": [
+        {
+            "type": "sphere",
+            "material": { "type": "lambertian", "diffuse": [0.5, 0.5, 0.5] },
+            "transform": { "position": [0, 0, -1], "size": [0.5] }
+        }
+    ]
+}"#;
+
+#[test]
+fn scenes_from_before_versioning_are_migrated() {
+    let config = scenefile::parse(UNVERSIONED_SCENE).unwrap();
+
+    assert_eq!(config.version, CURRENT_VERSION);
+    assert_eq!(config.width, 32);
+    assert_eq!(config.shapes.len(), 1);
+}
+
+#[test]
+fn scenes_from_a_newer_version_are_rejected() {
+    let newer =
+        UNVERSIONED_SCENE.replacen('{', &format!("{{ \"version\": {},", CURRENT_VERSION + 1), 1);
+
+    let error = scenefile::parse(&newer).unwrap_err();
+    assert!(error.to_string().contains("only reads up to version"));
+    assert!(scenefile::parse(&UNVERSIONED_SCENE.replacen('{', "{ \"version\": -1,", 1)).is_err());
+}
+
+#[test]
+fn schema_describes_the_fields_of_the_default_scene() {
+    let schema = serde_json::to_value(configuration::json_schema()).unwrap();
+    let definitions = &schema["definitions"];
+    for name in ["Camera", "Shape", "Material", "Transform"].iter() {
+        assert!(definitions[name].is_object(), "{} has no definition", name);
+    }
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scene_config.json");
+    let scene: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    let fields = |value: &serde_json::Value| {
+        value
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    for field in fields(&scene) {
+        assert!(
+            schema["properties"][&field].is_object(),
+            "{} missing",
+            field
+        );
+    }
+    for field in fields(&scene["camera"]) {
+        assert!(
+            definitions["Camera"]["properties"][&field].is_object(),
+            "{} missing",
+            field
+        );
+    }
+    for field in fields(&scene["shapes"][0]["transform"]) {
+        let transform = &definitions["Transform"]["properties"];
+        assert!(transform[&field].is_object(), "{} missing", field);
+    }
+}
+
+const TOML_SCENE: &str = r#"
+# comments are why there's more than JSON
+width = 32
+aspectRatio = 2.0
+outputPath = "image.png"
+rayStep = 1
+samplesPerPixel = 4
+rayMaxDepth = 8
+camera = { fovDeg = 40.0, position = [0, 0, 0], lookAt = [0, 0, -1], up = [0, 1, 0] }
+
+[[shapes]]
+type = "sphere"
+material = { type = "lambertian", diffuse = [0.5, 0.5, 0.5] }
+transform = { position = [0, 0, -1], size = [0.5] }
+"#;
+
+const YAML_SCENE: &str = r#"
+# comments are why there's more than JSON
+width: 32
+aspectRatio: 2.0
+outputPath: image.png
+rayStep: 1
+samplesPerPixel: 4
+rayMaxDepth: 8
+camera: { fovDeg: 40.0, position: [0, 0, 0], lookAt: [0, 0, -1], up: [0, 1, 0] }
+shapes:
+  - type: sphere
+    material: { type: lambertian, diffuse: [0.5, 0.5, 0.5] }
+    transform: { position: [0, 0, -1], size: [0.5] }
+"#;
+
+const RON_SCENE: &str = r#"
+// comments are why there's more than JSON, and trailing commas are fine
+(
+    width: 32,
+    aspectRatio: 2.0,
+    outputPath: "image.png",
+    rayStep: 1,
+    samplesPerPixel: 4,
+    rayMaxDepth: 8,
+    camera: (fovDeg: Some(40.0), position: [0, 0, 0], lookAt: [0, 0, -1], up: [0, 1, 0]),
+    shapes: [
+        (
+            type: "sphere",
+            material: (type: "lambertian", diffuse: [0.5, 0.5, 0.5]),
+            transform: (position: [0, 0, -1], size: [0.5]),
+        ),
+    ],
+)
+"#;
+
+#[test]
+fn every_format_reads_the_same_scene() {
+    let json = scenefile::parse(UNVERSIONED_SCENE).unwrap();
+
+    for (format, text) in [
+        (SceneFormat::Toml, TOML_SCENE),
+        (SceneFormat::Yaml, YAML_SCENE),
+        (SceneFormat::Ron, RON_SCENE),
+    ]
+    .iter()
+    {
+        let config: Configuration = scenefile::parse_as(*format, text).unwrap();
+        assert_eq!(config, json, "{:?}", format);
+    }
+}
+
+#[test]
+fn errors_point_at_the_line_and_column() {
+    let wrong_width = |text: &str, from: &str, to: &str| text.replacen(from, to, 1);
+    let cases = [
+        (
+            SceneFormat::Json,
+            wrong_width(UNVERSIONED_SCENE, "32", "\"wide\""),
+        ),
+        (SceneFormat::Toml, wrong_width(TOML_SCENE, "32", "\"wide\"")),
+        (SceneFormat::Yaml, wrong_width(YAML_SCENE, "32", "wide")),
+        (SceneFormat::Ron, wrong_width(RON_SCENE, "32", "\"wide\"")),
+    ];
+
+    for (format, text) in cases.iter() {
+        let error = scenefile::parse_as::<Configuration>(*format, text).unwrap_err();
+        let width_line = text.lines().position(|line| line.contains("wide")).unwrap() + 1;
+        let expected = format!("line {}, column", width_line);
+        assert!(
+            error.to_string().starts_with(&expected),
+            "{:?}: {}",
+            format,
+            error
+        );
+        assert!(
+            error.to_string().contains("invalid type"),
+            "{:?}: {}",
+            format,
+            error
+        );
+    }
+
+    let error = scenefile::parse_as::<Configuration>(SceneFormat::Toml, "width = \n").unwrap_err();
+    assert!(
+        error.to_string().starts_with("line 1, column 9"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn convert_round_trips_through_every_format() {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let source = directory.join("convert_source.toml");
+    fs::write(&source, TOML_SCENE).unwrap();
+    let expected: Configuration = scenefile::parse_as(SceneFormat::Toml, TOML_SCENE).unwrap();
+
+    let mut previous = source;
+    for extension in ["yaml", "ron", "json", "toml"].iter() {
+        let converted = directory.join(format!("convert_target.{}", extension));
+        scenefile::convert(&previous, &converted).unwrap();
+        assert_eq!(scenefile::load(converted.to_str().unwrap()), expected);
+        previous = converted;
+    }
+}
+
+fn with_generator(generator: serde_json::Value) -> Configuration {
+    let mut scene: serde_json::Value = serde_json::from_str(UNVERSIONED_SCENE).unwrap();
+    scene["generators"] = serde_json::json!([generator]);
+    scenefile::parse(&scene.to_string()).unwrap()
+}
+
+#[test]
+fn generators_that_cant_be_expanded_are_named_in_the_error() {
+    let shape = serde_json::json!({
+        "type": "sphere",
+        "transform": { "position": [0, 0, 0], "size": [0.1] }
+    });
+
+    let mut short_spacing = with_generator(serde_json::json!({
+        "type": "grid", "count": [2, 2, 2], "spacing": [1, 1], "shape": shape
+    }));
+    let error = scenefile::expand(&mut short_spacing)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("generators[0] (grid)"), "{}", error);
+    assert!(error.contains("spacing needs 3 values"), "{}", error);
+
+    let mut weightless = with_generator(serde_json::json!({
+        "type": "scatter",
+        "count": [4],
+        "shape": shape,
+        "materials": [{ "weight": 0, "material": { "type": "lambertian", "diffuse": [1, 1, 1] } }]
+    }));
+    let error = scenefile::expand(&mut weightless).unwrap_err().to_string();
+    assert!(error.contains("generators[0] (scatter)"), "{}", error);
+    assert!(error.contains("add up to 0"), "{}", error);
+}
+
+#[test]
+fn misspelled_fields_are_rejected_and_custom_ones_go_in_parameters() {
+    let misspelled = UNVERSIONED_SCENE
+        .replacen('{', &format!("{{ \"version\": {},", CURRENT_VERSION), 1)
+        .replace(
+            "\"type\": \"lambertian\"",
+            "\"type\": \"dielectric\", \"refractionIdx\": 1.5",
+        );
+    let error = scenefile::parse(&misspelled).err().unwrap().to_string();
+    assert!(error.contains("unknown field `refractionIdx`"), "{}", error);
+
+    let mut scene: serde_json::Value = serde_json::from_str(UNVERSIONED_SCENE).unwrap();
+    scene["version"] = serde_json::json!(CURRENT_VERSION);
+    scene["shapes"][0]["parameters"] = serde_json::json!({ "sides": 6 });
+    let config = scenefile::parse(&scene.to_string()).unwrap();
+    assert_eq!(config.shapes[0].parameters["sides"], 6);
+}
+
+#[test]
+fn custom_fields_of_version_1_scenes_are_moved_into_parameters() {
+    let mut scene: serde_json::Value = serde_json::from_str(UNVERSIONED_SCENE).unwrap();
+    scene["version"] = serde_json::json!(1);
+    scene["shapes"][0]["sides"] = serde_json::json!(6);
+    scene["shapes"][0]["material"]["roughness"] = serde_json::json!(0.5);
+
+    let config = scenefile::parse(&scene.to_string()).unwrap();
+    assert_eq!(config.shapes[0].parameters["sides"], 6);
+    let material = config.shapes[0].material.resolve(&config.materials);
+    assert_eq!(material.parameters["roughness"], 0.5);
+}
@@ -0,0 +1,313 @@
+use crayfish::service::{JobLimits, RenderService};
+use crayfish::CancellationToken;
+use serde_json::Value;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const SCENE: &str = r#"{
+    "width": 24,
+    "aspectRatio": 1.5,
+    "outputPath": "unused.png",
+    "rayStep": 1,
+    "samplesPerPixel": 4,
+    "rayMaxDepth": 8,
+    "seed": 1234,
+    "camera": {
+        "fovDeg": 40.0,
+        "position": [0.0, 1.0, 4.0],
+        "lookAt": [0.0, 0.0, 0.0],
+        "up": [0.0, 1.0, 0.0]
+    },
+    "shapes": [
+        {
+            "type": "sphere",
+            "material": { "type": "lambertian", "diffuse": [0.8, 0.3, 0.3] },
+            "transform": { "position": [0.0, 0.0, 0.0], "size": [1.0, 1.0, 1.0] }
+        }
+    ]
+}"#;
+
+fn start(service: RenderService) -> (SocketAddr, CancellationToken, JoinHandle<()>) {
+    let shutdown = CancellationToken::new();
+    let service = service.with_shutdown(shutdown.clone());
+    let address = service.local_addr();
+
+    (
+        address,
+        shutdown,
+        thread::spawn(move || service.run().unwrap()),
+    )
+}
+
+// a minimal http/1.0 client, returns the status code and the body
+fn request(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap();
+    let status_line = String::from_utf8_lossy(&response[..header_end]).to_string();
+    let status = status_line.split(' ').nth(1).unwrap().parse().unwrap();
+
+    (status, response[header_end + 4..].to_vec())
+}
+
+fn json(body: &[u8]) -> Value {
+    serde_json::from_slice(body).unwrap()
+}
+
+fn wait_until_finished(address: SocketAddr, id: u64) -> Value {
+    let started = Instant::now();
+    loop {
+        let (status, body) = request(address, "GET", &format!("/jobs/{}", id), "");
+        assert_eq!(status, 200);
+
+        let job = json(&body);
+        if job["state"] != "queued" && job["state"] != "rendering" {
+            return job;
+        }
+
+        assert!(
+            started.elapsed() < Duration::from_secs(60),
+            "The job never finished"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn submitted_scenes_are_rendered_and_downloadable() {
+    let (address, shutdown, service) = start(RenderService::bind("127.0.0.1:0").unwrap());
+
+    let (status, body) = request(address, "POST", "/jobs", SCENE);
+    assert_eq!(status, 202);
+    let id = json(&body)["id"].as_u64().unwrap();
+
+    let job = wait_until_finished(address, id);
+    assert_eq!(job["state"], "done");
+    assert_eq!(job["progress"], 1.0);
+
+    let (status, png) = request(address, "GET", &format!("/jobs/{}/image.png", id), "");
+    assert_eq!(status, 200);
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+    let (status, exr) = request(address, "GET", &format!("/jobs/{}/image.exr", id), "");
+    assert_eq!(status, 200);
+    assert_eq!(&exr[..4], &[0x76, 0x2f, 0x31, 0x01]);
+
+    shutdown.cancel();
+    service.join().unwrap();
+}
+
+#[test]
+fn invalid_scenes_and_unknown_jobs_are_rejected() {
+    let (address, shutdown, service) = start(RenderService::bind("127.0.0.1:0").unwrap());
+
+    let (status, body) = request(address, "POST", "/jobs", "{ \"width\": 24 }");
+    assert_eq!(status, 400);
+    assert!(json(&body)["error"].is_string());
+
+    // included files would be looked for on the server
+    let including = SCENE.replacen('{', "{ \"include\": [\"materials.json\"],", 1);
+    let (status, body) = request(address, "POST", "/jobs", &including);
+    assert_eq!(status, 400);
+    assert!(json(&body)["error"].as_str().unwrap().contains("include"));
+
+    assert_eq!(request(address, "GET", "/jobs/42", "").0, 404);
+    assert_eq!(request(address, "GET", "/nothing", "").0, 404);
+
+    shutdown.cancel();
+    service.join().unwrap();
+}
+
+#[test]
+fn submissions_beyond_the_queue_limit_are_turned_away() {
+    // nothing renders while a slow job holds the only slot, so the queue fills up
+    let slow_scene = SCENE.replacen('{', "{ \"timeBudgetSeconds\": 60,", 1);
+    let (address, shutdown, service) = start(
+        RenderService::bind("127.0.0.1:0")
+            .unwrap()
+            .with_concurrency(1)
+            .with_queue_limit(1),
+    );
+
+    let (_, body) = request(address, "POST", "/jobs", &slow_scene);
+    let slow_id = json(&body)["id"].as_u64().unwrap();
+    let started = Instant::now();
+    while json(&request(address, "GET", &format!("/jobs/{}", slow_id), "").1)["state"]
+        != "rendering"
+    {
+        assert!(started.elapsed() < Duration::from_secs(60));
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(request(address, "POST", "/jobs", SCENE).0, 202);
+    assert_eq!(request(address, "POST", "/jobs", SCENE).0, 503);
+
+    // cancelling the slow job frees the slot for the queued one
+    let (status, _) = request(address, "DELETE", &format!("/jobs/{}", slow_id), "");
+    assert_eq!(status, 202);
+    assert_eq!(wait_until_finished(address, slow_id)["state"], "cancelled");
+    assert_eq!(wait_until_finished(address, slow_id + 1)["state"], "done");
+
+    shutdown.cancel();
+    service.join().unwrap();
+}
+
+fn error(body: &[u8]) -> String {
+    json(body)["error"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn jobs_beyond_the_limits_are_rejected() {
+    let limits = JobLimits {
+        max_size: 32,
+        max_samples_per_pixel: 8,
+        max_time_budget: Duration::from_secs(5),
+        max_shapes: 1000,
+    };
+    let (address, shutdown, service) = start(
+        RenderService::bind("127.0.0.1:0")
+            .unwrap()
+            .with_job_limits(limits),
+    );
+
+    let wide = SCENE.replace("\"width\": 24", "\"width\": 33");
+    let tall = SCENE.replace("\"aspectRatio\": 1.5", "\"aspectRatio\": 0.5");
+    let many_samples = SCENE.replace("\"samplesPerPixel\": 4", "\"samplesPerPixel\": 9");
+    let long = SCENE.replacen('{', "{ \"timeBudgetSeconds\": 6,", 1);
+    let endless = SCENE.replacen('{', "{ \"timeBudgetSeconds\": -1,", 1);
+    let crowded = SCENE.replacen(
+        '{',
+        r#"{ "generators": [{
+            "type": "grid", "count": [100000, 100000, 100000],
+            "shape": { "type": "sphere", "transform": { "position": [0, 0, 0], "size": [0.1] } }
+        }],"#,
+        1,
+    );
+    for (scene, field) in [
+        (wide, "wide"),
+        (tall, "wide"),
+        (many_samples, "samplesPerPixel"),
+        (long, "timeBudgetSeconds"),
+        (endless, "timeBudgetSeconds"),
+        (crowded, "shapes"),
+    ] {
+        let (status, body) = request(address, "POST", "/jobs", &scene);
+        assert_eq!(status, 400);
+        assert!(error(&body).contains(field), "{}", error(&body));
+    }
+
+    let short = SCENE.replacen('{', "{ \"timeBudgetSeconds\": 0.1,", 1);
+    assert_eq!(request(address, "POST", "/jobs", &short).0, 202);
+
+    shutdown.cancel();
+    service.join().unwrap();
+}
+
+#[test]
+fn the_oldest_finished_jobs_are_forgotten() {
+    let (address, shutdown, service) = start(
+        RenderService::bind("127.0.0.1:0")
+            .unwrap()
+            .with_concurrency(1)
+            .with_finished_limit(1),
+    );
+
+    let first = json(&request(address, "POST", "/jobs", SCENE).1)["id"]
+        .as_u64()
+        .unwrap();
+    assert_eq!(wait_until_finished(address, first)["state"], "done");
+    let second = json(&request(address, "POST", "/jobs", SCENE).1)["id"]
+        .as_u64()
+        .unwrap();
+    assert_eq!(wait_until_finished(address, second)["state"], "done");
+
+    assert_eq!(
+        request(address, "GET", &format!("/jobs/{}", first), "").0,
+        404
+    );
+    assert_eq!(
+        request(address, "GET", &format!("/jobs/{}", second), "").0,
+        200
+    );
+
+    shutdown.cancel();
+    service.join().unwrap();
+}
+
+// a scene with a mesh next to the sphere
+fn scene_with_mesh(path: &str) -> String {
+    SCENE.replacen(
+        "\"shapes\": [",
+        &format!(
+            "\"shapes\": [{{ \"type\": \"mesh\", \"path\": {:?}, \"material\": {{ \"type\": \"lambertian\", \"diffuse\": [1, 1, 1] }} }},",
+            path
+        ),
+        1,
+    )
+}
+
+#[test]
+fn scenes_only_load_files_from_the_asset_directory() {
+    let assets = Path::new(env!("CARGO_TARGET_TMPDIR")).join("service_assets");
+    fs::create_dir_all(&assets).unwrap();
+    fs::write(
+        assets.join("triangle.ply"),
+        "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+         property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n\
+         0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n",
+    )
+    .unwrap();
+
+    // without an asset directory no file can be named
+    let (address, shutdown, service) = start(RenderService::bind("127.0.0.1:0").unwrap());
+    let (status, body) = request(address, "POST", "/jobs", &scene_with_mesh("triangle.ply"));
+    assert_eq!(status, 400);
+    assert!(error(&body).contains("asset directory"), "{}", error(&body));
+    shutdown.cancel();
+    service.join().unwrap();
+
+    let (address, shutdown, service) = start(
+        RenderService::bind("127.0.0.1:0")
+            .unwrap()
+            .with_asset_directory(&assets),
+    );
+    let outside = assets.join("triangle.ply");
+    for path in ["../service_assets/triangle.ply", outside.to_str().unwrap()] {
+        let (status, body) = request(address, "POST", "/jobs", &scene_with_mesh(path));
+        assert_eq!(status, 400, "{}", path);
+        assert!(error(&body).contains("relative"), "{}", error(&body));
+    }
+    let with_mask = SCENE.replacen(
+        "\"fovDeg\": 40.0,",
+        "\"fovDeg\": 40.0, \"apertureMask\": \"/etc/passwd\",",
+        1,
+    );
+    assert_eq!(request(address, "POST", "/jobs", &with_mask).0, 400);
+
+    let (status, body) = request(address, "POST", "/jobs", &scene_with_mesh("triangle.ply"));
+    assert_eq!(status, 202);
+    let id = json(&body)["id"].as_u64().unwrap();
+    assert_eq!(wait_until_finished(address, id)["state"], "done");
+
+    shutdown.cancel();
+    service.join().unwrap();
+}